@@ -0,0 +1,24 @@
+#![no_main]
+
+use esp32_aws_core::{
+    authorization::{authorize, NonceLog, Validity},
+    command::Command,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Payloads on the command topic
+    let _ = serde_json::from_slice::<Command>(data);
+
+    // The envelope around them, checked against a clock and a window
+    if let Ok(validity) = serde_json::from_slice::<Validity>(data) {
+        let mut nonces = NonceLog::new();
+        let _ = authorize(&validity, 300, Some(1_735_689_600), &mut nonces);
+        let _ = authorize(&validity, u32::MAX, Some(u64::MAX), &mut nonces);
+    }
+
+    // Lines typed on the serial console
+    if let Ok(line) = std::str::from_utf8(data) {
+        let _ = Command::from_console(line);
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use esp32_aws_core::frame::{self, Sequences};
+use libfuzzer_sys::fuzz_target;
+
+const GATEWAY_KEY: &[u8] = b"fuzz gateway key";
+
+// Frames received over the LoRa link
+fuzz_target!(|data: &[u8]| {
+    let allowed = ["node-1".to_string()];
+    if let Ok(received) = frame::receive(data, GATEWAY_KEY, &allowed, &mut Sequences::new()) {
+        let node_key = frame::node_key(GATEWAY_KEY, &received.node_id);
+        let encoded = frame::encode(
+            &received.node_id,
+            &received.reading,
+            received.sequence,
+            &node_key,
+        )
+        .unwrap();
+        let again = frame::receive(&encoded, GATEWAY_KEY, &allowed, &mut Sequences::new());
+        assert_eq!(again.unwrap(), received);
+    }
+});
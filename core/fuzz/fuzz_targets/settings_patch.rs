@@ -0,0 +1,22 @@
+#![no_main]
+
+use esp32_aws_core::settings::Settings;
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+
+// Desired state documents from the device shadow
+fuzz_target!(|data: &[u8]| {
+    let Ok(desired) = serde_json::from_slice::<Value>(data) else {
+        return;
+    };
+
+    let mut settings = Settings::default();
+    match settings.apply(&desired) {
+        Ok(changed) => {
+            assert_eq!(changed, settings != Settings::default());
+            // Applying the same document again changes nothing
+            assert!(!settings.clone().apply(&desired).unwrap());
+        }
+        Err(_) => assert_eq!(settings, Settings::default()),
+    }
+});
@@ -0,0 +1,96 @@
+/// Outlier-trimmed mean of one metric and the spread of the samples that were kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trimmed {
+    pub mean: f32,
+    pub spread: f32,
+}
+
+/// Drops the lowest and highest quarter of the values (at least one each from
+/// three samples on) and averages the rest.
+pub fn trimmed(values: &mut [f32]) -> Trimmed {
+    if values.is_empty() {
+        return Trimmed {
+            mean: 0.0,
+            spread: 0.0,
+        };
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+
+    let trim = if values.len() >= 3 {
+        (values.len() / 4).max(1)
+    } else {
+        0
+    };
+    let kept = &values[trim..values.len() - trim];
+
+    Trimmed {
+        mean: kept.iter().sum::<f32>() / kept.len() as f32,
+        spread: kept[kept.len() - 1] - kept[0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_is_zero() {
+        assert_eq!(
+            trimmed(&mut []),
+            Trimmed {
+                mean: 0.0,
+                spread: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn single_sample_is_kept() {
+        assert_eq!(
+            trimmed(&mut [21.5]),
+            Trimmed {
+                mean: 21.5,
+                spread: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn two_samples_are_not_trimmed() {
+        assert_eq!(
+            trimmed(&mut [20.0, 22.0]),
+            Trimmed {
+                mean: 21.0,
+                spread: 2.0
+            }
+        );
+    }
+
+    #[test]
+    fn three_samples_keep_the_median() {
+        assert_eq!(
+            trimmed(&mut [30.0, 20.0, 21.0]),
+            Trimmed {
+                mean: 21.0,
+                spread: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn outliers_on_both_ends_are_dropped() {
+        let mut values = [20.0, 100.0, 21.0, 22.0, -50.0, 21.0, 20.0, 22.0];
+        let result = trimmed(&mut values);
+
+        assert_eq!(result.mean, 21.0);
+        assert_eq!(result.spread, 2.0);
+    }
+
+    #[test]
+    fn values_end_up_sorted() {
+        let mut values = [3.0, 1.0, 2.0];
+        trimmed(&mut values);
+        assert_eq!(values, [1.0, 2.0, 3.0]);
+    }
+}
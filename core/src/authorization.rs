@@ -0,0 +1,408 @@
+//! Bounds on when a command received over MQTT may run, so a stale or
+//! retained one does not run again after a reconnect or a reboot.
+
+use alloc::{collections::VecDeque, string::String};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// How far ahead of the device clock a command may be issued.
+pub const MAX_CLOCK_SKEW_S: u64 = 30;
+/// Nonces remembered until their command expires, commands with a nonce are
+/// rejected while this many have not.
+pub const MAX_NONCES: usize = 32;
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// Envelope fields next to `command`, all optional.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Validity {
+    /// Unix time the command was sent at
+    pub issued_at: Option<u64>,
+    /// Unix time after which the command must not run
+    pub expires_at: Option<u64>,
+    /// Unique per command, a command with a nonce runs once. Needs an
+    /// `expires_at` or, with a window configured, an `issued_at`, so that the
+    /// nonce can be forgotten once the command would be rejected anyway.
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejected {
+    /// The command has to be checked against the clock, which is not set
+    ClockNotSynced,
+    /// A window is configured and the command has no `issued_at`
+    MissingTimestamp,
+    Expired {
+        expires_at: u64,
+    },
+    TooOld {
+        age_s: u64,
+    },
+    FromTheFuture {
+        ahead_s: u64,
+    },
+    Replayed,
+    NonceTooLong(usize),
+    /// A nonce without an `expires_at` or a window would have to be kept forever
+    NonceWithoutExpiry,
+    /// The log holds `MAX_NONCES` nonces of commands that have not expired yet
+    NonceLogFull,
+    /// An `issued_at` so far ahead that the end of its window does not fit a `u64`
+    TimestampOutOfRange,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejected::ClockNotSynced => write!(f, "Clock not synced, cannot check the command"),
+            Rejected::MissingTimestamp => write!(f, "Command has no issued_at"),
+            Rejected::Expired { expires_at } => write!(f, "Command expired at {}", expires_at),
+            Rejected::TooOld { age_s } => write!(f, "Command issued {} s ago", age_s),
+            Rejected::FromTheFuture { ahead_s } => {
+                write!(f, "Command issued {} s ahead of the clock", ahead_s)
+            }
+            Rejected::Replayed => write!(f, "Nonce already used"),
+            Rejected::NonceTooLong(len) => {
+                write!(f, "Nonce of {} bytes is longer than {}", len, MAX_NONCE_LEN)
+            }
+            Rejected::NonceWithoutExpiry => {
+                write!(
+                    f,
+                    "Nonce needs an expires_at or an issued_at within a window"
+                )
+            }
+            Rejected::NonceLogFull => write!(
+                f,
+                "{} nonces of unexpired commands stored, retry later",
+                MAX_NONCES
+            ),
+            Rejected::TimestampOutOfRange => write!(f, "Command issued_at out of range"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Rejected {}
+
+/// Nonces of the commands that ran, with the time until which they matter.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct NonceLog {
+    nonces: VecDeque<(String, u64)>,
+}
+
+impl NonceLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nonces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nonces.is_empty()
+    }
+
+    fn contains(&self, nonce: &str) -> bool {
+        self.nonces.iter().any(|(seen, _)| seen == nonce)
+    }
+
+    // Evicting a nonce that still matters would let its command run again
+    fn insert(&mut self, nonce: String, until: u64, now: u64) -> Result<(), Rejected> {
+        self.nonces.retain(|&(_, seen_until)| seen_until >= now);
+        if self.nonces.len() == MAX_NONCES {
+            return Err(Rejected::NonceLogFull);
+        }
+        self.nonces.push_back((nonce, until));
+        Ok(())
+    }
+}
+
+/// Checks a command against the clock and the nonces that ran, and records
+/// its nonce when it may run. With `window_s` 0 commands without fields run as
+/// before, an `expires_at` or a nonce is still honored.
+///
+/// Without a nonce nothing stops a command from running twice within the
+/// window or before it expires.
+pub fn authorize(
+    validity: &Validity,
+    window_s: u32,
+    now: Option<u64>,
+    nonces: &mut NonceLog,
+) -> Result<(), Rejected> {
+    // The sender picks issued_at, the end of its window may not fit
+    let window_end = match validity.issued_at {
+        Some(issued_at) if window_s > 0 => Some(
+            issued_at
+                .checked_add(window_s as u64)
+                .ok_or(Rejected::TimestampOutOfRange)?,
+        ),
+        _ => None,
+    };
+    // Past the window or the expiry the command is rejected anyway
+    let nonce_until = validity.expires_at.or(window_end);
+    if let Some(nonce) = &validity.nonce {
+        if nonce.len() > MAX_NONCE_LEN {
+            return Err(Rejected::NonceTooLong(nonce.len()));
+        }
+        if nonce_until.is_none() {
+            return Err(Rejected::NonceWithoutExpiry);
+        }
+    }
+    if window_s > 0 && validity.issued_at.is_none() {
+        return Err(Rejected::MissingTimestamp);
+    }
+    if validity.issued_at.is_none() && validity.expires_at.is_none() && validity.nonce.is_none() {
+        return Ok(());
+    }
+    let now = now.ok_or(Rejected::ClockNotSynced)?;
+
+    if let Some(expires_at) = validity.expires_at {
+        if now > expires_at {
+            return Err(Rejected::Expired { expires_at });
+        }
+    }
+    if let Some(issued_at) = validity.issued_at {
+        if issued_at > now.saturating_add(MAX_CLOCK_SKEW_S) {
+            return Err(Rejected::FromTheFuture {
+                ahead_s: issued_at - now,
+            });
+        }
+        let age_s = now.saturating_sub(issued_at);
+        if window_s > 0 && age_s > window_s as u64 {
+            return Err(Rejected::TooOld { age_s });
+        }
+    }
+
+    if let (Some(nonce), Some(until)) = (&validity.nonce, nonce_until) {
+        if nonces.contains(nonce) {
+            return Err(Rejected::Replayed);
+        }
+        nonces.insert(nonce.clone(), until, now)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use proptest::prelude::*;
+
+    const NOW: u64 = 1_735_689_600;
+
+    fn issued(issued_at: u64) -> Validity {
+        Validity {
+            issued_at: Some(issued_at),
+            ..Validity::default()
+        }
+    }
+
+    fn with_nonce(nonce: &str) -> Validity {
+        Validity {
+            nonce: Some(nonce.into()),
+            ..issued(NOW)
+        }
+    }
+
+    #[test]
+    fn plain_commands_run_without_a_window() {
+        let mut nonces = NonceLog::new();
+        assert_eq!(
+            authorize(&Validity::default(), 0, None, &mut nonces),
+            Ok(())
+        );
+        assert_eq!(
+            authorize(&Validity::default(), 60, Some(NOW), &mut nonces),
+            Err(Rejected::MissingTimestamp)
+        );
+    }
+
+    #[test]
+    fn fields_parse_from_the_envelope() {
+        let validity: Validity = serde_json::from_str(
+            r#"{"command": "selftest", "issued_at": 1735689600, "nonce": "a1"}"#,
+        )
+        .unwrap();
+        assert_eq!(validity, with_nonce("a1"));
+    }
+
+    #[test]
+    fn window_bounds_the_age() {
+        let mut nonces = NonceLog::new();
+        assert_eq!(
+            authorize(&issued(NOW - 60), 60, Some(NOW), &mut nonces),
+            Ok(())
+        );
+        assert_eq!(
+            authorize(&issued(NOW - 61), 60, Some(NOW), &mut nonces),
+            Err(Rejected::TooOld { age_s: 61 })
+        );
+        assert_eq!(
+            authorize(&issued(NOW + 31), 60, Some(NOW), &mut nonces),
+            Err(Rejected::FromTheFuture { ahead_s: 31 })
+        );
+        // Without a window the age alone is not checked
+        assert_eq!(
+            authorize(&issued(NOW - 3600), 0, Some(NOW), &mut nonces),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn window_past_the_end_of_time_is_rejected() {
+        let mut nonces = NonceLog::new();
+        assert_eq!(
+            authorize(&issued(u64::MAX - 59), 60, Some(NOW), &mut nonces),
+            Err(Rejected::TimestampOutOfRange)
+        );
+        assert_eq!(
+            authorize(&with_nonce("a1"), 60, Some(u64::MAX), &mut nonces),
+            Err(Rejected::TooOld {
+                age_s: u64::MAX - NOW
+            })
+        );
+    }
+
+    #[test]
+    fn expiry_is_honored_without_a_window() {
+        let mut nonces = NonceLog::new();
+        let validity = Validity {
+            expires_at: Some(NOW),
+            ..Validity::default()
+        };
+        assert_eq!(authorize(&validity, 0, Some(NOW), &mut nonces), Ok(()));
+        assert_eq!(
+            authorize(&validity, 0, Some(NOW + 1), &mut nonces),
+            Err(Rejected::Expired { expires_at: NOW })
+        );
+        assert_eq!(
+            authorize(&validity, 0, None, &mut nonces),
+            Err(Rejected::ClockNotSynced)
+        );
+    }
+
+    #[test]
+    fn nonce_runs_once() {
+        let mut nonces = NonceLog::new();
+        assert_eq!(
+            authorize(&with_nonce("a1"), 60, Some(NOW), &mut nonces),
+            Ok(())
+        );
+        assert_eq!(
+            authorize(&with_nonce("a1"), 60, Some(NOW + 1), &mut nonces),
+            Err(Rejected::Replayed)
+        );
+        assert_eq!(
+            authorize(&with_nonce("a2"), 60, Some(NOW + 1), &mut nonces),
+            Ok(())
+        );
+
+        let long = "x".repeat(MAX_NONCE_LEN + 1);
+        assert_eq!(
+            authorize(&with_nonce(&long), 60, Some(NOW), &mut nonces),
+            Err(Rejected::NonceTooLong(MAX_NONCE_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn nonce_needs_an_expiry() {
+        let mut nonces = NonceLog::new();
+        // Without a window the issued_at alone does not bound the command
+        assert_eq!(
+            authorize(&with_nonce("a1"), 0, Some(NOW), &mut nonces),
+            Err(Rejected::NonceWithoutExpiry)
+        );
+        let expiring = Validity {
+            expires_at: Some(NOW + 60),
+            ..with_nonce("a1")
+        };
+        assert_eq!(authorize(&expiring, 0, Some(NOW), &mut nonces), Ok(()));
+        assert_eq!(nonces.len(), 1);
+    }
+
+    #[test]
+    fn full_log_rejects_instead_of_evicting() {
+        let mut nonces = NonceLog::new();
+        for n in 0..MAX_NONCES {
+            authorize(&with_nonce(&format!("n{}", n)), 60, Some(NOW), &mut nonces).unwrap();
+        }
+        assert_eq!(
+            authorize(&with_nonce("late"), 60, Some(NOW + 1), &mut nonces),
+            Err(Rejected::NonceLogFull)
+        );
+        // The oldest is still known
+        assert_eq!(
+            authorize(&with_nonce("n0"), 60, Some(NOW + 1), &mut nonces),
+            Err(Rejected::Replayed)
+        );
+        // Once the window has passed the log has room again
+        let later = Validity {
+            nonce: Some("late".into()),
+            ..issued(NOW + 61)
+        };
+        assert_eq!(authorize(&later, 60, Some(NOW + 61), &mut nonces), Ok(()));
+        assert_eq!(nonces.len(), 1);
+    }
+
+    #[test]
+    fn nonces_are_forgotten_once_the_command_is_too_old() {
+        let mut nonces = NonceLog::new();
+        authorize(&with_nonce("a1"), 60, Some(NOW), &mut nonces).unwrap();
+        let later = Validity {
+            nonce: Some("a2".into()),
+            ..issued(NOW + 120)
+        };
+        authorize(&later, 60, Some(NOW + 120), &mut nonces).unwrap();
+        assert_eq!(nonces.len(), 1);
+    }
+
+    #[test]
+    fn log_survives_a_round_trip() {
+        let mut nonces = NonceLog::new();
+        authorize(&with_nonce("a1"), 60, Some(NOW), &mut nonces).unwrap();
+        let json = serde_json::to_string(&nonces).unwrap();
+        let mut restored: NonceLog = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            authorize(&with_nonce("a1"), 60, Some(NOW), &mut restored),
+            Err(Rejected::Replayed)
+        );
+    }
+
+    fn arbitrary_validity() -> impl Strategy<Value = Validity> {
+        (
+            proptest::option::of(any::<u64>()),
+            proptest::option::of(any::<u64>()),
+            proptest::option::of("[a-z0-9]{1,8}"),
+        )
+            .prop_map(|(issued_at, expires_at, nonce)| Validity {
+                issued_at,
+                expires_at,
+                nonce,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn any_envelope_is_decided(
+            validity in arbitrary_validity(),
+            window_s in any::<u32>(),
+            now in proptest::option::of(any::<u64>()),
+        ) {
+            let mut nonces = NonceLog::new();
+            let _ = authorize(&validity, window_s, now, &mut nonces);
+            prop_assert!(nonces.len() <= 1);
+        }
+
+        #[test]
+        fn log_stays_bounded(count in 0usize..100, window_s in 1u32..600) {
+            let mut nonces = NonceLog::new();
+            for n in 0..count {
+                let validity = Validity { nonce: Some(format!("n{}", n)), ..issued(NOW) };
+                let expected = if n < MAX_NONCES { Ok(()) } else { Err(Rejected::NonceLogFull) };
+                prop_assert_eq!(authorize(&validity, window_s, Some(NOW), &mut nonces), expected);
+                prop_assert!(nonces.len() <= MAX_NONCES);
+            }
+        }
+    }
+}
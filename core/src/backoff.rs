@@ -0,0 +1,87 @@
+use core::time::Duration;
+
+/// Delays to wait after each failed attempt, growing by `factor` up to `max`.
+///
+/// Yields one delay per attempt, so `attempts` bounds the number of tries.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    next: Duration,
+    factor: u32,
+    max: Duration,
+    remaining: u32,
+}
+
+impl Backoff {
+    pub fn exponential(initial: Duration, factor: u32, max: Duration, attempts: u32) -> Self {
+        Backoff {
+            next: initial.min(max),
+            factor,
+            max,
+            remaining: attempts,
+        }
+    }
+
+    pub fn constant(delay: Duration, attempts: u32) -> Self {
+        Self::exponential(delay, 1, delay, attempts)
+    }
+}
+
+impl Iterator for Backoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let delay = self.next;
+        self.next = delay
+            .checked_mul(self.factor)
+            .map_or(self.max, |next| next.min(self.max));
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn secs(delays: impl Iterator<Item = Duration>) -> Vec<u64> {
+        delays.map(|delay| delay.as_secs()).collect()
+    }
+
+    #[test]
+    fn constant_repeats_the_delay() {
+        assert_eq!(
+            secs(Backoff::constant(Duration::from_secs(5), 3)),
+            [5, 5, 5]
+        );
+    }
+
+    #[test]
+    fn exponential_doubles_up_to_the_cap() {
+        let backoff = Backoff::exponential(Duration::from_secs(2), 2, Duration::from_secs(10), 5);
+        assert_eq!(secs(backoff), [2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn initial_delay_is_capped() {
+        let backoff = Backoff::exponential(Duration::from_secs(30), 2, Duration::from_secs(10), 2);
+        assert_eq!(secs(backoff), [10, 10]);
+    }
+
+    #[test]
+    fn zero_attempts_yield_nothing() {
+        assert_eq!(Backoff::constant(Duration::from_secs(1), 0).count(), 0);
+    }
+
+    #[test]
+    fn overflow_saturates_at_the_cap() {
+        let mut backoff = Backoff::exponential(Duration::MAX / 2, 4, Duration::MAX, 3);
+        backoff.next();
+        assert_eq!(backoff.next(), Some(Duration::MAX));
+        assert_eq!(backoff.next(), Some(Duration::MAX));
+    }
+}
@@ -0,0 +1,407 @@
+//! The validity window of an X.509 certificate, read from its PEM encoding.
+//!
+//! Only walks the DER structure as far as the validity field, the certificate
+//! itself is verified by the TLS stack.
+
+use alloc::{string::String, vec::Vec};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use core::fmt;
+
+const SEQUENCE: u8 = 0x30;
+const CONTEXT_0: u8 = 0xa0;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertError {
+    NoPemBlock,
+    InvalidBase64,
+    Truncated,
+    /// A length in more than four bytes, or in none
+    InvalidLength,
+    UnexpectedTag {
+        expected: u8,
+        found: u8,
+    },
+    InvalidTime,
+}
+
+impl fmt::Display for CertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CertError::NoPemBlock => write!(f, "No PEM block found"),
+            CertError::InvalidBase64 => write!(f, "Invalid base64 in PEM block"),
+            CertError::Truncated => write!(f, "Unexpected end of certificate"),
+            CertError::InvalidLength => write!(f, "Invalid DER length"),
+            CertError::UnexpectedTag { expected, found } => write!(
+                f,
+                "Expected DER tag {:#04x}, found {:#04x}",
+                expected, found
+            ),
+            CertError::InvalidTime => write!(f, "Invalid certificate time"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CertError {}
+
+/// Validity window of a certificate, in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validity {
+    pub not_before: u64,
+    pub not_after: u64,
+}
+
+impl Validity {
+    pub fn contains(&self, now: u64) -> bool {
+        self.not_before <= now && now <= self.not_after
+    }
+
+    /// Whole days left until `not_after`, negative once expired.
+    pub fn days_remaining(&self, now: u64) -> i64 {
+        (self.not_after as i64 - now as i64).div_euclid(86_400)
+    }
+}
+
+/// Reads the validity window from a PEM encoded certificate.
+pub fn parse_validity(pem: &[u8]) -> Result<Validity, CertError> {
+    let der = pem_to_der(pem)?;
+
+    let (certificate, _) = read_expected(&der, SEQUENCE)?;
+    let (mut tbs, _) = read_expected(certificate, SEQUENCE)?;
+
+    // The version field is optional and defaults to v1
+    if tbs.first() == Some(&CONTEXT_0) {
+        tbs = read(tbs)?.2;
+    }
+    // Serial number, signature algorithm and issuer
+    for _ in 0..3 {
+        tbs = read(tbs)?.2;
+    }
+
+    let (validity, _) = read_expected(tbs, SEQUENCE)?;
+    let (not_before, rest) = read_time(validity)?;
+    let (not_after, _) = read_time(rest)?;
+
+    Ok(Validity {
+        not_before,
+        not_after,
+    })
+}
+
+/// The DER bytes of the first PEM block.
+fn pem_to_der(pem: &[u8]) -> Result<Vec<u8>, CertError> {
+    let pem = core::str::from_utf8(pem).map_err(|_| CertError::NoPemBlock)?;
+
+    let mut lines = pem
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .peekable();
+    if lines.peek().is_none() {
+        return Err(CertError::NoPemBlock);
+    }
+    let body: String = lines
+        .flat_map(|line| line.chars())
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+
+    STANDARD.decode(body).map_err(|_| CertError::InvalidBase64)
+}
+
+/// Splits off one DER element, returning its tag, contents and the remaining input.
+fn read(data: &[u8]) -> Result<(u8, &[u8], &[u8]), CertError> {
+    let (&tag, rest) = data.split_first().ok_or(CertError::Truncated)?;
+    let (&len, mut rest) = rest.split_first().ok_or(CertError::Truncated)?;
+
+    let len = if len & 0x80 == 0 {
+        len as usize
+    } else {
+        let count = (len & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(CertError::InvalidLength);
+        }
+        if rest.len() < count {
+            return Err(CertError::Truncated);
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        rest = &rest[count..];
+        len
+    };
+
+    if rest.len() < len {
+        return Err(CertError::Truncated);
+    }
+
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+fn read_expected(data: &[u8], expected: u8) -> Result<(&[u8], &[u8]), CertError> {
+    let (tag, contents, rest) = read(data)?;
+    if tag != expected {
+        return Err(CertError::UnexpectedTag {
+            expected,
+            found: tag,
+        });
+    }
+    Ok((contents, rest))
+}
+
+fn read_time(data: &[u8]) -> Result<(u64, &[u8]), CertError> {
+    let (tag, contents, rest) = read(data)?;
+
+    let (year, digits) = match tag {
+        UTC_TIME if contents.len() >= 12 => {
+            // Two digit years are 1950-2049 (RFC 5280)
+            let year = number(&contents[..2])?;
+            let year = if year < 50 { 2000 + year } else { 1900 + year };
+            (year, &contents[2..])
+        }
+        GENERALIZED_TIME if contents.len() >= 14 => (number(&contents[..4])?, &contents[4..]),
+        _ => return Err(CertError::InvalidTime),
+    };
+
+    let [month, day, hour, minute, second] = [0, 2, 4, 6, 8].map(|at| number(&digits[at..at + 2]));
+    let time = unix_time(year, month?, day?, hour?, minute?, second?)?;
+
+    Ok((time, rest))
+}
+
+fn number(digits: &[u8]) -> Result<u32, CertError> {
+    digits.iter().try_fold(0, |acc, &d| match d {
+        b'0'..=b'9' => Ok(acc * 10 + (d - b'0') as u32),
+        _ => Err(CertError::InvalidTime),
+    })
+}
+
+/// Seconds since the Unix epoch of a UTC date, using the days-from-civil
+/// algorithm. Times before the epoch are clamped to it, they only ever start
+/// a validity window.
+pub fn unix_time(
+    year: u32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> Result<u64, CertError> {
+    // A leap second is written as :60
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(CertError::InvalidTime);
+    }
+
+    let (year, month) = if month <= 2 {
+        (year as i64 - 1, month as i64 + 9)
+    } else {
+        (year as i64, month as i64 - 3)
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let time = days * 86_400 + hour as i64 * 3_600 + minute as i64 * 60 + second as i64;
+    Ok(time.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, vec};
+    use proptest::prelude::*;
+
+    fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        match contents.len() {
+            len @ 0..=0x7f => der.push(len as u8),
+            len @ 0x80..=0xff => der.extend_from_slice(&[0x81, len as u8]),
+            len => der.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+        }
+        der.extend_from_slice(contents);
+        der
+    }
+
+    /// A certificate holding the fields the parser walks past and the two times.
+    fn certificate(not_before: &[u8], not_after: &[u8]) -> Vec<u8> {
+        let validity = [not_before, not_after].concat();
+        let tbs = [
+            element(CONTEXT_0, &element(0x02, &[2])),
+            element(0x02, &[0x01, 0x23]),
+            element(SEQUENCE, &element(0x06, &[0x2a, 0x86, 0x48])),
+            element(SEQUENCE, &element(0x31, b"issuer")),
+            element(SEQUENCE, &validity),
+            element(SEQUENCE, b"subject"),
+        ]
+        .concat();
+        element(SEQUENCE, &element(SEQUENCE, &tbs))
+    }
+
+    fn pem(der: &[u8]) -> String {
+        let body = STANDARD.encode(der);
+        let lines: Vec<&str> = body
+            .as_bytes()
+            .chunks(64)
+            .map(|line| core::str::from_utf8(line).unwrap())
+            .collect();
+        format!(
+            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+            lines.join("\n")
+        )
+    }
+
+    #[test]
+    fn validity_of_a_certificate() {
+        let der = certificate(
+            &element(UTC_TIME, b"250101000000Z"),
+            &element(GENERALIZED_TIME, b"20491231235959Z"),
+        );
+        assert_eq!(
+            parse_validity(pem(&der).as_bytes()),
+            Ok(Validity {
+                not_before: 1_735_689_600,
+                not_after: 2_524_607_999,
+            })
+        );
+    }
+
+    #[test]
+    fn two_digit_years_span_1950_to_2049() {
+        let der = certificate(
+            &element(UTC_TIME, b"691231235959Z"),
+            &element(UTC_TIME, b"491231235959Z"),
+        );
+        let validity = parse_validity(pem(&der).as_bytes()).unwrap();
+        // 1969 is before the epoch
+        assert_eq!(validity.not_before, 0);
+        assert_eq!(validity.not_after, 2_524_607_999);
+    }
+
+    #[test]
+    fn days_from_civil() {
+        assert_eq!(unix_time(1970, 1, 1, 0, 0, 0), Ok(0));
+        assert_eq!(unix_time(2000, 2, 29, 12, 0, 0), Ok(951_825_600));
+        assert_eq!(unix_time(2024, 3, 1, 0, 0, 0), Ok(1_709_251_200));
+        assert_eq!(unix_time(2024, 13, 1, 0, 0, 0), Err(CertError::InvalidTime));
+        assert_eq!(unix_time(2024, 1, 1, 24, 0, 0), Err(CertError::InvalidTime));
+        assert_eq!(unix_time(0, 1, 1, 0, 0, 0), Ok(0));
+    }
+
+    #[test]
+    fn long_lengths() {
+        let contents = [7u8; 300];
+        let der = element(0x04, &contents);
+        assert_eq!(&der[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(read(&der), Ok((0x04, &contents[..], &[][..])));
+
+        assert_eq!(read(&[0x04, 0x80]), Err(CertError::InvalidLength));
+        assert_eq!(
+            read(&[0x04, 0x85, 0, 0, 0, 0, 1]),
+            Err(CertError::InvalidLength)
+        );
+        assert_eq!(read(&[0x04, 0x82, 0x01]), Err(CertError::Truncated));
+        assert_eq!(read(&[0x04, 0x03, 1, 2]), Err(CertError::Truncated));
+    }
+
+    #[test]
+    fn malformed_pem() {
+        assert_eq!(
+            parse_validity(b"not a certificate"),
+            Err(CertError::NoPemBlock)
+        );
+        assert_eq!(
+            parse_validity(b"-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n"),
+            Err(CertError::NoPemBlock)
+        );
+        assert_eq!(
+            parse_validity(b"-----BEGIN CERTIFICATE-----\nMII*\n-----END CERTIFICATE-----\n"),
+            Err(CertError::InvalidBase64)
+        );
+        assert_eq!(
+            parse_validity(pem(&element(0x02, &[1])).as_bytes()),
+            Err(CertError::UnexpectedTag {
+                expected: SEQUENCE,
+                found: 0x02,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_times() {
+        for time in [
+            element(UTC_TIME, b"2501010000Z"),
+            element(UTC_TIME, b"25010100000aZ"),
+            element(GENERALIZED_TIME, b"250101000000Z"),
+            element(UTC_TIME, b"250132000000Z"),
+            element(0x04, b"250101000000Z"),
+        ] {
+            let der = certificate(&time, &element(UTC_TIME, b"491231235959Z"));
+            assert_eq!(
+                parse_validity(pem(&der).as_bytes()),
+                Err(CertError::InvalidTime)
+            );
+        }
+    }
+
+    #[test]
+    fn days_remaining_round_down() {
+        let validity = Validity {
+            not_before: 0,
+            not_after: 10 * 86_400,
+        };
+        assert!(validity.contains(10 * 86_400));
+        assert!(!validity.contains(10 * 86_400 + 1));
+        assert_eq!(validity.days_remaining(86_400 / 2), 9);
+        assert_eq!(validity.days_remaining(10 * 86_400 + 1), -1);
+    }
+
+    fn is_leap(year: u32) -> bool {
+        year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let _ = parse_validity(pem(&data).as_bytes());
+            let _ = parse_validity(&data);
+        }
+
+        #[test]
+        fn pem_round_trip(der in proptest::collection::vec(any::<u8>(), 1..512)) {
+            prop_assert_eq!(pem_to_der(pem(&der).as_bytes()), Ok(der));
+        }
+
+        #[test]
+        fn generalized_times_are_read_back(
+            year in 1970u32..10_000,
+            month in 1u32..=12,
+            day in 1u32..=28,
+            hour in 0u32..24,
+            minute in 0u32..60,
+            second in 0u32..60,
+        ) {
+            let time = format!("{:04}{:02}{:02}{:02}{:02}{:02}Z", year, month, day, hour, minute, second);
+            let der = certificate(&element(GENERALIZED_TIME, time.as_bytes()), &element(UTC_TIME, b"491231235959Z"));
+            let validity = parse_validity(pem(&der).as_bytes()).unwrap();
+            prop_assert_eq!(Ok(validity.not_before), unix_time(year, month, day, hour, minute, second));
+        }
+
+        #[test]
+        fn days_and_years_are_consecutive(year in 1970u32..10_000, month in 1u32..=12, day in 1u32..28) {
+            let time = |year, month, day| unix_time(year, month, day, 0, 0, 0).unwrap();
+            prop_assert_eq!(time(year, month, day + 1) - time(year, month, day), 86_400);
+
+            let days_in_year = if is_leap(year) { 366 } else { 365 };
+            prop_assert_eq!(time(year + 1, 1, 1) - time(year, 1, 1), days_in_year * 86_400);
+        }
+    }
+}
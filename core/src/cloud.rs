@@ -0,0 +1,144 @@
+//! Cloud specific parts of the MQTT connection: authentication, topics and
+//! the document holding the desired settings (AWS shadow, Azure twin). The
+//! rest of the firmware only talks to a [`Backend`].
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::Settings, twin::DesiredUpdate};
+
+mod aws;
+mod azure;
+mod mqtt;
+
+pub use aws::{Aws, ShadowTopics};
+pub use azure::{sas_token, Azure};
+pub use mqtt::GenericMqtt;
+
+/// The cloud the device connects to, kept in NVS so one firmware serves all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    #[default]
+    Aws = 0,
+    Azure = 1,
+    /// Any other broker, settings are exchanged as plain retained messages
+    Mqtt = 2,
+}
+
+/// How the device is known to the cloud, from the build configuration.
+pub struct Identity<'a> {
+    /// Also the AWS thing name and the Azure device id
+    pub client_id: &'a str,
+    /// Broker host name, the Azure IoT hub
+    pub host: &'a str,
+    pub sub_topic: &'a str,
+    pub pub_topic: &'a str,
+}
+
+/// What a received message means to the device.
+#[derive(Debug, PartialEq)]
+pub enum Inbound {
+    Desired(DesiredUpdate),
+    /// Anything else, handed to the command parser
+    Message,
+    /// Responses the device does not act on
+    Ignored,
+}
+
+/// A message to publish.
+#[derive(Debug, PartialEq)]
+pub struct Publication {
+    pub topic: String,
+    pub payload: String,
+}
+
+pub trait Backend: Send + Sync {
+    fn kind(&self) -> Kind;
+
+    /// MQTT user name, for clouds that want one.
+    fn username(&self) -> Option<String> {
+        None
+    }
+
+    /// MQTT password valid from `now` (Unix seconds), when the device
+    /// authenticates with a token instead of its client certificate.
+    fn password(&self, _now: u64) -> Option<String> {
+        None
+    }
+
+    /// How long a [`password`](Backend::password) stays valid, in seconds.
+    fn token_lifetime(&self) -> Option<u64> {
+        None
+    }
+
+    /// Topic for readings, or for the `suffix` stream (`health`, `alerts`, ...) when given.
+    fn topic(&self, suffix: Option<&str>) -> String;
+
+    /// Topics to subscribe to after every (re)connect.
+    fn subscriptions(&self) -> Vec<String>;
+
+    /// Topic to publish an empty message to, asking for the current desired settings.
+    fn twin_request(&self) -> Option<String>;
+
+    fn parse(&self, topic: &str, data: &[u8]) -> Result<Inbound, serde_json::Error>;
+
+    /// Reports the settings currently in effect.
+    fn report(&self, settings: &Settings) -> Result<Publication, serde_json::Error>;
+}
+
+impl Kind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Kind::Aws),
+            1 => Some(Kind::Azure),
+            2 => Some(Kind::Mqtt),
+            _ => None,
+        }
+    }
+}
+
+/// Creates the backend for `kind`. `sas_key` is the Azure shared access key,
+/// without it Azure authenticates with the client certificate.
+pub fn backend(kind: Kind, identity: &Identity, sas_key: Option<&[u8]>) -> Box<dyn Backend> {
+    match kind {
+        Kind::Aws => Box::new(Aws::new(identity)),
+        Kind::Azure => Box::new(Azure::new(identity, sas_key)),
+        Kind::Mqtt => Box::new(GenericMqtt::new(identity)),
+    }
+}
+
+/// `topic` with `suffix` below it, e.g. `<pub_topic>/health`.
+fn subtopic(topic: &str, suffix: Option<&str>) -> String {
+    match suffix {
+        Some(suffix) => alloc::format!("{}/{}", topic, suffix),
+        None => topic.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: Identity = Identity {
+        client_id: "thing",
+        host: "example.net",
+        sub_topic: "esp32/sub",
+        pub_topic: "esp32/pub",
+    };
+
+    #[test]
+    fn kind_round_trips_through_nvs_value() {
+        for kind in [Kind::Aws, Kind::Azure, Kind::Mqtt] {
+            assert_eq!(Kind::from_u8(kind as u8), Some(kind));
+        }
+        assert_eq!(Kind::from_u8(3), None);
+    }
+
+    #[test]
+    fn backend_matches_kind() {
+        for kind in [Kind::Aws, Kind::Azure, Kind::Mqtt] {
+            assert_eq!(backend(kind, &IDENTITY, None).kind(), kind);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{subtopic, Backend, Identity, Inbound, Kind, Publication};
+use crate::{settings::Settings, twin::DesiredUpdate};
+
+/// Topics of the classic (unnamed) AWS IoT device shadow.
+pub struct ShadowTopics {
+    pub get: String,
+    pub get_accepted: String,
+    pub update: String,
+    pub update_delta: String,
+}
+
+impl ShadowTopics {
+    pub fn new(thing_name: &str) -> Self {
+        let base = format!("$aws/things/{}/shadow", thing_name);
+
+        ShadowTopics {
+            get: format!("{}/get", base),
+            get_accepted: format!("{}/get/accepted", base),
+            update: format!("{}/update", base),
+            update_delta: format!("{}/update/delta", base),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GetAccepted {
+    state: GetAcceptedState,
+    version: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct GetAcceptedState {
+    desired: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    state: Value,
+    version: Option<u64>,
+}
+
+/// AWS IoT Core, authenticated by the client certificate. Settings come from the device shadow.
+pub struct Aws {
+    sub_topic: String,
+    pub_topic: String,
+    shadow: ShadowTopics,
+}
+
+impl Aws {
+    pub fn new(identity: &Identity) -> Self {
+        Aws {
+            sub_topic: identity.sub_topic.into(),
+            pub_topic: identity.pub_topic.into(),
+            // The client id doubles as the thing name, as AWS IoT policies usually require
+            shadow: ShadowTopics::new(identity.client_id),
+        }
+    }
+}
+
+impl Backend for Aws {
+    fn kind(&self) -> Kind {
+        Kind::Aws
+    }
+
+    fn topic(&self, suffix: Option<&str>) -> String {
+        subtopic(&self.pub_topic, suffix)
+    }
+
+    fn subscriptions(&self) -> Vec<String> {
+        vec![
+            self.sub_topic.clone(),
+            self.shadow.get_accepted.clone(),
+            self.shadow.update_delta.clone(),
+        ]
+    }
+
+    fn twin_request(&self) -> Option<String> {
+        Some(self.shadow.get.clone())
+    }
+
+    fn parse(&self, topic: &str, data: &[u8]) -> Result<Inbound, serde_json::Error> {
+        if topic == self.shadow.get_accepted {
+            let document: GetAccepted = serde_json::from_slice(data)?;
+            Ok(Inbound::Desired(DesiredUpdate {
+                state: document.state.desired,
+                version: document.version,
+                complete: true,
+            }))
+        } else if topic == self.shadow.update_delta {
+            // Only the changed part of the desired state
+            let document: Delta = serde_json::from_slice(data)?;
+            Ok(Inbound::Desired(DesiredUpdate {
+                state: Some(document.state),
+                version: document.version,
+                complete: false,
+            }))
+        } else {
+            Ok(Inbound::Message)
+        }
+    }
+
+    fn report(&self, settings: &Settings) -> Result<Publication, serde_json::Error> {
+        Ok(Publication {
+            topic: self.shadow.update.clone(),
+            payload: serde_json::to_string(&json!({ "state": { "reported": settings } }))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aws() -> Aws {
+        Aws::new(&Identity {
+            client_id: "thing",
+            host: "example-ats.iot.eu-west-1.amazonaws.com",
+            sub_topic: "esp32/sub",
+            pub_topic: "esp32/pub",
+        })
+    }
+
+    #[test]
+    fn shadow_topics_use_the_thing_name() {
+        let aws = aws();
+        assert_eq!(
+            aws.subscriptions(),
+            [
+                "esp32/sub",
+                "$aws/things/thing/shadow/get/accepted",
+                "$aws/things/thing/shadow/update/delta"
+            ]
+        );
+        assert_eq!(aws.twin_request().unwrap(), "$aws/things/thing/shadow/get");
+        assert_eq!(aws.topic(Some("health")), "esp32/pub/health");
+    }
+
+    #[test]
+    fn desired_state_from_get_accepted() {
+        let inbound = aws()
+            .parse(
+                "$aws/things/thing/shadow/get/accepted",
+                br#"{"state": {"desired": {"burst_samples": 4}, "reported": {}}, "version": 3}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            inbound,
+            Inbound::Desired(DesiredUpdate {
+                state: Some(json!({"burst_samples": 4})),
+                version: Some(3),
+                complete: true,
+            })
+        );
+    }
+
+    #[test]
+    fn get_accepted_without_desired_state() {
+        let inbound = aws()
+            .parse(
+                "$aws/things/thing/shadow/get/accepted",
+                br#"{"state": {"reported": {}}}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            inbound,
+            Inbound::Desired(DesiredUpdate {
+                state: None,
+                version: None,
+                complete: true,
+            })
+        );
+    }
+
+    #[test]
+    fn delta_carries_the_changed_state() {
+        let inbound = aws()
+            .parse(
+                "$aws/things/thing/shadow/update/delta",
+                br#"{"state": {"publish_latest": true}, "version": 4}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            inbound,
+            Inbound::Desired(DesiredUpdate {
+                state: Some(json!({"publish_latest": true})),
+                version: Some(4),
+                complete: false,
+            })
+        );
+    }
+
+    #[test]
+    fn other_topics_are_messages() {
+        assert_eq!(
+            aws().parse("esp32/sub", b"not json").unwrap(),
+            Inbound::Message
+        );
+    }
+
+    #[test]
+    fn report_wraps_settings_in_reported_state() {
+        let report = aws().report(&Settings::default()).unwrap();
+        let document: Value = serde_json::from_str(&report.payload).unwrap();
+
+        assert_eq!(report.topic, "$aws/things/thing/shadow/update");
+        assert_eq!(
+            document["state"]["reported"],
+            serde_json::to_value(Settings::default()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+
+use super::{Backend, Identity, Inbound, Kind, Publication};
+use crate::{settings::Settings, twin::DesiredUpdate};
+
+const API_VERSION: &str = "2021-04-12";
+// The client keeps reconnecting with the same token, so it is renewed in time
+const SAS_TOKEN_LIFETIME_S: u64 = 24 * 60 * 60;
+
+const TWIN_RESPONSE_PREFIX: &str = "$iothub/twin/res/";
+const TWIN_DESIRED_PREFIX: &str = "$iothub/twin/PATCH/properties/desired/";
+
+#[derive(Deserialize)]
+struct Twin {
+    desired: Option<Value>,
+}
+
+/// The hub puts the version of the desired properties next to them.
+fn version(desired: &Value) -> Option<u64> {
+    desired.get("$version")?.as_u64()
+}
+
+/// Azure IoT Hub, authenticated by the client certificate or a SAS token.
+/// Settings come from the desired properties of the device twin.
+pub struct Azure {
+    device_id: String,
+    hub: String,
+    sas_key: Option<Vec<u8>>,
+    // Request id of twin operations, the hub echoes it in the response topic
+    request_id: AtomicU32,
+}
+
+impl Azure {
+    pub fn new(identity: &Identity, sas_key: Option<&[u8]>) -> Self {
+        Azure {
+            device_id: identity.client_id.into(),
+            hub: identity.host.into(),
+            sas_key: sas_key.map(Into::into),
+            request_id: AtomicU32::new(0),
+        }
+    }
+
+    fn next_request_id(&self) -> u32 {
+        self.request_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Backend for Azure {
+    fn kind(&self) -> Kind {
+        Kind::Azure
+    }
+
+    fn username(&self) -> Option<String> {
+        Some(format!(
+            "{}/{}/?api-version={}",
+            self.hub, self.device_id, API_VERSION
+        ))
+    }
+
+    fn password(&self, now: u64) -> Option<String> {
+        let resource = format!("{}/devices/{}", self.hub, self.device_id);
+        self.sas_key
+            .as_ref()
+            .map(|key| sas_token(&resource, key, now + SAS_TOKEN_LIFETIME_S))
+    }
+
+    fn token_lifetime(&self) -> Option<u64> {
+        self.sas_key.as_ref().map(|_| SAS_TOKEN_LIFETIME_S)
+    }
+
+    /// Device-to-cloud messages all go to one topic, streams are told apart by a
+    /// `stream` message property.
+    fn topic(&self, suffix: Option<&str>) -> String {
+        let topic = format!("devices/{}/messages/events/", self.device_id);
+        match suffix {
+            Some(suffix) => format!("{}stream={}", topic, url_encode(suffix)),
+            None => topic,
+        }
+    }
+
+    fn subscriptions(&self) -> Vec<String> {
+        vec![
+            format!("devices/{}/messages/devicebound/#", self.device_id),
+            format!("{}#", TWIN_RESPONSE_PREFIX),
+            format!("{}#", TWIN_DESIRED_PREFIX),
+        ]
+    }
+
+    fn twin_request(&self) -> Option<String> {
+        Some(format!("$iothub/twin/GET/?$rid={}", self.next_request_id()))
+    }
+
+    fn parse(&self, topic: &str, data: &[u8]) -> Result<Inbound, serde_json::Error> {
+        if let Some(response) = topic.strip_prefix(TWIN_RESPONSE_PREFIX) {
+            // `<status>/?$rid=<id>`, only the full twin of a GET has a body worth reading
+            if response.split('/').next() != Some("200") {
+                return Ok(Inbound::Ignored);
+            }
+            let twin: Twin = serde_json::from_slice(data)?;
+            Ok(Inbound::Desired(DesiredUpdate {
+                version: twin.desired.as_ref().and_then(version),
+                state: twin.desired,
+                complete: true,
+            }))
+        } else if topic.starts_with(TWIN_DESIRED_PREFIX) {
+            let patch: Value = serde_json::from_slice(data)?;
+            Ok(Inbound::Desired(DesiredUpdate {
+                version: version(&patch),
+                state: Some(patch),
+                complete: false,
+            }))
+        } else {
+            Ok(Inbound::Message)
+        }
+    }
+
+    fn report(&self, settings: &Settings) -> Result<Publication, serde_json::Error> {
+        Ok(Publication {
+            topic: format!(
+                "$iothub/twin/PATCH/properties/reported/?$rid={}",
+                self.next_request_id()
+            ),
+            payload: serde_json::to_string(settings)?,
+        })
+    }
+}
+
+/// Shared access signature for `resource`, valid until `expiry` (Unix seconds).
+pub fn sas_token(resource: &str, key: &[u8], expiry: u64) -> String {
+    let resource = url_encode(resource);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}\n{}", resource, expiry).as_bytes());
+    let signature = STANDARD.encode(mac.finalize().into_bytes());
+
+    format!(
+        "SharedAccessSignature sr={}&sig={}&se={}",
+        resource,
+        url_encode(&signature),
+        expiry
+    )
+}
+
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn azure(sas_key: Option<&[u8]>) -> Azure {
+        Azure::new(
+            &Identity {
+                client_id: "thing",
+                host: "myhub.azure-devices.net",
+                sub_topic: "esp32/sub",
+                pub_topic: "esp32/pub",
+            },
+            sas_key,
+        )
+    }
+
+    #[test]
+    fn sas_token_matches_reference() {
+        assert_eq!(
+            sas_token("myhub.azure-devices.net/devices/thing", KEY, 1_700_000_000),
+            "SharedAccessSignature sr=myhub.azure-devices.net%2Fdevices%2Fthing\
+             &sig=sJhmGujN3ckwk670dFcUxV2ov767FpoijnS2O57tRf8%3D&se=1700000000"
+        );
+    }
+
+    #[test]
+    fn password_only_with_sas_key() {
+        assert_eq!(azure(None).password(0), None);
+        assert_eq!(azure(None).token_lifetime(), None);
+
+        let token = azure(Some(KEY)).password(1_700_000_000).unwrap();
+        assert!(token.ends_with(&format!("&se={}", 1_700_000_000 + SAS_TOKEN_LIFETIME_S)));
+    }
+
+    #[test]
+    fn username_names_hub_and_device() {
+        assert_eq!(
+            azure(None).username().unwrap(),
+            "myhub.azure-devices.net/thing/?api-version=2021-04-12"
+        );
+    }
+
+    #[test]
+    fn streams_are_message_properties() {
+        let azure = azure(None);
+        assert_eq!(azure.topic(None), "devices/thing/messages/events/");
+        assert_eq!(
+            azure.topic(Some("nodes/a1")),
+            "devices/thing/messages/events/stream=nodes%2Fa1"
+        );
+    }
+
+    #[test]
+    fn twin_requests_get_fresh_ids() {
+        let azure = azure(None);
+        assert_eq!(azure.twin_request().unwrap(), "$iothub/twin/GET/?$rid=0");
+        assert_eq!(azure.twin_request().unwrap(), "$iothub/twin/GET/?$rid=1");
+        assert!(azure
+            .report(&Settings::default())
+            .unwrap()
+            .topic
+            .ends_with("?$rid=2"));
+    }
+
+    #[test]
+    fn desired_properties_from_twin() {
+        let inbound = azure(None)
+            .parse(
+                "$iothub/twin/res/200/?$rid=0",
+                br#"{"desired": {"burst_samples": 4, "$version": 7}, "reported": {"$version": 2}}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            inbound,
+            Inbound::Desired(DesiredUpdate {
+                state: Some(json!({"burst_samples": 4, "$version": 7})),
+                version: Some(7),
+                complete: true,
+            })
+        );
+    }
+
+    #[test]
+    fn desired_patch() {
+        let inbound = azure(None)
+            .parse(
+                "$iothub/twin/PATCH/properties/desired/?$version=8",
+                br#"{"publish_latest": true, "$version": 8}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            inbound,
+            Inbound::Desired(DesiredUpdate {
+                state: Some(json!({"publish_latest": true, "$version": 8})),
+                version: Some(8),
+                complete: false,
+            })
+        );
+    }
+
+    #[test]
+    fn acknowledgements_are_ignored() {
+        let azure = azure(None);
+        assert_eq!(
+            azure.parse("$iothub/twin/res/204/?$rid=2", b"").unwrap(),
+            Inbound::Ignored
+        );
+        assert_eq!(
+            azure.parse("$iothub/twin/res/429/?$rid=3", b"").unwrap(),
+            Inbound::Ignored
+        );
+    }
+
+    #[test]
+    fn cloud_to_device_messages() {
+        assert_eq!(
+            azure(None)
+                .parse(
+                    "devices/thing/messages/devicebound/%24.to=x",
+                    br#"{"command": "selftest"}"#
+                )
+                .unwrap(),
+            Inbound::Message
+        );
+    }
+
+    #[test]
+    fn url_encoding_keeps_unreserved_characters() {
+        assert_eq!(url_encode("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(url_encode("a+b/c="), "a%2Bb%2Fc%3D");
+    }
+}
@@ -0,0 +1,114 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use serde_json::Value;
+
+use super::{subtopic, Backend, Identity, Inbound, Kind, Publication};
+use crate::{settings::Settings, twin::DesiredUpdate};
+
+/// A plain MQTT broker. Desired settings are a retained message on
+/// `<sub_topic>/settings`, delivered again on every subscribe, and the settings in
+/// effect are reported to `<pub_topic>/settings`.
+pub struct GenericMqtt {
+    sub_topic: String,
+    pub_topic: String,
+    settings_topic: String,
+}
+
+impl GenericMqtt {
+    pub fn new(identity: &Identity) -> Self {
+        GenericMqtt {
+            sub_topic: identity.sub_topic.into(),
+            pub_topic: identity.pub_topic.into(),
+            settings_topic: format!("{}/settings", identity.sub_topic),
+        }
+    }
+}
+
+impl Backend for GenericMqtt {
+    fn kind(&self) -> Kind {
+        Kind::Mqtt
+    }
+
+    fn topic(&self, suffix: Option<&str>) -> String {
+        subtopic(&self.pub_topic, suffix)
+    }
+
+    fn subscriptions(&self) -> Vec<String> {
+        vec![self.sub_topic.clone(), self.settings_topic.clone()]
+    }
+
+    fn twin_request(&self) -> Option<String> {
+        None
+    }
+
+    fn parse(&self, topic: &str, data: &[u8]) -> Result<Inbound, serde_json::Error> {
+        if topic == self.settings_topic {
+            // Retained, so the broker always hands out the whole document
+            Ok(Inbound::Desired(DesiredUpdate {
+                state: Some(serde_json::from_slice::<Value>(data)?),
+                version: None,
+                complete: true,
+            }))
+        } else {
+            Ok(Inbound::Message)
+        }
+    }
+
+    fn report(&self, settings: &Settings) -> Result<Publication, serde_json::Error> {
+        Ok(Publication {
+            topic: self.topic(Some("settings")),
+            payload: serde_json::to_string(settings)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mqtt() -> GenericMqtt {
+        GenericMqtt::new(&Identity {
+            client_id: "thing",
+            host: "broker.example.net",
+            sub_topic: "esp32/sub",
+            pub_topic: "esp32/pub",
+        })
+    }
+
+    #[test]
+    fn settings_are_a_topic_below_the_command_topic() {
+        let mqtt = mqtt();
+        assert_eq!(mqtt.subscriptions(), ["esp32/sub", "esp32/sub/settings"]);
+        assert_eq!(mqtt.twin_request(), None);
+    }
+
+    #[test]
+    fn settings_topic_carries_desired_settings() {
+        let inbound = mqtt()
+            .parse("esp32/sub/settings", br#"{"burst_samples": 2}"#)
+            .unwrap();
+        assert_eq!(
+            inbound,
+            Inbound::Desired(DesiredUpdate {
+                state: Some(json!({"burst_samples": 2})),
+                version: None,
+                complete: true,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_settings_document_is_an_error() {
+        assert!(mqtt().parse("esp32/sub/settings", b"{").is_err());
+    }
+
+    #[test]
+    fn report_goes_below_the_telemetry_topic() {
+        let report = mqtt().report(&Settings::default()).unwrap();
+        assert_eq!(report.topic, "esp32/pub/settings");
+        assert_eq!(
+            serde_json::from_str::<Value>(&report.payload).unwrap(),
+            serde_json::to_value(Settings::default()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! The parts of CoAP (RFC 7252) needed to POST readings as confirmable requests.
+
+use alloc::vec::Vec;
+
+const VERSION: u8 = 1;
+const TYPE_CONFIRMABLE: u8 = 0;
+const TYPE_ACKNOWLEDGEMENT: u8 = 2;
+const TYPE_RESET: u8 = 3;
+const CODE_POST: u8 = 0x02;
+const CODE_EMPTY: u8 = 0x00;
+
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// How the gateway answered a confirmable request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    /// Acknowledged with a 2.xx code, or empty with a separate response to follow
+    Delivered,
+    /// Acknowledged with an error code, e.g. `class` 4 and `detail` 4 for 4.04
+    Rejected { class: u8, detail: u8 },
+    /// The gateway could not process the message at all
+    Reset,
+}
+
+/// Encodes a confirmable POST to `path` without a token, the message ID is
+/// enough to match a piggybacked response.
+pub fn encode_post(message_id: u16, path: &[&str], content_format: u16, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(payload.len() + 32);
+    message.push(VERSION << 6 | TYPE_CONFIRMABLE << 4);
+    message.push(CODE_POST);
+    message.extend_from_slice(&message_id.to_be_bytes());
+
+    // Options are sorted by number and delta-encoded
+    let mut last = 0;
+    for segment in path.iter().filter(|segment| !segment.is_empty()) {
+        encode_option(&mut message, OPTION_URI_PATH - last, segment.as_bytes());
+        last = OPTION_URI_PATH;
+    }
+    // Unsigned option values are sent without leading zero bytes
+    let format = content_format.to_be_bytes();
+    let skip = format.iter().take_while(|byte| **byte == 0).count();
+    encode_option(&mut message, OPTION_CONTENT_FORMAT - last, &format[skip..]);
+
+    if !payload.is_empty() {
+        message.push(PAYLOAD_MARKER);
+        message.extend_from_slice(payload);
+    }
+    message
+}
+
+/// The answer to request `message_id`, `None` for datagrams that are not one,
+/// such as late acknowledgements of an earlier message.
+pub fn parse_response(datagram: &[u8], message_id: u16) -> Option<Response> {
+    if datagram.len() < 4 || datagram[0] >> 6 != VERSION {
+        return None;
+    }
+    if u16::from_be_bytes([datagram[2], datagram[3]]) != message_id {
+        return None;
+    }
+
+    let code = datagram[1];
+    match (datagram[0] >> 4) & 0x03 {
+        TYPE_ACKNOWLEDGEMENT if code == CODE_EMPTY || code >> 5 == 2 => Some(Response::Delivered),
+        TYPE_ACKNOWLEDGEMENT => Some(Response::Rejected {
+            class: code >> 5,
+            detail: code & 0x1f,
+        }),
+        TYPE_RESET => Some(Response::Reset),
+        _ => None,
+    }
+}
+
+fn encode_option(message: &mut Vec<u8>, delta: u16, value: &[u8]) {
+    let (delta_nibble, delta_ext) = option_nibble(delta as usize);
+    let (len_nibble, len_ext) = option_nibble(value.len());
+
+    message.push(delta_nibble << 4 | len_nibble);
+    message.extend_from_slice(&delta_ext);
+    message.extend_from_slice(&len_ext);
+    message.extend_from_slice(value);
+}
+
+/// The 4 bit header value and extended bytes for an option delta or length.
+fn option_nibble(value: usize) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, alloc::vec![(value - 13) as u8]),
+        _ => (14, ((value - 269) as u16).to_be_bytes().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn post_header_and_options() {
+        let message = encode_post(0x1234, &["readings"], 50, b"{}");
+
+        let mut expected = alloc::vec![0x40, 0x02, 0x12, 0x34];
+        // Uri-Path, delta 11 and length 8
+        expected.push(0xb8);
+        expected.extend_from_slice(b"readings");
+        // Content-Format, delta 1 and the one byte value 50
+        expected.extend_from_slice(&[0x11, 50]);
+        expected.push(0xff);
+        expected.extend_from_slice(b"{}");
+
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn repeated_path_options_have_zero_delta() {
+        let message = encode_post(1, &["a", "bc"], 50, &[]);
+        assert_eq!(&message[4..], &[0xb1, b'a', 0x02, b'b', b'c', 0x11, 50]);
+    }
+
+    #[test]
+    fn empty_path_segments_are_skipped() {
+        assert_eq!(
+            encode_post(1, &["", "a", ""], 50, &[]),
+            encode_post(1, &["a"], 50, &[])
+        );
+    }
+
+    #[test]
+    fn content_format_zero_has_an_empty_value() {
+        let message = encode_post(1, &[], 0, &[]);
+        assert_eq!(&message[4..], &[0xc0]);
+    }
+
+    #[test]
+    fn empty_payload_has_no_marker() {
+        assert!(!encode_post(1, &["a"], 50, &[]).contains(&PAYLOAD_MARKER));
+    }
+
+    #[test]
+    fn long_option_values_use_extended_length() {
+        let segment = "x".repeat(20);
+        let message = encode_post(1, &[&segment], 50, &[]);
+        assert_eq!(&message[4..6], &[0xbd, 20 - 13]);
+
+        let segment = "x".repeat(300);
+        let message = encode_post(1, &[&segment], 50, &[]);
+        assert_eq!(&message[4..7], &[0xbe, 0, (300 - 269) as u8]);
+    }
+
+    #[test]
+    fn piggybacked_success_is_delivered() {
+        // ACK 2.04 Changed
+        assert_eq!(
+            parse_response(&[0x60, 0x44, 0x00, 0x07], 7),
+            Some(Response::Delivered)
+        );
+    }
+
+    #[test]
+    fn empty_ack_is_delivered() {
+        assert_eq!(
+            parse_response(&[0x60, 0x00, 0x00, 0x07], 7),
+            Some(Response::Delivered)
+        );
+    }
+
+    #[test]
+    fn error_code_is_rejected() {
+        // ACK 4.04 Not Found
+        assert_eq!(
+            parse_response(&[0x60, 0x84, 0x00, 0x07], 7),
+            Some(Response::Rejected {
+                class: 4,
+                detail: 4
+            })
+        );
+    }
+
+    #[test]
+    fn reset_is_reported() {
+        assert_eq!(
+            parse_response(&[0x70, 0x00, 0x00, 0x07], 7),
+            Some(Response::Reset)
+        );
+    }
+
+    #[test]
+    fn unrelated_datagrams_are_ignored() {
+        // Other message ID
+        assert_eq!(parse_response(&[0x60, 0x44, 0x00, 0x06], 7), None);
+        // Too short
+        assert_eq!(parse_response(&[0x60, 0x44, 0x00], 7), None);
+        // Wrong version
+        assert_eq!(parse_response(&[0xa0, 0x44, 0x00, 0x07], 7), None);
+        // Confirmable request from the gateway
+        assert_eq!(parse_response(&[0x40, 0x02, 0x00, 0x07], 7), None);
+    }
+
+    proptest! {
+        #[test]
+        fn parse_response_never_panics(
+            datagram in proptest::collection::vec(any::<u8>(), 0..32),
+            message_id in any::<u16>(),
+        ) {
+            let _ = parse_response(&datagram, message_id);
+        }
+
+        #[test]
+        fn own_requests_are_not_responses(
+            message_id in any::<u16>(),
+            path in proptest::collection::vec("[a-z]{0,300}", 0..4),
+            payload in proptest::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let path: Vec<&str> = path.iter().map(String::as_str).collect();
+            let request = encode_post(message_id, &path, 50, &payload);
+            prop_assert_eq!(parse_response(&request, message_id), None);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use alloc::{collections::VecDeque, format, string::String};
+use core::time::Duration;
+
+/// A session that ends sooner than this after connecting counts as cut short.
+pub const SHORT_SESSION: Duration = Duration::from_secs(15);
+/// Sessions cut short within this long of each other are counted together.
+pub const WINDOW: Duration = Duration::from_secs(300);
+/// Sessions cut short within `WINDOW` that point to a client ID collision.
+pub const SHORT_SESSIONS: usize = 3;
+
+/// Tells when another device connects with the same client ID.
+///
+/// The broker drops the older connection when a client ID connects a second
+/// time, so two devices sharing one keep taking turns: each reconnect ends the
+/// session of the other after a few seconds. A cellular or Wi-Fi dropout ends
+/// a session once, a collision over and over.
+#[derive(Debug, Default)]
+pub struct CollisionDetector {
+    connected_at: Option<Duration>,
+    // When the recent short sessions ended
+    short_sessions: VecDeque<Duration>,
+}
+
+impl CollisionDetector {
+    pub const fn new() -> Self {
+        CollisionDetector {
+            connected_at: None,
+            short_sessions: VecDeque::new(),
+        }
+    }
+
+    pub fn connected(&mut self, now: Duration) {
+        self.connected_at = Some(now);
+    }
+
+    /// Records the end of a session, true when it makes a collision likely.
+    /// Counting starts over after that, so one is reported once per `SHORT_SESSIONS`.
+    pub fn disconnected(&mut self, now: Duration) -> bool {
+        let Some(connected_at) = self.connected_at.take() else {
+            return false;
+        };
+
+        if now.saturating_sub(connected_at) >= SHORT_SESSION {
+            self.short_sessions.clear();
+            return false;
+        }
+
+        while self
+            .short_sessions
+            .front()
+            .is_some_and(|&ended| now.saturating_sub(ended) >= WINDOW)
+        {
+            self.short_sessions.pop_front();
+        }
+        self.short_sessions.push_back(now);
+
+        if self.short_sessions.len() < SHORT_SESSIONS {
+            return false;
+        }
+        self.short_sessions.clear();
+        true
+    }
+}
+
+/// Client ID to fall back to, told apart from the other device by the end of
+/// the MAC address. Applying it again returns the same ID.
+pub fn fallback_client_id(client_id: &str, mac: &[u8; 6]) -> String {
+    let suffix = format!("-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5]);
+    if client_id.ends_with(&suffix) {
+        return client_id.into();
+    }
+    format!("{}{}", client_id, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const MAC: [u8; 6] = [0x24, 0x6f, 0x28, 0xa1, 0xb2, 0x0c];
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn session(detector: &mut CollisionDetector, start: u64, len: u64) -> bool {
+        detector.connected(secs(start));
+        detector.disconnected(secs(start + len))
+    }
+
+    #[test]
+    fn repeated_short_sessions_are_a_collision() {
+        let mut detector = CollisionDetector::new();
+        assert!(!session(&mut detector, 0, 3));
+        assert!(!session(&mut detector, 5, 3));
+        assert!(session(&mut detector, 10, 3));
+        // Counting starts over once reported
+        assert!(!session(&mut detector, 15, 3));
+    }
+
+    #[test]
+    fn long_session_starts_over() {
+        let mut detector = CollisionDetector::new();
+        session(&mut detector, 0, 3);
+        session(&mut detector, 5, 3);
+        assert!(!session(&mut detector, 10, 600));
+        assert!(!session(&mut detector, 615, 3));
+    }
+
+    #[test]
+    fn dropouts_far_apart_are_not_a_collision() {
+        let mut detector = CollisionDetector::new();
+        assert!(!session(&mut detector, 0, 3));
+        assert!(!session(&mut detector, 200, 3));
+        assert!(!session(&mut detector, 400, 3));
+        assert!(session(&mut detector, 420, 3));
+    }
+
+    #[test]
+    fn failed_reconnects_are_not_sessions() {
+        let mut detector = CollisionDetector::new();
+        for now in 0..10 {
+            assert!(!detector.disconnected(secs(now)));
+        }
+    }
+
+    #[test]
+    fn fallback_is_suffixed_once() {
+        let fallback = fallback_client_id("thing-1", &MAC);
+        assert_eq!(fallback, "thing-1-a1b20c");
+        assert_eq!(fallback_client_id(&fallback, &MAC), fallback);
+    }
+
+    proptest! {
+        #[test]
+        fn long_sessions_never_collide(lens in proptest::collection::vec(SHORT_SESSION.as_secs()..10_000, 0..50)) {
+            let mut detector = CollisionDetector::new();
+            let mut now = 0;
+            for len in lens {
+                prop_assert!(!session(&mut detector, now, len));
+                now += len + 1;
+            }
+        }
+    }
+}
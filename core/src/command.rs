@@ -0,0 +1,340 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::cloud;
+
+/// Commands accepted on the command topic and the serial console.
+///
+/// On MQTT a command is a JSON envelope such as `{"command": "selftest"}`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Runs the self-test and publishes the report
+    Selftest,
+    /// Changes the log level of one module (`wifi`, `mqtt`, `sensor`, `ota` or `*`)
+    LogLevel { module: String, level: String },
+    /// Streams log lines to `<pub_topic>/logs` for a bounded time
+    TailLogs {
+        #[serde(default = "default_tail_duration")]
+        duration_s: u64,
+        #[serde(default = "default_tail_rate")]
+        rate: u32,
+    },
+    /// Ends a running log stream
+    StopLogs,
+    /// Resets the drawn charge after a battery swap
+    BatteryReplaced,
+    /// Stores the cloud to connect to, applied on the next boot
+    CloudBackend { backend: cloud::Kind },
+    /// Publishes the newest entries of the event journal to `<pub_topic>/journal`
+    Journal {
+        #[serde(default = "default_journal_count")]
+        count: u16,
+    },
+    /// Reads `len` registers of an I2C device from `register` on, needs
+    /// unlocked diagnostics
+    I2cRead {
+        address: u8,
+        register: u8,
+        #[serde(default = "default_i2c_len")]
+        len: u8,
+    },
+    /// Writes `data` to the registers of an I2C device from `register` on,
+    /// needs diagnostics unlocked with writes allowed
+    I2cWrite {
+        address: u8,
+        register: u8,
+        data: Vec<u8>,
+    },
+    /// Stores the LoRa role, applied on the next boot
+    #[cfg(feature = "lora")]
+    LoraRole { role: LoraRole },
+}
+
+/// What the device does with its LoRa radio, kept in NVS as nodes cannot be
+/// configured through the shadow.
+#[cfg(feature = "lora")]
+#[derive(serde::Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoraRole {
+    /// Radio unused, readings go over Wi-Fi
+    Off = 0,
+    /// Readings are sent to a gateway, Wi-Fi stays off
+    Node = 1,
+    /// Node readings are received and published over MQTT
+    Gateway = 2,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Empty,
+    /// A console argument without `=`
+    InvalidArgument(String),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Empty command"),
+            ParseError::InvalidArgument(arg) => write!(f, "Expected key=value, got {}", arg),
+            ParseError::Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(e: serde_json::Error) -> Self {
+        ParseError::Json(e)
+    }
+}
+
+fn default_tail_duration() -> u64 {
+    60
+}
+
+fn default_tail_rate() -> u32 {
+    5
+}
+
+fn default_journal_count() -> u16 {
+    20
+}
+
+fn default_i2c_len() -> u8 {
+    1
+}
+
+impl Command {
+    /// Parses a console line.
+    ///
+    /// Accepts either a JSON envelope or the command name followed by
+    /// `key=value` arguments, e.g. `log_level module=wifi level=debug`.
+    pub fn from_console(line: &str) -> Result<Self, ParseError> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            return Ok(serde_json::from_str(line)?);
+        }
+
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or(ParseError::Empty)?;
+
+        let mut envelope = Map::new();
+        envelope.insert("command".into(), Value::String(name.into()));
+
+        for arg in words {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| ParseError::InvalidArgument(arg.into()))?;
+            // Numbers and booleans are passed as such, everything else as a string
+            let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+            envelope.insert(key.into(), value);
+        }
+
+        Ok(serde_json::from_value(Value::Object(envelope))?)
+    }
+
+    /// The `command` field of the envelope.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Selftest => "selftest",
+            Command::LogLevel { .. } => "log_level",
+            Command::TailLogs { .. } => "tail_logs",
+            Command::StopLogs => "stop_logs",
+            Command::BatteryReplaced => "battery_replaced",
+            Command::CloudBackend { .. } => "cloud_backend",
+            Command::Journal { .. } => "journal",
+            Command::I2cRead { .. } => "i2c_read",
+            Command::I2cWrite { .. } => "i2c_write",
+            #[cfg(feature = "lora")]
+            Command::LoraRole { .. } => "lora_role",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::json_value;
+    use proptest::prelude::*;
+
+    #[test]
+    fn json_envelope() {
+        let command: Command = serde_json::from_str(r#"{"command": "selftest"}"#).unwrap();
+        assert_eq!(command, Command::Selftest);
+    }
+
+    #[test]
+    fn name_is_the_envelope_tag() {
+        for line in [
+            "selftest",
+            "stop_logs",
+            "journal",
+            "tail_logs",
+            "battery_replaced",
+        ] {
+            assert_eq!(Command::from_console(line).unwrap().name(), line);
+        }
+        let command = Command::from_console("i2c_read address=119 register=208").unwrap();
+        assert_eq!(command.name(), "i2c_read");
+    }
+
+    #[test]
+    fn tail_logs_defaults() {
+        assert_eq!(
+            Command::from_console("tail_logs").unwrap(),
+            Command::TailLogs {
+                duration_s: 60,
+                rate: 5
+            }
+        );
+    }
+
+    #[test]
+    fn console_arguments() {
+        assert_eq!(
+            Command::from_console("  log_level module=wifi level=debug\n").unwrap(),
+            Command::LogLevel {
+                module: "wifi".into(),
+                level: "debug".into()
+            }
+        );
+    }
+
+    #[test]
+    fn cloud_backend_by_name() {
+        assert_eq!(
+            Command::from_console("cloud_backend backend=azure").unwrap(),
+            Command::CloudBackend {
+                backend: cloud::Kind::Azure
+            }
+        );
+        assert!(Command::from_console("cloud_backend backend=gcp").is_err());
+    }
+
+    #[test]
+    fn journal_count() {
+        assert_eq!(
+            Command::from_console("journal").unwrap(),
+            Command::Journal { count: 20 }
+        );
+        assert_eq!(
+            Command::from_console("journal count=5").unwrap(),
+            Command::Journal { count: 5 }
+        );
+    }
+
+    #[test]
+    fn console_accepts_json() {
+        assert_eq!(
+            Command::from_console(r#"{"command": "stop_logs"}"#).unwrap(),
+            Command::StopLogs
+        );
+    }
+
+    #[test]
+    fn empty_line_is_rejected() {
+        assert!(matches!(
+            Command::from_console("   "),
+            Err(ParseError::Empty)
+        ));
+    }
+
+    #[test]
+    fn argument_without_value_is_rejected() {
+        assert!(matches!(
+            Command::from_console("tail_logs rate"),
+            Err(ParseError::InvalidArgument(arg)) if arg == "rate"
+        ));
+    }
+
+    #[test]
+    fn unknown_command_is_rejected() {
+        assert!(matches!(
+            Command::from_console("reboot"),
+            Err(ParseError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn wrong_argument_type_is_rejected() {
+        assert!(Command::from_console("tail_logs rate=-1").is_err());
+        assert!(Command::from_console("log_level module=1 level=debug").is_err());
+    }
+
+    // Words the console would pass as JSON values instead of strings
+    fn is_json_literal(word: &str) -> bool {
+        serde_json::from_str::<Value>(word).is_ok()
+    }
+
+    proptest! {
+        #[test]
+        fn console_never_panics(line in "\\PC*") {
+            let _ = Command::from_console(&line);
+        }
+
+        #[test]
+        fn console_never_panics_on_commands(
+            name in "(selftest|log_level|tail_logs|stop_logs|battery_replaced|cloud_backend)",
+            args in proptest::collection::vec("[a-z_]{1,12}=[^ ]{0,12}", 0..4),
+        ) {
+            let _ = Command::from_console(&format!("{} {}", name, args.join(" ")));
+        }
+
+        #[test]
+        fn json_never_panics(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let _ = serde_json::from_slice::<Command>(&data);
+        }
+
+        #[test]
+        fn json_values_never_panic(
+            command in "(selftest|log_level|tail_logs|stop_logs|battery_replaced|[a-z_]{0,12})",
+            key in "[a-z_]{1,12}",
+            value in json_value(),
+        ) {
+            let mut envelope = Map::new();
+            envelope.insert("command".into(), Value::String(command));
+            envelope.insert(key, value);
+            let _ = serde_json::from_value::<Command>(Value::Object(envelope));
+        }
+
+        #[test]
+        fn tail_logs_arguments_round_trip(duration_s in any::<u64>(), rate in any::<u32>()) {
+            let line = format!("tail_logs duration_s={} rate={}", duration_s, rate);
+            prop_assert_eq!(
+                Command::from_console(&line).unwrap(),
+                Command::TailLogs { duration_s, rate }
+            );
+        }
+
+        #[test]
+        fn log_level_arguments_round_trip(
+            module in "[a-z*]{1,8}".prop_filter("JSON literal", |word| !is_json_literal(word)),
+            level in "[a-z]{1,8}".prop_filter("JSON literal", |word| !is_json_literal(word)),
+        ) {
+            let line = format!("log_level module={} level={}", module, level);
+            prop_assert_eq!(
+                Command::from_console(&line).unwrap(),
+                Command::LogLevel { module, level }
+            );
+        }
+
+        #[test]
+        fn console_and_json_agree(duration_s in any::<u64>(), rate in any::<u32>()) {
+            let line = format!("tail_logs duration_s={} rate={}", duration_s, rate);
+            let json = format!(
+                r#"{{"command":"tail_logs","duration_s":{},"rate":{}}}"#,
+                duration_s, rate
+            );
+            prop_assert_eq!(
+                Command::from_console(&line).unwrap(),
+                serde_json::from_str::<Command>(&json).unwrap()
+            );
+        }
+    }
+}
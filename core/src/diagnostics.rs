@@ -0,0 +1,172 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::command::Command;
+
+/// Longest register transfer of a diagnostic command.
+pub const MAX_TRANSFER: usize = 32;
+
+/// Gate of the commands that touch hardware directly, off unless the shadow
+/// turns it on for a debugging session.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Diagnostics {
+    /// Allows reading I2C registers
+    pub unlocked: bool,
+    /// Also allows writing them, which can put a device into any state
+    pub allow_writes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denied {
+    Locked,
+    WritesNotAllowed,
+    /// Reserved 7 bit addresses, 0x00-0x07 and 0x78-0x7f
+    ReservedAddress(u8),
+    /// Nothing or more than `MAX_TRANSFER` bytes to transfer
+    InvalidLength(usize),
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Denied::Locked => write!(f, "Diagnostics are locked"),
+            Denied::WritesNotAllowed => write!(f, "Register writes are not allowed"),
+            Denied::ReservedAddress(address) => {
+                write!(f, "{:#04x} is not a valid device address", address)
+            }
+            Denied::InvalidLength(len) => write!(
+                f,
+                "Transfer of {} bytes, expected 1 to {}",
+                len, MAX_TRANSFER
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Denied {}
+
+/// Whether `command` may run. Commands other than the diagnostic ones always may.
+pub fn authorize(command: &Command, diagnostics: &Diagnostics) -> Result<(), Denied> {
+    let (address, len, write) = match command {
+        Command::I2cRead { address, len, .. } => (*address, *len as usize, false),
+        Command::I2cWrite { address, data, .. } => (*address, data.len(), true),
+        _ => return Ok(()),
+    };
+
+    if !diagnostics.unlocked {
+        return Err(Denied::Locked);
+    }
+    if write && !diagnostics.allow_writes {
+        return Err(Denied::WritesNotAllowed);
+    }
+    if !(0x08..=0x77).contains(&address) {
+        return Err(Denied::ReservedAddress(address));
+    }
+    if !(1..=MAX_TRANSFER).contains(&len) {
+        return Err(Denied::InvalidLength(len));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    const UNLOCKED: Diagnostics = Diagnostics {
+        unlocked: true,
+        allow_writes: false,
+    };
+
+    fn read(address: u8, len: u8) -> Command {
+        Command::I2cRead {
+            address,
+            register: 0xd0,
+            len,
+        }
+    }
+
+    fn write(data: alloc::vec::Vec<u8>) -> Command {
+        Command::I2cWrite {
+            address: 0x77,
+            register: 0x74,
+            data,
+        }
+    }
+
+    #[test]
+    fn locked_by_default() {
+        assert_eq!(
+            authorize(&read(0x77, 1), &Diagnostics::default()),
+            Err(Denied::Locked)
+        );
+        assert_eq!(
+            authorize(&Command::Selftest, &Diagnostics::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn writes_need_their_own_flag() {
+        assert_eq!(authorize(&read(0x77, 1), &UNLOCKED), Ok(()));
+        assert_eq!(
+            authorize(&write(vec![0x01]), &UNLOCKED),
+            Err(Denied::WritesNotAllowed)
+        );
+
+        let writes = Diagnostics {
+            allow_writes: true,
+            ..UNLOCKED
+        };
+        assert_eq!(authorize(&write(vec![0x01]), &writes), Ok(()));
+        // Allowing writes alone does not unlock anything
+        let locked = Diagnostics {
+            unlocked: false,
+            ..writes
+        };
+        assert_eq!(authorize(&write(vec![0x01]), &locked), Err(Denied::Locked));
+    }
+
+    #[test]
+    fn transfers_are_bounded() {
+        assert_eq!(
+            authorize(&read(0x77, 0), &UNLOCKED),
+            Err(Denied::InvalidLength(0))
+        );
+        assert_eq!(
+            authorize(&read(0x77, MAX_TRANSFER as u8 + 1), &UNLOCKED),
+            Err(Denied::InvalidLength(MAX_TRANSFER + 1))
+        );
+        assert_eq!(
+            authorize(&read(0x00, 1), &UNLOCKED),
+            Err(Denied::ReservedAddress(0x00))
+        );
+        assert_eq!(
+            authorize(&read(0x78, 1), &UNLOCKED),
+            Err(Denied::ReservedAddress(0x78))
+        );
+    }
+
+    #[test]
+    fn console_arguments() {
+        assert_eq!(
+            Command::from_console("i2c_read address=119 register=208").unwrap(),
+            read(0x77, 1)
+        );
+        assert_eq!(
+            Command::from_console("i2c_write address=119 register=116 data=[1,2]").unwrap(),
+            write(vec![1, 2])
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn locked_denies_every_transfer(address in any::<u8>(), len in any::<u8>(), allow_writes in any::<bool>()) {
+            let locked = Diagnostics { unlocked: false, allow_writes };
+            prop_assert_eq!(authorize(&read(address, len), &locked), Err(Denied::Locked));
+        }
+    }
+}
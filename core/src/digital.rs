@@ -0,0 +1,255 @@
+use alloc::string::String;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_INPUTS: usize = 8;
+
+/// A digital input such as a door contact, float switch or tamper switch,
+/// configured through the shadow.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DigitalInput {
+    pub pin: u8,
+    /// Name of the input in telemetry and events
+    pub label: String,
+    /// Report a low level as `true`, for contacts switching to ground
+    #[serde(default)]
+    pub invert: bool,
+    #[serde(default)]
+    pub pull: Pull,
+    /// How long a level has to hold before it counts
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Pull {
+    #[default]
+    None,
+    Up,
+    Down,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    TooMany,
+    /// Not a GPIO of the ESP32, or one wired to the flash
+    InvalidPin(u8),
+    /// GPIO 34 to 39 have no pull resistors
+    NoPull(u8),
+    DuplicatePin(u8),
+    DuplicateLabel(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::TooMany => write!(f, "At most {} digital inputs", MAX_INPUTS),
+            ConfigError::InvalidPin(pin) => write!(f, "GPIO {} cannot be used as input", pin),
+            ConfigError::NoPull(pin) => write!(f, "GPIO {} has no pull resistor", pin),
+            ConfigError::DuplicatePin(pin) => write!(f, "GPIO {} configured twice", pin),
+            ConfigError::DuplicateLabel(label) => write!(f, "Label {} used twice", label),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConfigError {}
+
+fn default_debounce_ms() -> u32 {
+    50
+}
+
+/// Whether `pin` is a GPIO of the ESP32 that is free to be used as an input.
+pub fn is_input_pin(pin: u8) -> bool {
+    // 6 to 11 drive the flash, 20, 24 and 28 to 31 are not bonded out
+    !matches!(pin, 6..=11 | 20 | 24 | 28..=31) && pin <= 39
+}
+
+/// Checks that every input is on a usable pin and can be told apart from the others.
+pub fn validate(inputs: &[DigitalInput]) -> Result<(), ConfigError> {
+    if inputs.len() > MAX_INPUTS {
+        return Err(ConfigError::TooMany);
+    }
+
+    for (i, input) in inputs.iter().enumerate() {
+        let pin = input.pin;
+        if !is_input_pin(pin) {
+            return Err(ConfigError::InvalidPin(pin));
+        }
+        if pin >= 34 && input.pull != Pull::None {
+            return Err(ConfigError::NoPull(pin));
+        }
+
+        let earlier = &inputs[..i];
+        if earlier.iter().any(|other| other.pin == pin) {
+            return Err(ConfigError::DuplicatePin(pin));
+        }
+        if earlier.iter().any(|other| other.label == input.label) {
+            return Err(ConfigError::DuplicateLabel(input.label.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Filters contact bounce: a new level is only taken once it held for the debounce time.
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    debounce_ms: u64,
+    state: bool,
+    // Level differing from `state` and when it was first seen
+    pending: Option<(bool, u64)>,
+}
+
+impl Debouncer {
+    pub fn new(initial: bool, debounce_ms: u32) -> Self {
+        Debouncer {
+            debounce_ms: debounce_ms as u64,
+            state: initial,
+            pending: None,
+        }
+    }
+
+    pub fn state(&self) -> bool {
+        self.state
+    }
+
+    /// Feeds the level sampled at `now_ms`, returns the new state on an edge.
+    pub fn update(&mut self, level: bool, now_ms: u64) -> Option<bool> {
+        if level == self.state {
+            self.pending = None;
+            return None;
+        }
+
+        let since = match self.pending {
+            Some((pending, since)) if pending == level => since,
+            _ => {
+                self.pending = Some((level, now_ms));
+                now_ms
+            }
+        };
+        if now_ms.saturating_sub(since) < self.debounce_ms {
+            return None;
+        }
+
+        self.state = level;
+        self.pending = None;
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn input(pin: u8, label: &str) -> DigitalInput {
+        DigitalInput {
+            pin,
+            label: label.into(),
+            invert: false,
+            pull: Pull::None,
+            debounce_ms: 50,
+        }
+    }
+
+    #[test]
+    fn defaults_from_minimal_document() {
+        let input: DigitalInput = serde_json::from_str(r#"{"pin": 4, "label": "door"}"#).unwrap();
+        assert_eq!(input, self::input(4, "door"));
+    }
+
+    #[test]
+    fn usable_pins_are_accepted() {
+        let inputs = [input(4, "door"), input(25, "float"), input(36, "tamper")];
+        assert_eq!(validate(&inputs), Ok(()));
+    }
+
+    #[test]
+    fn flash_and_missing_pins_are_rejected() {
+        for pin in [6, 11, 20, 24, 28, 40] {
+            assert_eq!(
+                validate(&[input(pin, "x")]),
+                Err(ConfigError::InvalidPin(pin))
+            );
+        }
+    }
+
+    #[test]
+    fn input_only_pins_have_no_pull() {
+        let inputs = [DigitalInput {
+            pull: Pull::Up,
+            ..input(34, "door")
+        }];
+        assert_eq!(validate(&inputs), Err(ConfigError::NoPull(34)));
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        assert_eq!(
+            validate(&[input(4, "a"), input(4, "b")]),
+            Err(ConfigError::DuplicatePin(4))
+        );
+        assert_eq!(
+            validate(&[input(4, "a"), input(5, "a")]),
+            Err(ConfigError::DuplicateLabel("a".into()))
+        );
+    }
+
+    #[test]
+    fn too_many_inputs_are_rejected() {
+        let inputs: alloc::vec::Vec<_> = (0..=MAX_INPUTS as u8)
+            .map(|i| input(32 + i % 2, "x"))
+            .collect();
+        assert_eq!(validate(&inputs), Err(ConfigError::TooMany));
+    }
+
+    #[test]
+    fn level_must_hold_for_debounce_time() {
+        let mut debouncer = Debouncer::new(false, 50);
+
+        assert_eq!(debouncer.update(true, 0), None);
+        assert_eq!(debouncer.update(true, 40), None);
+        assert_eq!(debouncer.update(true, 50), Some(true));
+        assert_eq!(debouncer.update(true, 60), None);
+        assert!(debouncer.state());
+    }
+
+    #[test]
+    fn bounce_restarts_debounce_time() {
+        let mut debouncer = Debouncer::new(false, 50);
+
+        assert_eq!(debouncer.update(true, 0), None);
+        assert_eq!(debouncer.update(false, 30), None);
+        assert_eq!(debouncer.update(true, 40), None);
+        assert_eq!(debouncer.update(true, 80), None);
+        assert_eq!(debouncer.update(true, 90), Some(true));
+    }
+
+    #[test]
+    fn zero_debounce_takes_every_change() {
+        let mut debouncer = Debouncer::new(false, 0);
+
+        assert_eq!(debouncer.update(true, 0), Some(true));
+        assert_eq!(debouncer.update(false, 1), Some(false));
+    }
+
+    proptest! {
+        #[test]
+        fn edges_alternate(samples in proptest::collection::vec((any::<bool>(), 0u64..100), 0..200)) {
+            let mut debouncer = Debouncer::new(false, 20);
+            let mut now = 0;
+            let mut last = false;
+
+            for (level, step) in samples {
+                now += step;
+                if let Some(state) = debouncer.update(level, now) {
+                    prop_assert_ne!(state, last);
+                    prop_assert_eq!(state, level);
+                    last = state;
+                }
+                prop_assert_eq!(debouncer.state(), last);
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Numeric codes of the errors the device reports, so alarms in the cloud can
+//! key on them. A code keeps its meaning once released: add new ones, never
+//! renumber or reuse them.
+
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+use crate::{authorization::Rejected, diagnostics::Denied, frame::FrameError};
+
+/// Grouped by hundreds: 1xx sensor, 2xx decoding, 3xx commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u16)]
+pub enum ErrorCode {
+    SensorRead = 100,
+    SensorRawRead = 101,
+    SensorCalibration = 102,
+
+    /// An inbound message that is neither a command nor a known message
+    MessageDecode = 200,
+    /// Desired settings that could not be parsed or applied
+    SettingsDecode = 201,
+    /// A LoRa frame that could not be decoded
+    FrameDecode = 202,
+    /// A LoRa frame whose tag does not match the node key
+    FrameUnauthenticated = 203,
+    FrameReplayed = 204,
+    /// A LoRa frame of a node not in `lora_nodes`
+    FrameUnknownNode = 205,
+
+    CommandClockNotSynced = 300,
+    CommandMissingTimestamp = 301,
+    CommandExpired = 302,
+    CommandTooOld = 303,
+    CommandFromTheFuture = 304,
+    CommandReplayed = 305,
+    CommandNonceTooLong = 306,
+    CommandNonceWithoutExpiry = 307,
+    CommandNonceLogFull = 308,
+    /// `issued_at`, `expires_at` or `nonce` of the wrong type, or an
+    /// `issued_at` out of range
+    CommandInvalidEnvelope = 309,
+    DiagnosticsLocked = 310,
+    DiagnosticsWritesNotAllowed = 311,
+    DiagnosticsReservedAddress = 312,
+    DiagnosticsInvalidLength = 313,
+    /// A command that was accepted but failed to run
+    CommandFailed = 320,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 24] = [
+        ErrorCode::SensorRead,
+        ErrorCode::SensorRawRead,
+        ErrorCode::SensorCalibration,
+        ErrorCode::MessageDecode,
+        ErrorCode::SettingsDecode,
+        ErrorCode::FrameDecode,
+        ErrorCode::FrameUnauthenticated,
+        ErrorCode::FrameReplayed,
+        ErrorCode::FrameUnknownNode,
+        ErrorCode::CommandClockNotSynced,
+        ErrorCode::CommandMissingTimestamp,
+        ErrorCode::CommandExpired,
+        ErrorCode::CommandTooOld,
+        ErrorCode::CommandFromTheFuture,
+        ErrorCode::CommandReplayed,
+        ErrorCode::CommandNonceTooLong,
+        ErrorCode::CommandNonceWithoutExpiry,
+        ErrorCode::CommandNonceLogFull,
+        ErrorCode::CommandInvalidEnvelope,
+        ErrorCode::DiagnosticsLocked,
+        ErrorCode::DiagnosticsWritesNotAllowed,
+        ErrorCode::DiagnosticsReservedAddress,
+        ErrorCode::DiagnosticsInvalidLength,
+        ErrorCode::CommandFailed,
+    ];
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// Name published next to the code, for humans reading the topic.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::SensorRead => "sensor_read",
+            ErrorCode::SensorRawRead => "sensor_raw_read",
+            ErrorCode::SensorCalibration => "sensor_calibration",
+            ErrorCode::MessageDecode => "message_decode",
+            ErrorCode::SettingsDecode => "settings_decode",
+            ErrorCode::FrameDecode => "frame_decode",
+            ErrorCode::FrameUnauthenticated => "frame_unauthenticated",
+            ErrorCode::FrameReplayed => "frame_replayed",
+            ErrorCode::FrameUnknownNode => "frame_unknown_node",
+            ErrorCode::CommandClockNotSynced => "command_clock_not_synced",
+            ErrorCode::CommandMissingTimestamp => "command_missing_timestamp",
+            ErrorCode::CommandExpired => "command_expired",
+            ErrorCode::CommandTooOld => "command_too_old",
+            ErrorCode::CommandFromTheFuture => "command_from_the_future",
+            ErrorCode::CommandReplayed => "command_replayed",
+            ErrorCode::CommandNonceTooLong => "command_nonce_too_long",
+            ErrorCode::CommandNonceWithoutExpiry => "command_nonce_without_expiry",
+            ErrorCode::CommandNonceLogFull => "command_nonce_log_full",
+            ErrorCode::CommandInvalidEnvelope => "command_invalid_envelope",
+            ErrorCode::DiagnosticsLocked => "diagnostics_locked",
+            ErrorCode::DiagnosticsWritesNotAllowed => "diagnostics_writes_not_allowed",
+            ErrorCode::DiagnosticsReservedAddress => "diagnostics_reserved_address",
+            ErrorCode::DiagnosticsInvalidLength => "diagnostics_invalid_length",
+            ErrorCode::CommandFailed => "command_failed",
+        }
+    }
+}
+
+impl From<&Rejected> for ErrorCode {
+    fn from(rejected: &Rejected) -> Self {
+        match rejected {
+            Rejected::ClockNotSynced => ErrorCode::CommandClockNotSynced,
+            Rejected::MissingTimestamp => ErrorCode::CommandMissingTimestamp,
+            Rejected::Expired { .. } => ErrorCode::CommandExpired,
+            Rejected::TooOld { .. } => ErrorCode::CommandTooOld,
+            Rejected::FromTheFuture { .. } => ErrorCode::CommandFromTheFuture,
+            Rejected::Replayed => ErrorCode::CommandReplayed,
+            Rejected::NonceTooLong(_) => ErrorCode::CommandNonceTooLong,
+            Rejected::NonceWithoutExpiry => ErrorCode::CommandNonceWithoutExpiry,
+            Rejected::NonceLogFull => ErrorCode::CommandNonceLogFull,
+            Rejected::TimestampOutOfRange => ErrorCode::CommandInvalidEnvelope,
+        }
+    }
+}
+
+impl From<&FrameError> for ErrorCode {
+    fn from(error: &FrameError) -> Self {
+        match error {
+            FrameError::BadTag => ErrorCode::FrameUnauthenticated,
+            FrameError::Replayed { .. } => ErrorCode::FrameReplayed,
+            FrameError::UnknownNode => ErrorCode::FrameUnknownNode,
+            _ => ErrorCode::FrameDecode,
+        }
+    }
+}
+
+impl From<Denied> for ErrorCode {
+    fn from(denied: Denied) -> Self {
+        match denied {
+            Denied::Locked => ErrorCode::DiagnosticsLocked,
+            Denied::WritesNotAllowed => ErrorCode::DiagnosticsWritesNotAllowed,
+            Denied::ReservedAddress(_) => ErrorCode::DiagnosticsReservedAddress,
+            Denied::InvalidLength(_) => ErrorCode::DiagnosticsInvalidLength,
+        }
+    }
+}
+
+/// Errors of one code go out at most once per `interval`, so one that recurs
+/// with every reading does not crowd the topic. The ones held back are
+/// counted and reported with the next that goes out.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    /// When the code last went out and how many were held back since
+    codes: BTreeMap<ErrorCode, (Duration, u32)>,
+}
+
+impl Throttle {
+    pub const fn new(interval: Duration) -> Self {
+        Throttle {
+            interval,
+            codes: BTreeMap::new(),
+        }
+    }
+
+    /// Whether an error of `code` at `now` goes out, with the number held
+    /// back since the last one. `None` holds it back.
+    pub fn admit(&mut self, code: ErrorCode, now: Duration) -> Option<u32> {
+        match self.codes.get_mut(&code) {
+            Some((sent_at, held_back)) if now.saturating_sub(*sent_at) < self.interval => {
+                *held_back += 1;
+                None
+            }
+            Some((sent_at, held_back)) => {
+                *sent_at = now;
+                Some(core::mem::take(held_back))
+            }
+            None => {
+                self.codes.insert(code, (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeSet;
+
+    #[test]
+    fn codes_are_stable() {
+        // Alarms in the cloud key on these, a change here breaks them
+        assert_eq!(ErrorCode::SensorRead.code(), 100);
+        assert_eq!(ErrorCode::MessageDecode.code(), 200);
+        assert_eq!(ErrorCode::FrameDecode.code(), 202);
+        assert_eq!(ErrorCode::CommandReplayed.code(), 305);
+        assert_eq!(ErrorCode::DiagnosticsLocked.code(), 310);
+        assert_eq!(ErrorCode::CommandFailed.code(), 320);
+    }
+
+    #[test]
+    fn codes_and_names_are_unique() {
+        let codes: BTreeSet<_> = ErrorCode::ALL.iter().map(|code| code.code()).collect();
+        let names: BTreeSet<_> = ErrorCode::ALL.iter().map(|code| code.name()).collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+        assert_eq!(names.len(), ErrorCode::ALL.len());
+    }
+
+    #[test]
+    fn rejections_map_to_command_codes() {
+        assert_eq!(
+            ErrorCode::from(&Rejected::Expired { expires_at: 0 }),
+            ErrorCode::CommandExpired
+        );
+        assert_eq!(
+            ErrorCode::from(Denied::ReservedAddress(0x78)),
+            ErrorCode::DiagnosticsReservedAddress
+        );
+        assert_eq!(
+            ErrorCode::from(&FrameError::Replayed { sequence: 3 }),
+            ErrorCode::FrameReplayed
+        );
+        assert_eq!(
+            ErrorCode::from(&FrameError::Truncated),
+            ErrorCode::FrameDecode
+        );
+        for code in ErrorCode::ALL {
+            let group = code.code() / 100;
+            let prefix = code.name().split('_').next().unwrap();
+            match group {
+                1 => assert_eq!(prefix, "sensor"),
+                3 => assert!(prefix == "command" || prefix == "diagnostics"),
+                _ => assert_eq!(group, 2),
+            }
+        }
+    }
+
+    #[test]
+    fn throttle_holds_back_repeats_per_code() {
+        let secs = Duration::from_secs;
+        let mut throttle = Throttle::new(secs(60));
+        assert_eq!(throttle.admit(ErrorCode::SensorRead, secs(0)), Some(0));
+        assert_eq!(throttle.admit(ErrorCode::SensorRead, secs(5)), None);
+        assert_eq!(throttle.admit(ErrorCode::SensorRead, secs(10)), None);
+        // Other codes are not held back by it
+        assert_eq!(throttle.admit(ErrorCode::FrameDecode, secs(10)), Some(0));
+
+        assert_eq!(throttle.admit(ErrorCode::SensorRead, secs(60)), Some(2));
+        assert_eq!(throttle.admit(ErrorCode::SensorRead, secs(65)), None);
+    }
+}
@@ -0,0 +1,46 @@
+//! Runtime switches for subsystems, set through the device shadow so one can
+//! be turned off on part of the fleet without a separate build.
+
+use serde::{Deserialize, Serialize};
+
+/// Each flag defaults to on, a device that never receives a shadow document
+/// runs every subsystem.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct FeatureFlags {
+    /// The periodic report on `<pub_topic>/health`
+    pub health_reports: bool,
+    /// An event for every change of a digital input, their states are still
+    /// part of the readings
+    pub input_events: bool,
+    /// Events when the sensor is attached or detached, the journal still
+    /// records them
+    pub sensor_events: bool,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        FeatureFlags {
+            health_reports: true,
+            input_events: true,
+            sensor_events: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_left_out_stay_on() {
+        let flags: FeatureFlags = serde_json::from_str(r#"{"input_events": false}"#).unwrap();
+        assert_eq!(
+            flags,
+            FeatureFlags {
+                input_events: false,
+                ..FeatureFlags::default()
+            }
+        );
+    }
+}
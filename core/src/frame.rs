@@ -0,0 +1,577 @@
+//! Compact binary frame of a reading for radio links where every byte costs airtime.
+//!
+//! Layout: version, node ID length, node ID, the four readings as big endian
+//! `f32`, flags, the timestamp as big endian `u64` when the flag is set, the
+//! sequence number as big endian `u32` and a tag. Without a gas reading its
+//! slot is zero and flagged. Burst statistics, trends, raw values, digital
+//! inputs and pulse counts are not carried.
+//!
+//! The tag is the HMAC-SHA256 of everything before it, truncated to `TAG_LEN`
+//! bytes, under the key of the node. Node keys are derived from the key of
+//! the gateway with `node_key`, so a gateway checks any node without storing
+//! a key per node and a node only ever holds its own one.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::telemetry::SensorData;
+
+const VERSION: u8 = 3;
+pub const MAX_NODE_ID_LEN: usize = 32;
+/// Bytes of the HMAC sent, enough against forgery over a link this slow.
+pub const TAG_LEN: usize = 8;
+const FLAG_CLOCK_SUSPECT: u8 = 0x01;
+const FLAG_TIMESTAMP: u8 = 0x02;
+const FLAG_NO_GAS: u8 = 0x04;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    NodeIdTooLong(usize),
+    /// A reading without converted temperature, humidity or pressure
+    MissingValues,
+    /// Empty, not UTF-8 or containing MQTT topic wildcards or separators
+    InvalidNodeId,
+    Truncated,
+    UnsupportedVersion(u8),
+    /// The tag does not match the key of the node
+    BadTag,
+    /// A node the gateway does not bridge
+    UnknownNode,
+    /// The sequence number is not newer than the last one taken from the node
+    Replayed {
+        sequence: u32,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::NodeIdTooLong(len) => write!(
+                f,
+                "Node ID of {} bytes is longer than {}",
+                len, MAX_NODE_ID_LEN
+            ),
+            FrameError::MissingValues => write!(f, "Reading without converted values"),
+            FrameError::InvalidNodeId => write!(f, "Invalid node ID"),
+            FrameError::Truncated => write!(f, "Truncated frame"),
+            FrameError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported frame version {}", version)
+            }
+            FrameError::BadTag => write!(f, "Frame not signed with the node key"),
+            FrameError::UnknownNode => write!(f, "Frame of a node that is not bridged"),
+            FrameError::Replayed { sequence } => {
+                write!(f, "Frame with sequence number {} replayed", sequence)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+fn mac(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+/// The key of `node_id`, HMAC-SHA256 of the node ID under the gateway key.
+pub fn node_key(gateway_key: &[u8], node_id: &str) -> [u8; 32] {
+    let mut mac = mac(gateway_key);
+    mac.update(node_id.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+/// Encodes and signs a reading, `sequence` has to grow with every frame.
+pub fn encode(
+    node_id: &str,
+    reading: &SensorData,
+    sequence: u32,
+    node_key: &[u8],
+) -> Result<Vec<u8>, FrameError> {
+    if node_id.len() > MAX_NODE_ID_LEN {
+        return Err(FrameError::NodeIdTooLong(node_id.len()));
+    }
+    if !is_valid_node_id(node_id) {
+        return Err(FrameError::InvalidNodeId);
+    }
+    let (Some(temperature), Some(humidity), Some(pressure)) =
+        (reading.temperature, reading.humidity, reading.pressure)
+    else {
+        return Err(FrameError::MissingValues);
+    };
+
+    let mut frame = Vec::with_capacity(2 + node_id.len() + 29 + TAG_LEN);
+    frame.push(VERSION);
+    frame.push(node_id.len() as u8);
+    frame.extend_from_slice(node_id.as_bytes());
+    for value in [
+        temperature,
+        humidity,
+        pressure,
+        reading.gas_resistance.unwrap_or(0.0),
+    ] {
+        frame.extend_from_slice(&value.to_be_bytes());
+    }
+
+    let mut flags = 0;
+    if reading.clock_suspect {
+        flags |= FLAG_CLOCK_SUSPECT;
+    }
+    if reading.timestamp.is_some() {
+        flags |= FLAG_TIMESTAMP;
+    }
+    if reading.gas_resistance.is_none() {
+        flags |= FLAG_NO_GAS;
+    }
+    frame.push(flags);
+    if let Some(timestamp) = reading.timestamp {
+        frame.extend_from_slice(&timestamp.to_be_bytes());
+    }
+    frame.extend_from_slice(&sequence.to_be_bytes());
+
+    let mut mac = mac(node_key);
+    mac.update(&frame);
+    frame.extend_from_slice(&mac.finalize().into_bytes()[..TAG_LEN]);
+
+    Ok(frame)
+}
+
+/// A frame that passed the checks of `receive`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Received {
+    pub node_id: String,
+    pub sequence: u32,
+    pub reading: SensorData,
+}
+
+/// Last sequence number taken from each node.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Sequences {
+    last: BTreeMap<String, u32>,
+}
+
+impl Sequences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets the nodes that are no longer bridged.
+    pub fn retain(&mut self, allowed: &[String]) {
+        self.last.retain(|node_id, _| allowed.contains(node_id));
+    }
+}
+
+/// Decodes a frame built by `encode` and checks it, in this order: the node
+/// is in `allowed`, the tag matches the key `node_key` derives for it from
+/// `gateway_key` and the sequence number is newer than the last one taken
+/// from the node, which it then becomes.
+pub fn receive(
+    frame: &[u8],
+    gateway_key: &[u8],
+    allowed: &[String],
+    sequences: &mut Sequences,
+) -> Result<Received, FrameError> {
+    let signed_len = frame
+        .len()
+        .checked_sub(TAG_LEN)
+        .ok_or(FrameError::Truncated)?;
+    let (signed, tag) = frame.split_at(signed_len);
+    let (node_id, sequence, reading) = decode(signed)?;
+
+    if !allowed.contains(&node_id) {
+        return Err(FrameError::UnknownNode);
+    }
+    let mut mac = mac(&node_key(gateway_key, &node_id));
+    mac.update(signed);
+    mac.verify_truncated_left(tag)
+        .map_err(|_| FrameError::BadTag)?;
+    if sequences
+        .last
+        .get(&node_id)
+        .is_some_and(|&last| sequence <= last)
+    {
+        return Err(FrameError::Replayed { sequence });
+    }
+
+    sequences.last.insert(node_id.clone(), sequence);
+    Ok(Received {
+        node_id,
+        sequence,
+        reading,
+    })
+}
+
+// The frame without its tag
+fn decode(frame: &[u8]) -> Result<(String, u32, SensorData), FrameError> {
+    let mut reader = Reader(frame);
+
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(FrameError::UnsupportedVersion(version));
+    }
+
+    let len = reader.u8()? as usize;
+    if len > MAX_NODE_ID_LEN {
+        return Err(FrameError::NodeIdTooLong(len));
+    }
+    let node_id = core::str::from_utf8(reader.take(len)?).map_err(|_| FrameError::InvalidNodeId)?;
+    if !is_valid_node_id(node_id) {
+        return Err(FrameError::InvalidNodeId);
+    }
+
+    let temperature = reader.f32()?;
+    let humidity = reader.f32()?;
+    let pressure = reader.f32()?;
+    let gas_resistance = reader.f32()?;
+
+    let flags = reader.u8()?;
+    let timestamp = if flags & FLAG_TIMESTAMP != 0 {
+        Some(u64::from_be_bytes(reader.array()?))
+    } else {
+        None
+    };
+    let sequence = reader.u32()?;
+
+    Ok((
+        node_id.into(),
+        sequence,
+        SensorData {
+            temperature: Some(temperature),
+            humidity: Some(humidity),
+            pressure: Some(pressure),
+            gas_resistance: (flags & FLAG_NO_GAS == 0).then_some(gas_resistance),
+            raw: None,
+            timestamp,
+            clock_suspect: flags & FLAG_CLOCK_SUSPECT != 0,
+            burst: None,
+            trend: None,
+            inputs: BTreeMap::new(),
+            pulses: BTreeMap::new(),
+        },
+    ))
+}
+
+// The node ID becomes a topic level on the gateway
+fn is_valid_node_id(node_id: &str) -> bool {
+    !node_id.is_empty() && !node_id.contains(['/', '+', '#'])
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FrameError> {
+        if self.0.len() < len {
+            return Err(FrameError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FrameError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, FrameError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, FrameError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, FrameError> {
+        Ok(f32::from_be_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    const GATEWAY_KEY: &[u8] = b"gateway key";
+
+    fn allowed() -> Vec<String> {
+        vec!["node-1".into(), "n".into()]
+    }
+
+    fn signed(node_id: &str, reading: &SensorData, sequence: u32) -> Vec<u8> {
+        encode(node_id, reading, sequence, &node_key(GATEWAY_KEY, node_id)).unwrap()
+    }
+
+    fn received(frame: &[u8]) -> Result<Received, FrameError> {
+        receive(frame, GATEWAY_KEY, &allowed(), &mut Sequences::new())
+    }
+
+    #[test]
+    fn round_trip_without_timestamp() {
+        let frame = signed("node-1", &SensorData::sample(), 7);
+        assert_eq!(frame.len(), 2 + 6 + 16 + 1 + 4 + TAG_LEN);
+        assert_eq!(
+            received(&frame).unwrap(),
+            Received {
+                node_id: "node-1".into(),
+                sequence: 7,
+                reading: SensorData::sample(),
+            }
+        );
+    }
+
+    #[test]
+    fn round_trip_with_timestamp_and_flags() {
+        let data = SensorData {
+            timestamp: Some(1_735_689_600),
+            clock_suspect: true,
+            ..SensorData::sample()
+        };
+        let frame = signed("node-1", &data, 0);
+        assert_eq!(received(&frame).unwrap().reading, data);
+    }
+
+    #[test]
+    fn round_trip_without_gas() {
+        let data = SensorData {
+            gas_resistance: None,
+            ..SensorData::sample()
+        };
+        let frame = signed("node-1", &data, 0);
+        assert_eq!(frame.len(), 2 + 6 + 16 + 1 + 4 + TAG_LEN);
+        assert_eq!(received(&frame).unwrap().reading, data);
+    }
+
+    #[test]
+    fn burst_statistics_are_dropped() {
+        let data = SensorData {
+            burst: Some(crate::telemetry::BurstStats {
+                samples: 4,
+                temperature_spread: 0.5,
+                humidity_spread: 1.0,
+                pressure_spread: 0.25,
+                gas_resistance_spread: Some(100.0),
+            }),
+            ..SensorData::sample()
+        };
+        let frame = signed("node-1", &data, 0);
+        assert_eq!(received(&frame).unwrap().reading.burst, None);
+    }
+
+    #[test]
+    fn layout_is_stable() {
+        let frame = signed("n", &SensorData::sample(), 0x0102);
+        assert_eq!(
+            frame[..frame.len() - TAG_LEN],
+            [
+                3, 1, b'n', 0x41, 0xac, 0, 0, 0x42, 0x21, 0, 0, 0x44, 0x7d, 0x50, 0, 0x47, 0xea,
+                0x60, 0, 0, 0, 0, 0x01, 0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn node_keys_differ_per_node() {
+        assert_ne!(
+            node_key(GATEWAY_KEY, "node-1"),
+            node_key(GATEWAY_KEY, "node-2")
+        );
+        assert_ne!(
+            node_key(GATEWAY_KEY, "node-1"),
+            node_key(b"other", "node-1")
+        );
+    }
+
+    #[test]
+    fn frames_under_another_key_are_rejected() {
+        // Signed by a node holding the key of a different node
+        let frame = encode(
+            "node-1",
+            &SensorData::sample(),
+            0,
+            &node_key(GATEWAY_KEY, "node-2"),
+        )
+        .unwrap();
+        assert_eq!(received(&frame), Err(FrameError::BadTag));
+
+        let frame = encode("node-1", &SensorData::sample(), 0, GATEWAY_KEY).unwrap();
+        assert_eq!(received(&frame), Err(FrameError::BadTag));
+    }
+
+    #[test]
+    fn unlisted_nodes_are_rejected() {
+        let frame = signed("node-3", &SensorData::sample(), 0);
+        assert_eq!(received(&frame), Err(FrameError::UnknownNode));
+    }
+
+    #[test]
+    fn sequence_numbers_have_to_grow() {
+        let mut sequences = Sequences::new();
+        let mut receive = |frame: &[u8]| receive(frame, GATEWAY_KEY, &allowed(), &mut sequences);
+
+        let first = signed("node-1", &SensorData::sample(), 5);
+        assert!(receive(&first).is_ok());
+        assert_eq!(receive(&first), Err(FrameError::Replayed { sequence: 5 }));
+        assert_eq!(
+            receive(&signed("node-1", &SensorData::sample(), 4)),
+            Err(FrameError::Replayed { sequence: 4 })
+        );
+        assert!(receive(&signed("node-1", &SensorData::sample(), 6)).is_ok());
+        // Each node counts on its own
+        assert!(receive(&signed("n", &SensorData::sample(), 0)).is_ok());
+    }
+
+    #[test]
+    fn rejected_frames_do_not_advance_the_sequence() {
+        let mut sequences = Sequences::new();
+        let mut forged = signed("node-1", &SensorData::sample(), 9);
+        *forged.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            receive(&forged, GATEWAY_KEY, &allowed(), &mut sequences),
+            Err(FrameError::BadTag)
+        );
+        assert!(receive(
+            &signed("node-1", &SensorData::sample(), 1),
+            GATEWAY_KEY,
+            &allowed(),
+            &mut sequences
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn sequences_survive_a_round_trip() {
+        let mut sequences = Sequences::new();
+        let frame = signed("node-1", &SensorData::sample(), 3);
+        receive(&frame, GATEWAY_KEY, &allowed(), &mut sequences).unwrap();
+
+        let json = serde_json::to_string(&sequences).unwrap();
+        let mut restored: Sequences = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            receive(&frame, GATEWAY_KEY, &allowed(), &mut restored),
+            Err(FrameError::Replayed { sequence: 3 })
+        );
+
+        restored.retain(&["n".into()]);
+        assert_eq!(restored, Sequences::new());
+    }
+
+    #[test]
+    fn raw_only_reading_is_rejected() {
+        let data = SensorData {
+            temperature: None,
+            ..SensorData::sample()
+        };
+        assert_eq!(
+            encode("node-1", &data, 0, GATEWAY_KEY),
+            Err(FrameError::MissingValues)
+        );
+    }
+
+    #[test]
+    fn long_node_id_is_rejected() {
+        let node_id = "x".repeat(MAX_NODE_ID_LEN + 1);
+        assert_eq!(
+            encode(&node_id, &SensorData::sample(), 0, GATEWAY_KEY),
+            Err(FrameError::NodeIdTooLong(MAX_NODE_ID_LEN + 1))
+        );
+        assert!(encode(&node_id[1..], &SensorData::sample(), 0, GATEWAY_KEY).is_ok());
+    }
+
+    #[test]
+    fn node_id_with_topic_characters_is_rejected() {
+        for node_id in ["", "a/b", "a+", "#"] {
+            assert_eq!(
+                encode(node_id, &SensorData::sample(), 0, GATEWAY_KEY),
+                Err(FrameError::InvalidNodeId)
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let frame = signed("node-1", &SensorData::sample(), 0);
+        for len in 0..frame.len() {
+            assert_eq!(received(&frame[..len]), Err(FrameError::Truncated));
+        }
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut frame = signed("node-1", &SensorData::sample(), 0);
+        frame[0] = 2;
+        assert_eq!(received(&frame), Err(FrameError::UnsupportedVersion(2)));
+    }
+
+    #[test]
+    fn invalid_node_id_on_air_is_rejected() {
+        let mut frame = signed("ab", &SensorData::sample(), 0);
+        frame[2] = b'/';
+        assert_eq!(received(&frame), Err(FrameError::InvalidNodeId));
+
+        frame[2] = 0xff;
+        assert_eq!(received(&frame), Err(FrameError::InvalidNodeId));
+    }
+
+    // Finite, a NaN would not compare equal after the round trip
+    fn value() -> impl Strategy<Value = f32> {
+        -1.0e9f32..1.0e9
+    }
+
+    fn arbitrary_reading() -> impl Strategy<Value = SensorData> {
+        (
+            [value(), value(), value()],
+            proptest::option::of(value()),
+            any::<Option<u64>>(),
+            any::<bool>(),
+        )
+            .prop_map(
+                |([temperature, humidity, pressure], gas_resistance, timestamp, clock_suspect)| {
+                    SensorData {
+                        temperature: Some(temperature),
+                        humidity: Some(humidity),
+                        pressure: Some(pressure),
+                        gas_resistance,
+                        raw: None,
+                        timestamp,
+                        clock_suspect,
+                        burst: None,
+                        trend: None,
+                        inputs: BTreeMap::new(),
+                        pulses: BTreeMap::new(),
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn receive_never_panics(data in proptest::collection::vec(any::<u8>(), 0..96)) {
+            let _ = received(&data);
+        }
+
+        #[test]
+        fn round_trip(node_id in "[^/+#]{1,8}", reading in arbitrary_reading(), sequence in any::<u32>()) {
+            prop_assume!(node_id.len() <= MAX_NODE_ID_LEN);
+            let frame = signed(&node_id, &reading, sequence);
+            let received = receive(&frame, GATEWAY_KEY, core::slice::from_ref(&node_id), &mut Sequences::new()).unwrap();
+            prop_assert_eq!(received, Received { node_id, sequence, reading });
+        }
+
+        #[test]
+        fn corrupted_frames_are_rejected(
+            reading in arbitrary_reading(),
+            sequence in any::<u32>(),
+            index in any::<prop::sample::Index>(),
+            flip in 1u8..,
+        ) {
+            let mut frame = signed("node-1", &reading, sequence);
+            let index = index.index(frame.len());
+            frame[index] ^= flip;
+            prop_assert!(received(&frame).is_err());
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::string::String;
+use core::ops::Range;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Entries kept on flash, older ones are overwritten.
+pub const CAPACITY: u32 = 64;
+
+/// Something significant that happened on the device, kept across reboots.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Counts up over the life of the device, also across reboots
+    pub seq: u32,
+    /// Unset for entries written before the clock was synced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    pub uptime_s: u64,
+    pub event: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+/// Ring slot an entry is stored in.
+pub fn slot(seq: u32) -> u32 {
+    seq % CAPACITY
+}
+
+/// Sequence numbers of the last `count` entries still on flash, when `next` is
+/// the one to be written next. Iterate with `.rev()` for the newest first.
+pub fn latest(next: u32, count: u16) -> Range<u32> {
+    let count = (count as u32).min(CAPACITY).min(next);
+    next - count..next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    #[test]
+    fn latest_is_bounded_by_what_was_written() {
+        assert_eq!(latest(0, 10), 0..0);
+        assert_eq!(latest(3, 10), 0..3);
+        assert_eq!(latest(100, 10), 90..100);
+        assert_eq!(latest(100, 500), 36..100);
+    }
+
+    #[test]
+    fn slots_wrap_around() {
+        assert_eq!(slot(0), 0);
+        assert_eq!(slot(CAPACITY - 1), CAPACITY - 1);
+        assert_eq!(slot(CAPACITY), 0);
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = Entry {
+            seq: 7,
+            timestamp: Some(1_735_689_600),
+            uptime_s: 42,
+            event: "alert".into(),
+            details: json!({ "kind": "clock_drift" }),
+        };
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<Entry>(&json).unwrap(), entry);
+    }
+
+    #[test]
+    fn unset_fields_are_left_out() {
+        let entry = Entry {
+            seq: 0,
+            timestamp: None,
+            uptime_s: 0,
+            event: "boot".into(),
+            details: Value::Null,
+        };
+        let value = serde_json::to_value(&entry).unwrap();
+        assert!(value.get("timestamp").is_none());
+        assert!(value.get("details").is_none());
+        assert_eq!(serde_json::from_value::<Entry>(value).unwrap(), entry);
+    }
+
+    proptest! {
+        #[test]
+        fn latest_entries_have_distinct_slots(next in 0u32..100_000, count in 0u16..200) {
+            let range = latest(next, count);
+            prop_assert!(range.len() <= count as usize);
+            prop_assert!(range.len() <= CAPACITY as usize);
+            prop_assert_eq!(range.end, next);
+
+            let mut slots: alloc::vec::Vec<u32> = range.map(slot).collect();
+            let len = slots.len();
+            slots.sort_unstable();
+            slots.dedup();
+            prop_assert_eq!(slots.len(), len);
+        }
+    }
+}
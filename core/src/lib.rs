@@ -0,0 +1,44 @@
+//! Hardware-free logic of the firmware: aggregation, thresholds, back-off,
+//! scheduling, the event journal, payload encoding, raw sensor registers,
+//! weather station conversions, error codes, the SiteWise format, the cloud
+//! backends and the parsers for commands, shadow settings, certificates and
+//! URLs. Nothing in here touches ESP-IDF, so it builds and is tested on the
+//! host.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+pub mod aggregate;
+pub mod authorization;
+pub mod backoff;
+pub mod cert;
+pub mod cloud;
+pub mod coap;
+pub mod collision;
+pub mod command;
+pub mod diagnostics;
+pub mod digital;
+pub mod errors;
+pub mod features;
+pub mod frame;
+pub mod journal;
+pub mod outbox;
+pub mod pulse;
+pub mod rapid_change;
+pub mod raw;
+pub mod readiness;
+pub mod schedule;
+pub mod settings;
+pub mod signing;
+pub mod sitewise;
+pub mod startup;
+pub mod summary;
+pub mod telemetry;
+pub mod threshold;
+pub mod trend;
+pub mod twin;
+pub mod url;
+pub mod weather;
+
+#[cfg(test)]
+mod strategies;
@@ -0,0 +1,378 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::time::Duration;
+use serde::Serialize;
+
+/// Class of an outgoing message, the most important first.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Alert = 0,
+    /// Replies to commands and to desired settings, and errors
+    Response = 1,
+    /// Readings and the reports derived from them
+    Telemetry = 2,
+    /// Copies and streams that can be lost: the retained latest reading, log lines
+    Bulk = 3,
+}
+
+const CLASSES: usize = 4;
+const PRIORITIES: [Priority; CLASSES] = [
+    Priority::Alert,
+    Priority::Response,
+    Priority::Telemetry,
+    Priority::Bulk,
+];
+
+/// Messages dropped per class since boot.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Dropped {
+    pub alert: u32,
+    pub response: u32,
+    pub telemetry: u32,
+    pub bulk: u32,
+}
+
+/// Messages waiting for the link to catch up, sent in order of priority.
+///
+/// When it is full, the least important class that is queued, and not more
+/// important than the incoming message, gives way: telemetry is thinned to
+/// every other reading so what is left still spans the backlog, the other
+/// classes drop their oldest message. A message less important than
+/// everything queued is dropped itself.
+#[derive(Debug)]
+pub struct Outbox<T> {
+    queues: [VecDeque<T>; CLASSES],
+    capacity: usize,
+    dropped: [u32; CLASSES],
+}
+
+impl<T> Outbox<T> {
+    pub const fn new(capacity: usize) -> Self {
+        Outbox {
+            queues: [
+                VecDeque::new(),
+                VecDeque::new(),
+                VecDeque::new(),
+                VecDeque::new(),
+            ],
+            capacity,
+            dropped: [0; CLASSES],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queues a message and returns the ones given up to make room for it,
+    /// or the message itself when it is the one dropped.
+    pub fn push(&mut self, priority: Priority, item: T) -> Vec<T> {
+        if self.len() < self.capacity {
+            self.queues[priority as usize].push_back(item);
+            return Vec::new();
+        }
+        match self.make_room(priority) {
+            Some(dropped) => {
+                self.queues[priority as usize].push_back(item);
+                dropped
+            }
+            None => {
+                self.dropped[priority as usize] += 1;
+                alloc::vec![item]
+            }
+        }
+    }
+
+    fn make_room(&mut self, incoming: Priority) -> Option<Vec<T>> {
+        let &victim = PRIORITIES[incoming as usize..]
+            .iter()
+            .rev()
+            .find(|&&priority| !self.queues[priority as usize].is_empty())?;
+
+        let class = victim as usize;
+        let queue = &mut self.queues[class];
+        let mut dropped = Vec::new();
+        if victim == Priority::Telemetry && queue.len() > 1 {
+            let mut kept = VecDeque::with_capacity(queue.len() / 2);
+            for (index, item) in queue.drain(..).enumerate() {
+                if index % 2 == 1 {
+                    kept.push_back(item);
+                } else {
+                    dropped.push(item);
+                }
+            }
+            *queue = kept;
+        } else {
+            dropped.extend(queue.pop_front());
+        }
+        self.dropped[class] += dropped.len() as u32;
+        Some(dropped)
+    }
+
+    /// Takes the oldest of the most important messages.
+    pub fn pop(&mut self) -> Option<(Priority, T)> {
+        PRIORITIES
+            .iter()
+            .find_map(|&priority| Some((priority, self.queues[priority as usize].pop_front()?)))
+    }
+
+    /// Puts a message taken by `pop` back in front, when it could not be sent.
+    pub fn requeue(&mut self, priority: Priority, item: T) {
+        self.queues[priority as usize].push_front(item);
+    }
+
+    pub fn dropped(&self) -> Dropped {
+        let [alert, response, telemetry, bulk] = self.dropped;
+        Dropped {
+            alert,
+            response,
+            telemetry,
+            bulk,
+        }
+    }
+}
+
+// Acknowledgements of ids not sent yet, kept until the send is recorded
+const MAX_EARLY_ACKS: usize = 8;
+
+/// Published messages the broker has not acknowledged yet, by message id.
+///
+/// A message the client gave up on without telling expires after `timeout`,
+/// so a lost acknowledgement does not hold the count up forever.
+#[derive(Debug)]
+pub struct InFlight {
+    timeout: Duration,
+    sent: VecDeque<(u32, Duration)>,
+    // The acknowledgement can arrive before the id of the publish is known
+    early: VecDeque<u32>,
+}
+
+impl InFlight {
+    pub const fn new(timeout: Duration) -> Self {
+        InFlight {
+            timeout,
+            sent: VecDeque::new(),
+            early: VecDeque::new(),
+        }
+    }
+
+    pub fn sent(&mut self, id: u32, now: Duration) {
+        if let Some(index) = self.early.iter().position(|&early| early == id) {
+            self.early.remove(index);
+            return;
+        }
+        self.sent.push_back((id, now));
+    }
+
+    pub fn acked(&mut self, id: u32) {
+        if let Some(index) = self.sent.iter().position(|&(sent, _)| sent == id) {
+            self.sent.remove(index);
+            return;
+        }
+        if self.early.len() == MAX_EARLY_ACKS {
+            self.early.pop_front();
+        }
+        self.early.push_back(id);
+    }
+
+    /// Restarts the timeout of the messages still waiting, after a reconnect
+    /// in which the client sends them again under the same ids. Returns how many.
+    pub fn resume(&mut self, now: Duration) -> usize {
+        let count = self.count(now);
+        for (_, at) in self.sent.iter_mut() {
+            *at = now;
+        }
+        count
+    }
+
+    /// Messages waiting for their acknowledgement at `now`.
+    pub fn count(&mut self, now: Duration) -> usize {
+        while let Some(&(_, at)) = self.sent.front() {
+            if now.saturating_sub(at) < self.timeout {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        self.sent.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use proptest::prelude::*;
+
+    fn drain(outbox: &mut Outbox<u32>) -> Vec<(Priority, u32)> {
+        core::iter::from_fn(|| outbox.pop()).collect()
+    }
+
+    #[test]
+    fn most_important_first_in_order() {
+        let mut outbox = Outbox::new(8);
+        outbox.push(Priority::Telemetry, 1);
+        outbox.push(Priority::Bulk, 2);
+        outbox.push(Priority::Alert, 3);
+        outbox.push(Priority::Telemetry, 4);
+        outbox.push(Priority::Response, 5);
+
+        assert_eq!(
+            drain(&mut outbox),
+            [
+                (Priority::Alert, 3),
+                (Priority::Response, 5),
+                (Priority::Telemetry, 1),
+                (Priority::Telemetry, 4),
+                (Priority::Bulk, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn bulk_gives_way_first() {
+        let mut outbox = Outbox::new(3);
+        outbox.push(Priority::Bulk, 1);
+        outbox.push(Priority::Telemetry, 2);
+        outbox.push(Priority::Bulk, 3);
+        outbox.push(Priority::Alert, 4);
+
+        assert_eq!(outbox.dropped().bulk, 1);
+        assert_eq!(
+            drain(&mut outbox),
+            [
+                (Priority::Alert, 4),
+                (Priority::Telemetry, 2),
+                (Priority::Bulk, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn telemetry_is_thinned() {
+        let mut outbox = Outbox::new(6);
+        for reading in 0..6 {
+            assert!(outbox.push(Priority::Telemetry, reading).is_empty());
+        }
+        assert_eq!(outbox.push(Priority::Telemetry, 6), [0, 2, 4]);
+
+        assert_eq!(outbox.dropped().telemetry, 3);
+        let readings: Vec<u32> = drain(&mut outbox).into_iter().map(|(_, r)| r).collect();
+        assert_eq!(readings, [1, 3, 5, 6]);
+    }
+
+    #[test]
+    fn less_important_than_everything_queued_is_dropped() {
+        let mut outbox = Outbox::new(2);
+        outbox.push(Priority::Alert, 1);
+        outbox.push(Priority::Response, 2);
+        assert_eq!(outbox.push(Priority::Bulk, 3), [3]);
+        assert_eq!(outbox.push(Priority::Telemetry, 4), [4]);
+
+        assert_eq!(
+            outbox.dropped(),
+            Dropped {
+                bulk: 1,
+                telemetry: 1,
+                ..Dropped::default()
+            }
+        );
+        assert_eq!(outbox.len(), 2);
+    }
+
+    #[test]
+    fn alerts_drop_their_oldest() {
+        let mut outbox = Outbox::new(2);
+        outbox.push(Priority::Alert, 1);
+        outbox.push(Priority::Alert, 2);
+        outbox.push(Priority::Alert, 3);
+
+        assert_eq!(outbox.dropped().alert, 1);
+        assert_eq!(
+            drain(&mut outbox),
+            [(Priority::Alert, 2), (Priority::Alert, 3)]
+        );
+    }
+
+    #[test]
+    fn requeued_message_goes_out_first() {
+        let mut outbox = Outbox::new(4);
+        outbox.push(Priority::Telemetry, 1);
+        outbox.push(Priority::Telemetry, 2);
+        let (priority, reading) = outbox.pop().unwrap();
+        outbox.requeue(priority, reading);
+        assert_eq!(outbox.pop(), Some((Priority::Telemetry, 1)));
+    }
+
+    #[test]
+    fn in_flight_until_acked_or_expired() {
+        let secs = Duration::from_secs;
+        let mut in_flight = InFlight::new(secs(30));
+        in_flight.sent(1, secs(0));
+        in_flight.sent(2, secs(10));
+        assert_eq!(in_flight.count(secs(10)), 2);
+
+        in_flight.acked(2);
+        assert_eq!(in_flight.count(secs(10)), 1);
+        assert_eq!(in_flight.count(secs(30)), 0);
+    }
+
+    #[test]
+    fn resumed_messages_get_the_full_timeout() {
+        let secs = Duration::from_secs;
+        let mut in_flight = InFlight::new(secs(30));
+        in_flight.sent(1, secs(0));
+        in_flight.sent(2, secs(20));
+
+        // The first one already expired while the link was down
+        assert_eq!(in_flight.resume(secs(40)), 1);
+        assert_eq!(in_flight.count(secs(69)), 1);
+        in_flight.acked(2);
+        assert_eq!(in_flight.count(secs(69)), 0);
+    }
+
+    #[test]
+    fn ack_before_send_is_matched() {
+        let mut in_flight = InFlight::new(Duration::from_secs(30));
+        in_flight.acked(5);
+        in_flight.sent(5, Duration::ZERO);
+        assert_eq!(in_flight.count(Duration::ZERO), 0);
+    }
+
+    fn priority() -> impl Strategy<Value = Priority> {
+        proptest::sample::select(PRIORITIES.to_vec())
+    }
+
+    proptest! {
+        #[test]
+        fn outbox_stays_bounded(pushes in proptest::collection::vec(priority(), 0..200), capacity in 1usize..16) {
+            let mut outbox = Outbox::new(capacity);
+            let mut given_up = 0;
+            for (n, &priority) in pushes.iter().enumerate() {
+                given_up += outbox.push(priority, n).len();
+                prop_assert!(outbox.len() <= capacity);
+            }
+
+            let dropped = outbox.dropped();
+            let dropped = dropped.alert + dropped.response + dropped.telemetry + dropped.bulk;
+            prop_assert_eq!(dropped as usize, given_up);
+            prop_assert_eq!(outbox.len() + dropped as usize, pushes.len());
+
+            let drained: Vec<Priority> = core::iter::from_fn(|| outbox.pop()).map(|(p, _)| p).collect();
+            prop_assert!(drained.windows(2).all(|w| w[0] <= w[1]));
+        }
+
+        #[test]
+        fn alerts_survive_telemetry(telemetry in 0usize..100, capacity in 1usize..16) {
+            let mut outbox = Outbox::new(capacity);
+            outbox.push(Priority::Alert, 0);
+            for n in 0..telemetry {
+                outbox.push(Priority::Telemetry, n);
+            }
+            prop_assert_eq!(outbox.pop().map(|(p, _)| p), Some(Priority::Alert));
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use alloc::string::String;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::digital::{self, DigitalInput};
+
+/// The ESP32 has eight pulse counter units.
+pub const MAX_COUNTERS: usize = 8;
+/// The hardware counter restarts from zero once it reaches this value.
+pub const COUNTER_LIMIT: u16 = i16::MAX as u16;
+/// The glitch filter counts at most 1023 cycles of the 80 MHz APB clock.
+pub const MAX_FILTER_NS: u32 = 1023 * 1000 / 80;
+
+const MS_PER_HOUR: f64 = 3_600_000.0;
+
+/// A pulse output such as a water flow meter or an S0 energy meter, configured
+/// through the shadow.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PulseCounter {
+    pub pin: u8,
+    /// Name of the counter in telemetry
+    pub label: String,
+    /// Pulses per unit of the meter, e.g. 1000 for an S0 meter with 1000 imp/kWh
+    #[serde(default = "default_pulses_per_unit")]
+    pub pulses_per_unit: f64,
+    /// Unit of `total`, the rate is in this unit per hour
+    #[serde(default)]
+    pub unit: String,
+    /// Pulses shorter than this are ignored as glitches
+    #[serde(default = "default_filter_ns")]
+    pub filter_ns: u32,
+}
+
+fn default_pulses_per_unit() -> f64 {
+    1.0
+}
+
+fn default_filter_ns() -> u32 {
+    MAX_FILTER_NS
+}
+
+/// Published per counter with every reading.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PulseReading {
+    /// Pulses counted since the counter was first configured
+    pub count: u64,
+    /// `count` in the unit of the meter
+    pub total: f64,
+    /// Units per hour since the previous reading
+    pub rate: f32,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub unit: String,
+}
+
+impl PulseCounter {
+    /// The reading for `count` pulses, `new_pulses` of them within the last `elapsed_ms`.
+    pub fn reading(&self, count: u64, new_pulses: u64, elapsed_ms: u64) -> PulseReading {
+        let rate = if elapsed_ms == 0 {
+            0.0
+        } else {
+            (new_pulses as f64 / self.pulses_per_unit * MS_PER_HOUR / elapsed_ms as f64) as f32
+        };
+
+        PulseReading {
+            count,
+            total: count as f64 / self.pulses_per_unit,
+            rate,
+            unit: self.unit.clone(),
+        }
+    }
+
+    /// Glitch filter length in APB clock cycles.
+    pub fn filter_cycles(&self) -> u16 {
+        (self.filter_ns.min(MAX_FILTER_NS) * 80).div_ceil(1000) as u16
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    TooMany,
+    /// Not a GPIO of the ESP32, or one wired to the flash
+    InvalidPin(u8),
+    /// Also configured as a digital input
+    PinInUse(u8),
+    DuplicatePin(u8),
+    DuplicateLabel(String),
+    /// `pulses_per_unit` is not a positive number
+    InvalidScale(String),
+    FilterTooLong(u32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::TooMany => write!(f, "At most {} pulse counters", MAX_COUNTERS),
+            ConfigError::InvalidPin(pin) => write!(f, "GPIO {} cannot count pulses", pin),
+            ConfigError::PinInUse(pin) => write!(f, "GPIO {} is a digital input", pin),
+            ConfigError::DuplicatePin(pin) => write!(f, "GPIO {} configured twice", pin),
+            ConfigError::DuplicateLabel(label) => write!(f, "Label {} used twice", label),
+            ConfigError::InvalidScale(label) => {
+                write!(f, "Pulses per unit of {} must be positive", label)
+            }
+            ConfigError::FilterTooLong(ns) => {
+                write!(f, "Filter of {} ns exceeds {} ns", ns, MAX_FILTER_NS)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConfigError {}
+
+/// Checks that every counter is on a usable pin, not taken by one of `inputs`,
+/// and can be told apart from the others.
+pub fn validate(counters: &[PulseCounter], inputs: &[DigitalInput]) -> Result<(), ConfigError> {
+    if counters.len() > MAX_COUNTERS {
+        return Err(ConfigError::TooMany);
+    }
+
+    for (i, counter) in counters.iter().enumerate() {
+        let pin = counter.pin;
+        if !digital::is_input_pin(pin) {
+            return Err(ConfigError::InvalidPin(pin));
+        }
+        if inputs.iter().any(|input| input.pin == pin) {
+            return Err(ConfigError::PinInUse(pin));
+        }
+        if !(counter.pulses_per_unit.is_finite() && counter.pulses_per_unit > 0.0) {
+            return Err(ConfigError::InvalidScale(counter.label.clone()));
+        }
+        if counter.filter_ns > MAX_FILTER_NS {
+            return Err(ConfigError::FilterTooLong(counter.filter_ns));
+        }
+
+        let earlier = &counters[..i];
+        if earlier.iter().any(|other| other.pin == pin) {
+            return Err(ConfigError::DuplicatePin(pin));
+        }
+        if earlier.iter().any(|other| other.label == counter.label) {
+            return Err(ConfigError::DuplicateLabel(counter.label.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Extends the 16 bit hardware counter to a 64 bit total.
+///
+/// The hardware counter wraps to zero at [`COUNTER_LIMIT`], it has to be read
+/// before it wraps a second time for the total to stay exact.
+#[derive(Debug, Clone)]
+pub struct PulseTotal {
+    total: u64,
+    last: u16,
+}
+
+impl PulseTotal {
+    /// Continues from `total`, with the hardware counter just cleared.
+    pub fn new(total: u64) -> Self {
+        PulseTotal { total, last: 0 }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Feeds the current hardware count, returns the total.
+    pub fn update(&mut self, count: u16) -> u64 {
+        let count = count % COUNTER_LIMIT;
+        let delta = if count >= self.last {
+            count - self.last
+        } else {
+            COUNTER_LIMIT - self.last + count
+        };
+
+        self.last = count;
+        self.total += delta as u64;
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digital::Pull;
+    use proptest::prelude::*;
+
+    fn counter(pin: u8, label: &str) -> PulseCounter {
+        PulseCounter {
+            pin,
+            label: label.into(),
+            pulses_per_unit: 1.0,
+            unit: String::new(),
+            filter_ns: MAX_FILTER_NS,
+        }
+    }
+
+    #[test]
+    fn defaults_from_minimal_document() {
+        let counter: PulseCounter =
+            serde_json::from_str(r#"{"pin": 26, "label": "water"}"#).unwrap();
+        assert_eq!(counter, self::counter(26, "water"));
+    }
+
+    #[test]
+    fn filter_in_apb_cycles() {
+        assert_eq!(counter(26, "water").filter_cycles(), 1023);
+        let counter = PulseCounter {
+            filter_ns: 1000,
+            ..counter(26, "water")
+        };
+        assert_eq!(counter.filter_cycles(), 80);
+    }
+
+    #[test]
+    fn usable_counters_are_accepted() {
+        let counters = [counter(26, "water"), counter(39, "energy")];
+        assert_eq!(validate(&counters, &[]), Ok(()));
+    }
+
+    #[test]
+    fn invalid_counters_are_rejected() {
+        assert_eq!(
+            validate(&[counter(6, "x")], &[]),
+            Err(ConfigError::InvalidPin(6))
+        );
+        assert_eq!(
+            validate(&[counter(4, "a"), counter(4, "b")], &[]),
+            Err(ConfigError::DuplicatePin(4))
+        );
+        assert_eq!(
+            validate(&[counter(4, "a"), counter(5, "a")], &[]),
+            Err(ConfigError::DuplicateLabel("a".into()))
+        );
+
+        for pulses_per_unit in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let counter = PulseCounter {
+                pulses_per_unit,
+                ..counter(4, "a")
+            };
+            assert_eq!(
+                validate(&[counter], &[]),
+                Err(ConfigError::InvalidScale("a".into()))
+            );
+        }
+
+        let counter = PulseCounter {
+            filter_ns: MAX_FILTER_NS + 1,
+            ..counter(4, "a")
+        };
+        assert_eq!(
+            validate(&[counter], &[]),
+            Err(ConfigError::FilterTooLong(MAX_FILTER_NS + 1))
+        );
+    }
+
+    #[test]
+    fn pins_of_digital_inputs_are_rejected() {
+        let input = DigitalInput {
+            pin: 4,
+            label: "door".into(),
+            invert: false,
+            pull: Pull::None,
+            debounce_ms: 50,
+        };
+        assert_eq!(
+            validate(&[counter(4, "water")], &[input]),
+            Err(ConfigError::PinInUse(4))
+        );
+    }
+
+    #[test]
+    fn reading_in_meter_units() {
+        let counter = PulseCounter {
+            pulses_per_unit: 1000.0,
+            unit: "kWh".into(),
+            ..counter(26, "energy")
+        };
+        // 10 pulses in 36 s of a 1000 imp/kWh meter is 1 kW
+        assert_eq!(
+            counter.reading(2500, 10, 36_000),
+            PulseReading {
+                count: 2500,
+                total: 2.5,
+                rate: 1.0,
+                unit: "kWh".into(),
+            }
+        );
+        assert_eq!(counter.reading(2500, 10, 0).rate, 0.0);
+    }
+
+    #[test]
+    fn total_continues_across_wraps() {
+        let mut total = PulseTotal::new(100);
+        assert_eq!(total.update(10), 110);
+        assert_eq!(
+            total.update(COUNTER_LIMIT - 1),
+            100 + COUNTER_LIMIT as u64 - 1
+        );
+        assert_eq!(total.update(5), 100 + COUNTER_LIMIT as u64 + 5);
+        assert_eq!(total.update(5), 100 + COUNTER_LIMIT as u64 + 5);
+    }
+
+    proptest! {
+        #[test]
+        fn total_matches_pulses(steps in proptest::collection::vec(0u16..COUNTER_LIMIT, 0..100)) {
+            let mut total = PulseTotal::new(0);
+            let mut hardware = 0u16;
+            let mut pulses = 0u64;
+
+            for step in steps {
+                hardware = ((hardware as u32 + step as u32) % COUNTER_LIMIT as u32) as u16;
+                pulses += step as u64;
+                prop_assert_eq!(total.update(hardware), pulses);
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Rapid changes of pressure and humidity, such as a storm front moving in or
+//! a door, window or enclosure being opened, reported as soon as they happen
+//! rather than with the next trend.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Samples kept, readings coming in faster than the window allows are thinned out.
+pub const MAX_SAMPLES: usize = 32;
+
+/// Where the device is installed, which decides how fast a change has to be
+/// to count as rapid.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Deployment {
+    /// No detection
+    #[default]
+    Off,
+    /// Weather: storm fronts, a few hPa over hours
+    Outdoor,
+    /// Rooms: a window or door opened to the outside
+    Indoor,
+    /// Sealed housings: any breach shows as a small, fast step
+    Enclosure,
+    /// The thresholds of `custom`
+    Custom,
+}
+
+/// Change of one metric within `window_s` that counts as rapid, 0 leaves the
+/// metric out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Thresholds {
+    pub window_s: u32,
+    pub pressure_hpa: f32,
+    pub humidity_pct: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Deployment::Indoor.thresholds().unwrap()
+    }
+}
+
+impl Deployment {
+    /// The preset thresholds, `None` when off or custom.
+    pub fn thresholds(self) -> Option<Thresholds> {
+        let (window_s, pressure_hpa, humidity_pct) = match self {
+            Deployment::Off | Deployment::Custom => return None,
+            Deployment::Outdoor => (3 * 3600, 3.0, 0.0),
+            Deployment::Indoor => (600, 1.5, 10.0),
+            Deployment::Enclosure => (300, 0.5, 5.0),
+        };
+        Some(Thresholds {
+            window_s,
+            pressure_hpa,
+            humidity_pct,
+        })
+    }
+}
+
+/// Rapid change detection as set in the shadow.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct RapidChange {
+    pub deployment: Deployment,
+    /// Used with the `custom` deployment
+    pub custom: Thresholds,
+}
+
+impl RapidChange {
+    /// The thresholds in effect, `None` when detection is off.
+    pub fn thresholds(&self) -> Option<Thresholds> {
+        match self.deployment {
+            Deployment::Custom => Some(self.custom),
+            deployment => deployment.thresholds(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Pressure,
+    Humidity,
+}
+
+/// A change past its threshold within the window.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    pub metric: Metric,
+    /// Value the change started from and the newest one
+    pub from: f32,
+    pub to: f32,
+    /// Time between the two
+    pub over_s: u32,
+}
+
+impl Change {
+    pub fn delta(&self) -> f32 {
+        self.to - self.from
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Duration,
+    values: [f32; 2],
+}
+
+/// Recent pressure and humidity readings, checked against the thresholds as
+/// each one comes in.
+#[derive(Debug, Clone)]
+pub struct RapidChangeDetector {
+    thresholds: Option<Thresholds>,
+    samples: VecDeque<Sample>,
+    /// Per metric, samples before this are ignored after a change was
+    /// reported so the same change is not reported again
+    rearmed_at: [Option<Duration>; 2],
+}
+
+impl RapidChangeDetector {
+    pub fn new(thresholds: Option<Thresholds>) -> Self {
+        RapidChangeDetector {
+            thresholds,
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+            rearmed_at: [None; 2],
+        }
+    }
+
+    /// Changes the thresholds, the samples taken so far are dropped when they change.
+    pub fn set_thresholds(&mut self, thresholds: Option<Thresholds>) {
+        if thresholds != self.thresholds {
+            *self = Self::new(thresholds);
+        }
+    }
+
+    /// Adds a reading and returns the changes it completes, at most one per metric.
+    pub fn add(&mut self, now: Duration, pressure: f32, humidity: f32) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let Some(thresholds) = self.thresholds else {
+            return changes;
+        };
+        let window = Duration::from_secs(thresholds.window_s as u64);
+        if window.is_zero() {
+            return changes;
+        }
+
+        while let Some(oldest) = self.samples.front() {
+            if now.saturating_sub(oldest.at) <= window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        let values = [pressure, humidity];
+        let limits = [thresholds.pressure_hpa, thresholds.humidity_pct];
+        for (i, metric) in [Metric::Pressure, Metric::Humidity].into_iter().enumerate() {
+            if limits[i] <= 0.0 {
+                continue;
+            }
+            // The sample furthest from the newest value, so a step is found
+            // wherever it falls within the window, the oldest of equals
+            let furthest = self
+                .samples
+                .iter()
+                .rev()
+                .filter(|sample| self.rearmed_at[i].map_or(true, |rearmed| sample.at >= rearmed))
+                .max_by(|a, b| {
+                    let a = (values[i] - a.values[i]).abs();
+                    let b = (values[i] - b.values[i]).abs();
+                    a.total_cmp(&b)
+                });
+            if let Some(sample) = furthest {
+                if (values[i] - sample.values[i]).abs() >= limits[i] {
+                    changes.push(Change {
+                        metric,
+                        from: sample.values[i],
+                        to: values[i],
+                        over_s: now.saturating_sub(sample.at).as_secs() as u32,
+                    });
+                    self.rearmed_at[i] = Some(now);
+                }
+            }
+        }
+
+        let spacing = window / MAX_SAMPLES as u32;
+        let thinned = self
+            .samples
+            .back()
+            .is_some_and(|newest| now.saturating_sub(newest.at) < spacing);
+        if !thinned {
+            if self.samples.len() == MAX_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(Sample { at: now, values });
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const INDOOR: Thresholds = Thresholds {
+        window_s: 600,
+        pressure_hpa: 1.5,
+        humidity_pct: 10.0,
+    };
+
+    fn at(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn off_by_default() {
+        let settings = RapidChange::default();
+        assert_eq!(settings.thresholds(), None);
+
+        let mut detector = RapidChangeDetector::new(settings.thresholds());
+        detector.add(at(1), 1013.0, 40.0);
+        assert!(detector.add(at(60), 990.0, 90.0).is_empty());
+    }
+
+    #[test]
+    fn custom_thresholds_apply_only_to_custom() {
+        let custom = Thresholds {
+            window_s: 60,
+            pressure_hpa: 0.2,
+            humidity_pct: 0.0,
+        };
+        let settings = RapidChange {
+            deployment: Deployment::Enclosure,
+            custom,
+        };
+        assert_eq!(settings.thresholds(), Deployment::Enclosure.thresholds());
+        let settings = RapidChange {
+            deployment: Deployment::Custom,
+            ..settings
+        };
+        assert_eq!(settings.thresholds(), Some(custom));
+    }
+
+    #[test]
+    fn step_within_the_window_is_reported_once() {
+        let mut detector = RapidChangeDetector::new(Some(INDOOR));
+        for s in 0..5 {
+            assert!(detector.add(at(1 + s * 60), 1013.0, 40.0).is_empty());
+        }
+        // Window opened, humidity jumps
+        let changes = detector.add(at(301), 1013.2, 55.0);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].metric, Metric::Humidity);
+        assert_eq!(changes[0].delta(), 15.0);
+        assert_eq!(changes[0].over_s, 300);
+
+        assert!(detector.add(at(361), 1013.2, 56.0).is_empty());
+    }
+
+    #[test]
+    fn slow_changes_are_not_rapid() {
+        let mut detector = RapidChangeDetector::new(Some(INDOOR));
+        // 1 hPa per 10 minutes never reaches 1.5 within the window
+        for s in 0..60 {
+            let pressure = 1013.0 - s as f32 * 0.1;
+            assert!(detector.add(at(1 + s * 60), pressure, 40.0).is_empty());
+        }
+    }
+
+    #[test]
+    fn storm_front_falls_over_hours() {
+        let mut detector = RapidChangeDetector::new(Deployment::Outdoor.thresholds());
+        let changes: Vec<_> = (0..180)
+            .flat_map(|m| detector.add(at(1 + m * 60), 1010.0 - m as f32 / 50.0, 80.0))
+            .collect();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].metric, Metric::Pressure);
+        assert!(changes[0].delta() <= -3.0);
+    }
+
+    #[test]
+    fn deployment_by_name() {
+        let settings: RapidChange = serde_json::from_str(r#"{"deployment": "enclosure"}"#).unwrap();
+        assert_eq!(settings.deployment, Deployment::Enclosure);
+        assert_eq!(settings.custom, Thresholds::default());
+    }
+
+    proptest! {
+        #[test]
+        fn changes_exceed_the_threshold(
+            readings in proptest::collection::vec((1u64..120, 900f32..1100.0, 0f32..100.0), 0..100),
+        ) {
+            let mut detector = RapidChangeDetector::new(Some(INDOOR));
+            let mut now = Duration::ZERO;
+            for (step, pressure, humidity) in readings {
+                now += at(step);
+                for change in detector.add(now, pressure, humidity) {
+                    let limit = match change.metric {
+                        Metric::Pressure => INDOOR.pressure_hpa,
+                        Metric::Humidity => INDOOR.humidity_pct,
+                    };
+                    prop_assert!(change.delta().abs() >= limit);
+                    prop_assert!(change.over_s <= INDOOR.window_s);
+                }
+                prop_assert!(detector.samples.len() <= MAX_SAMPLES);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Register level values of the BME680, so readings can be compensated again
+//! offline with the calibration of the chip.
+
+use alloc::{collections::BTreeMap, string::String};
+use core::fmt::Write;
+use serde::Serialize;
+
+/// First register of the measurement results and how many follow.
+pub const FIELD_REGISTER: u8 = 0x1d;
+pub const FIELD_LEN: usize = 15;
+
+/// Start and length of the register blocks the compensation needs: the two
+/// blocks of calibration coefficients, and the heater resistance and range
+/// switching error.
+pub const CALIBRATION_BLOCKS: [(u8, usize); 3] = [(0x89, 25), (0xe1, 16), (0x00, 5)];
+
+const GAS_VALID: u8 = 0x20;
+const GAS_RANGE: u8 = 0x0f;
+
+/// ADC counts of the last measurement, before compensation.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct RawValues {
+    pub temperature_adc: u32,
+    pub pressure_adc: u32,
+    pub humidity_adc: u16,
+    /// Unset when the measurement did not include a valid gas reading
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_adc: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_range: Option<u8>,
+}
+
+impl RawValues {
+    /// Parses the registers from `FIELD_REGISTER` on.
+    pub fn parse(field: &[u8; FIELD_LEN]) -> Self {
+        let adc20 = |msb: u8, lsb: u8, xlsb: u8| {
+            (msb as u32) << 12 | (lsb as u32) << 4 | (xlsb as u32) >> 4
+        };
+        let gas_valid = field[14] & GAS_VALID != 0;
+
+        RawValues {
+            pressure_adc: adc20(field[2], field[3], field[4]),
+            temperature_adc: adc20(field[5], field[6], field[7]),
+            humidity_adc: (field[8] as u16) << 8 | field[9] as u16,
+            gas_adc: gas_valid.then_some((field[13] as u16) << 2 | (field[14] as u16) >> 6),
+            gas_range: gas_valid.then_some(field[14] & GAS_RANGE),
+        }
+    }
+}
+
+/// Calibration of one chip, published retained next to the raw values.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Calibration {
+    /// Hex encoded contents of `CALIBRATION_BLOCKS` by start register, e.g. `"0x89"`
+    pub registers: BTreeMap<String, String>,
+    /// Offset the firmware adds to the compensated temperature
+    pub temperature_offset_c: f32,
+}
+
+impl Calibration {
+    pub fn new<'a>(
+        blocks: impl IntoIterator<Item = (u8, &'a [u8])>,
+        temperature_offset_c: f32,
+    ) -> Self {
+        let registers = blocks
+            .into_iter()
+            .map(|(register, data)| {
+                let mut hex = String::with_capacity(data.len() * 2);
+                for byte in data {
+                    let _ = write!(hex, "{:02x}", byte);
+                }
+                (alloc::format!("{:#04x}", register), hex)
+            })
+            .collect();
+
+        Calibration {
+            registers,
+            temperature_offset_c,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // 0x1d..=0x2b after a forced measurement with the gas heater on
+    const FIELD: [u8; FIELD_LEN] = [
+        0x80, 0x00, 0x4f, 0x3a, 0x70, 0x7e, 0x1c, 0x60, 0x5c, 0x21, 0x80, 0x00, 0x00, 0xb3, 0x74,
+    ];
+
+    #[test]
+    fn counts_are_assembled_from_the_registers() {
+        let raw = RawValues::parse(&FIELD);
+        assert_eq!(raw.pressure_adc, 0x4f3a7);
+        assert_eq!(raw.temperature_adc, 0x7e1c6);
+        assert_eq!(raw.humidity_adc, 0x5c21);
+        assert_eq!(raw.gas_adc, Some(0xb3 << 2 | 1));
+        assert_eq!(raw.gas_range, Some(4));
+    }
+
+    #[test]
+    fn invalid_gas_is_left_out() {
+        let mut field = FIELD;
+        field[14] &= !GAS_VALID;
+        let raw = RawValues::parse(&field);
+        assert_eq!(raw.gas_adc, None);
+
+        let value = serde_json::to_value(raw).unwrap();
+        assert!(value.get("gas_adc").is_none());
+        assert!(value.get("gas_range").is_none());
+    }
+
+    #[test]
+    fn calibration_by_start_register() {
+        let calibration = Calibration::new([(0x89, &[0x12, 0xab][..]), (0x00, &[0x3c][..])], -2.2);
+        assert_eq!(
+            serde_json::to_value(&calibration).unwrap()["registers"],
+            serde_json::json!({ "0x00": "3c", "0x89": "12ab" })
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn counts_fit_the_adc_width(field in any::<[u8; FIELD_LEN]>()) {
+            let raw = RawValues::parse(&field);
+            prop_assert!(raw.temperature_adc < 1 << 20);
+            prop_assert!(raw.pressure_adc < 1 << 20);
+            prop_assert!(raw.gas_adc.unwrap_or(0) < 1 << 10);
+            prop_assert!(raw.gas_range.unwrap_or(0) < 16);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Not started yet, or waiting for something to happen
+    Pending,
+    Ready,
+    Failed,
+    /// Not started because a dependency is not ready
+    Blocked,
+}
+
+/// A subsystem brought up at startup and what has to be ready before it.
+#[derive(Debug, Clone, Copy)]
+pub struct Component {
+    pub name: &'static str,
+    pub depends_on: &'static [&'static str],
+}
+
+impl Component {
+    pub const fn new(name: &'static str, depends_on: &'static [&'static str]) -> Self {
+        Component { name, depends_on }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DeclarationError {
+    Duplicate(&'static str),
+    /// A dependency that is not declared before the component
+    UnknownDependency {
+        component: &'static str,
+        dependency: &'static str,
+    },
+}
+
+impl fmt::Display for DeclarationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeclarationError::Duplicate(name) => write!(f, "{} is declared twice", name),
+            DeclarationError::UnknownDependency {
+                component,
+                dependency,
+            } => write!(
+                f,
+                "{} depends on {}, which is not declared before it",
+                component, dependency
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DeclarationError {}
+
+/// Readiness of the components, reported as a map of name to state.
+///
+/// Components are declared in the order they start, after what they depend
+/// on, so the dependencies cannot form a cycle.
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    components: Vec<(Component, State)>,
+}
+
+impl Readiness {
+    pub fn new(components: &[Component]) -> Result<Self, DeclarationError> {
+        for (index, component) in components.iter().enumerate() {
+            let earlier = &components[..index];
+            if earlier.iter().any(|other| other.name == component.name) {
+                return Err(DeclarationError::Duplicate(component.name));
+            }
+            if let Some(&dependency) = component
+                .depends_on
+                .iter()
+                .find(|&&dependency| !earlier.iter().any(|other| other.name == dependency))
+            {
+                return Err(DeclarationError::UnknownDependency {
+                    component: component.name,
+                    dependency,
+                });
+            }
+        }
+
+        Ok(Readiness {
+            components: components
+                .iter()
+                .map(|&component| (component, State::Pending))
+                .collect(),
+        })
+    }
+
+    pub fn state(&self, name: &str) -> Option<State> {
+        self.components
+            .iter()
+            .find(|(component, _)| component.name == name)
+            .map(|&(_, state)| state)
+    }
+
+    pub fn is_ready(&self, name: &str) -> bool {
+        self.state(name) == Some(State::Ready)
+    }
+
+    /// Whether `name` can start, which needs all its dependencies ready. Otherwise
+    /// it is marked blocked and the dependency holding it up returned.
+    pub fn gate(&mut self, name: &'static str) -> Result<(), &'static str> {
+        let Some(index) = self.components.iter().position(|(c, _)| c.name == name) else {
+            return Err(name);
+        };
+
+        let component = self.components[index].0;
+        if let Some(&dependency) = component
+            .depends_on
+            .iter()
+            .find(|&&dependency| !self.is_ready(dependency))
+        {
+            self.components[index].1 = State::Blocked;
+            return Err(dependency);
+        }
+        Ok(())
+    }
+
+    /// Sets the state of a declared component, others are ignored.
+    pub fn set(&mut self, name: &str, state: State) {
+        if let Some((_, current)) = self.components.iter_mut().find(|(c, _)| c.name == name) {
+            *current = state;
+        }
+    }
+}
+
+impl Serialize for Readiness {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.components.len()))?;
+        for (component, state) in &self.components {
+            map.serialize_entry(component.name, state)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPONENTS: &[Component] = &[
+        Component::new("nvs", &[]),
+        Component::new("wifi", &["nvs"]),
+        Component::new("time", &["wifi"]),
+        Component::new("mqtt", &["wifi"]),
+        Component::new("display", &[]),
+    ];
+
+    #[test]
+    fn starts_once_dependencies_are_ready() {
+        let mut readiness = Readiness::new(COMPONENTS).unwrap();
+        assert_eq!(readiness.gate("wifi"), Err("nvs"));
+        assert_eq!(readiness.state("wifi"), Some(State::Blocked));
+
+        readiness.set("nvs", State::Ready);
+        assert_eq!(readiness.gate("wifi"), Ok(()));
+        readiness.set("wifi", State::Ready);
+        assert_eq!(readiness.gate("mqtt"), Ok(()));
+    }
+
+    #[test]
+    fn failure_blocks_what_depends_on_it() {
+        let mut readiness = Readiness::new(COMPONENTS).unwrap();
+        readiness.set("nvs", State::Ready);
+        readiness.set("wifi", State::Failed);
+        readiness.set("display", State::Failed);
+
+        assert_eq!(readiness.gate("time"), Err("wifi"));
+        assert_eq!(readiness.gate("mqtt"), Err("wifi"));
+        assert!(!readiness.is_ready("time"));
+    }
+
+    #[test]
+    fn unknown_component_is_not_started() {
+        let mut readiness = Readiness::new(COMPONENTS).unwrap();
+        assert_eq!(readiness.gate("sd_card"), Err("sd_card"));
+        readiness.set("sd_card", State::Ready);
+        assert_eq!(readiness.state("sd_card"), None);
+    }
+
+    #[test]
+    fn dependencies_must_be_declared_first() {
+        assert_eq!(
+            Readiness::new(&[
+                Component::new("mqtt", &["wifi"]),
+                Component::new("wifi", &[])
+            ])
+            .unwrap_err(),
+            DeclarationError::UnknownDependency {
+                component: "mqtt",
+                dependency: "wifi"
+            }
+        );
+        assert_eq!(
+            Readiness::new(&[Component::new("wifi", &[]), Component::new("wifi", &[])])
+                .unwrap_err(),
+            DeclarationError::Duplicate("wifi")
+        );
+        // Depending on itself is a cycle as well
+        assert!(Readiness::new(&[Component::new("wifi", &["wifi"])]).is_err());
+    }
+
+    #[test]
+    fn reported_as_map_in_declaration_order() {
+        let mut readiness = Readiness::new(COMPONENTS).unwrap();
+        readiness.set("nvs", State::Ready);
+        readiness.set("display", State::Failed);
+
+        assert_eq!(
+            serde_json::to_string(&readiness).unwrap(),
+            r#"{"nvs":"ready","wifi":"pending","time":"pending","mqtt":"pending","display":"failed"}"#
+        );
+    }
+}
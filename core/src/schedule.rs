@@ -0,0 +1,136 @@
+use core::time::Duration;
+
+// Times are monotonic durations since an arbitrary start, usually boot
+
+/// Fires once per period, the first time right away.
+#[derive(Debug, Clone)]
+pub struct Interval {
+    period: Duration,
+    last: Option<Duration>,
+}
+
+impl Interval {
+    pub fn new(period: Duration) -> Self {
+        Interval { period, last: None }
+    }
+
+    pub fn is_due(&self, now: Duration) -> bool {
+        self.last
+            .map_or(true, |last| now.saturating_sub(last) >= self.period)
+    }
+
+    /// Records a run, the next one is due a period later.
+    pub fn mark(&mut self, now: Duration) {
+        self.last = Some(now);
+    }
+}
+
+/// Allows `rate` events per second on average and bursts of up to `capacity`.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: u32,
+    capacity: u32,
+    tokens: u32,
+    refilled: Duration,
+}
+
+impl TokenBucket {
+    /// Starts with `rate` tokens, one second worth of events.
+    pub fn new(rate: u32, capacity: u32, now: Duration) -> Self {
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: rate.min(capacity),
+            refilled: now,
+        }
+    }
+
+    /// Takes a token if one is left, refilling for every whole second since the last refill.
+    pub fn try_take(&mut self, now: Duration) -> bool {
+        let elapsed = now.saturating_sub(self.refilled).as_secs();
+        if elapsed > 0 {
+            let added = u32::try_from(elapsed)
+                .unwrap_or(u32::MAX)
+                .saturating_mul(self.rate);
+            self.tokens = self.tokens.saturating_add(added).min(self.capacity);
+            self.refilled += Duration::from_secs(elapsed);
+        }
+
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn interval_is_due_before_first_run() {
+        assert!(Interval::new(secs(300)).is_due(Duration::ZERO));
+    }
+
+    #[test]
+    fn interval_waits_a_period_after_a_run() {
+        let mut interval = Interval::new(secs(300));
+        interval.mark(secs(10));
+
+        assert!(!interval.is_due(secs(10)));
+        assert!(!interval.is_due(secs(309)));
+        assert!(interval.is_due(secs(310)));
+    }
+
+    #[test]
+    fn interval_tolerates_time_going_backwards() {
+        let mut interval = Interval::new(secs(300));
+        interval.mark(secs(100));
+        assert!(!interval.is_due(secs(50)));
+    }
+
+    #[test]
+    fn bucket_allows_initial_burst_of_rate() {
+        let mut bucket = TokenBucket::new(3, 15, Duration::ZERO);
+
+        assert!(bucket.try_take(Duration::ZERO));
+        assert!(bucket.try_take(Duration::ZERO));
+        assert!(bucket.try_take(Duration::ZERO));
+        assert!(!bucket.try_take(millis(999)));
+    }
+
+    #[test]
+    fn bucket_refills_per_whole_second() {
+        let mut bucket = TokenBucket::new(1, 5, Duration::ZERO);
+        assert!(bucket.try_take(Duration::ZERO));
+        assert!(!bucket.try_take(millis(500)));
+        assert!(bucket.try_take(millis(1500)));
+        // The half second left over counts towards the next refill
+        assert!(bucket.try_take(millis(2000)));
+        assert!(!bucket.try_take(millis(2100)));
+    }
+
+    #[test]
+    fn bucket_is_capped_at_capacity() {
+        let mut bucket = TokenBucket::new(2, 4, Duration::ZERO);
+        let now = secs(60);
+
+        let taken = (0..10).filter(|_| bucket.try_take(now)).count();
+        assert_eq!(taken, 4);
+    }
+
+    #[test]
+    fn bucket_survives_long_idle_periods() {
+        let mut bucket = TokenBucket::new(u32::MAX, u32::MAX, Duration::ZERO);
+        assert!(bucket.try_take(secs(u64::MAX / 2)));
+    }
+}
@@ -0,0 +1,569 @@
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    diagnostics::Diagnostics, digital::DigitalInput, features::FeatureFlags, pulse::PulseCounter,
+    rapid_change::RapidChange, sitewise::SiteWise, startup::Startup, summary::SummarySchedule,
+    weather::WeatherStation,
+};
+
+/// Settings that can be changed at runtime through the device shadow.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    /// Subsystems switched on or off
+    pub features: FeatureFlags,
+    /// Raise an alert once the device certificate expires within this many days
+    pub cert_expiry_warn_days: u32,
+    /// Also publish every reading retained to `<pub_topic>/latest`
+    pub publish_latest: bool,
+    /// Samples per reading, more than one publishes their outlier-trimmed mean
+    pub burst_samples: u8,
+    /// Battery capacity for the battery life estimate, 0 on mains power
+    pub battery_capacity_mah: u32,
+    /// Transport readings are sent over, commands and the shadow always use MQTT
+    pub transport: Transport,
+    /// Gateway readings are POSTed to with the CoAP transport, `coap://host[:port]/path`
+    pub coap_url: String,
+    /// Contacts and switches whose states are part of every reading
+    pub digital_inputs: Vec<DigitalInput>,
+    /// Flow and energy meters whose pulses are counted
+    pub pulse_counters: Vec<PulseCounter>,
+    /// Rain gauge, anemometer and wind vane, publishes a weather payload with every reading
+    pub weather_station: WeatherStation,
+    /// When the daily summary goes to `<pub_topic>/summary`
+    pub daily_summary: SummarySchedule,
+    /// Window of the temperature, humidity and pressure trends, 0 leaves them out
+    pub trend_window_min: u16,
+    /// Append an HMAC of the provisioned signing key to every reading
+    pub sign_payloads: bool,
+    /// What the BME680 measures, `gas_disabled` for battery nodes
+    pub sensor_profile: SensorProfile,
+    /// Reconnect with a client ID suffixed from the MAC address when another
+    /// device turns out to be using the same one
+    pub client_id_fallback: bool,
+    /// Ask the broker to keep subscriptions and messages for the device across
+    /// disconnects, the client is created again when it changes
+    pub persistent_session: bool,
+    /// Converted values, the ADC counts behind them or both
+    pub reported_values: ReportedValues,
+    /// Gate of the commands that read and write I2C registers
+    pub diagnostics: Diagnostics,
+    /// Commands over MQTT have to carry an `issued_at` at most this many
+    /// seconds old, 0 accepts them without one. On by default so that a
+    /// retained command does not run again after a reconnect.
+    pub command_window_s: u32,
+    /// Which rapid pressure and humidity changes publish an event right away
+    pub rapid_change: RapidChange,
+    /// Random waits before Wi-Fi and MQTT come up after a power loss
+    pub startup: Startup,
+    /// Readings as SiteWise property values, for an IoT Core rule to forward
+    pub sitewise: SiteWise,
+    /// Node IDs a LoRa gateway bridges, frames of other nodes are dropped
+    pub lora_nodes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    #[default]
+    Mqtt,
+    Coap,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorProfile {
+    /// Temperature, humidity, pressure and gas resistance
+    #[default]
+    Standard,
+    /// Leaves the gas heater off, readings carry no gas resistance
+    GasDisabled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportedValues {
+    #[default]
+    Converted,
+    /// Converted values and the raw ones under `raw`
+    Both,
+    Raw,
+}
+
+impl ReportedValues {
+    pub fn converted(self) -> bool {
+        self != ReportedValues::Raw
+    }
+
+    pub fn raw(self) -> bool {
+        self != ReportedValues::Converted
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            features: FeatureFlags::default(),
+            cert_expiry_warn_days: 30,
+            publish_latest: true,
+            burst_samples: 1,
+            battery_capacity_mah: 0,
+            transport: Transport::default(),
+            coap_url: String::new(),
+            digital_inputs: Vec::new(),
+            pulse_counters: Vec::new(),
+            weather_station: WeatherStation::default(),
+            daily_summary: SummarySchedule::default(),
+            trend_window_min: 30,
+            sign_payloads: false,
+            sensor_profile: SensorProfile::default(),
+            client_id_fallback: false,
+            persistent_session: false,
+            reported_values: ReportedValues::default(),
+            diagnostics: Diagnostics::default(),
+            command_window_s: 300,
+            rapid_change: RapidChange::default(),
+            startup: Startup::default(),
+            sitewise: SiteWise::default(),
+            lora_nodes: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Merges a (possibly partial) desired state document into the settings.
+    ///
+    /// Keys missing from `desired` keep their current value and unknown keys are
+    /// ignored. If the merged document does not deserialize, the settings are left
+    /// untouched. Returns whether anything changed.
+    pub fn apply(&mut self, desired: &Value) -> Result<bool, serde_json::Error> {
+        let mut merged = serde_json::to_value(&*self)?;
+        merge(&mut merged, desired);
+
+        let updated: Settings = serde_json::from_value(merged)?;
+        let changed = updated != *self;
+        *self = updated;
+
+        Ok(changed)
+    }
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                // The shadow uses null to delete a key, keep our current value for those
+                if value.is_null() {
+                    continue;
+                }
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        digital::Pull,
+        rapid_change::{Deployment, Thresholds},
+        sitewise::Alias,
+        strategies::json_value,
+    };
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    const KEYS: &[&str] = &[
+        "features",
+        "cert_expiry_warn_days",
+        "publish_latest",
+        "burst_samples",
+        "battery_capacity_mah",
+        "transport",
+        "coap_url",
+        "digital_inputs",
+        "pulse_counters",
+        "weather_station",
+        "daily_summary",
+        "trend_window_min",
+        "sign_payloads",
+        "sensor_profile",
+        "client_id_fallback",
+        "persistent_session",
+        "reported_values",
+        "diagnostics",
+        "command_window_s",
+        "rapid_change",
+        "startup",
+        "sitewise",
+        "lora_nodes",
+    ];
+
+    #[test]
+    fn partial_document_keeps_other_settings() {
+        let mut settings = Settings::default();
+        assert!(settings
+            .apply(&json!({ "burst_samples": 4, "features": { "sensor_events": false } }))
+            .unwrap());
+
+        assert_eq!(settings.burst_samples, 4);
+        assert!(!settings.features.sensor_events);
+        assert!(settings.features.health_reports);
+        assert_eq!(settings.cert_expiry_warn_days, 30);
+    }
+
+    #[test]
+    fn null_keeps_current_value() {
+        let mut settings = Settings {
+            burst_samples: 4,
+            ..Settings::default()
+        };
+        assert!(!settings.apply(&json!({ "burst_samples": null })).unwrap());
+        assert_eq!(settings.burst_samples, 4);
+    }
+
+    #[test]
+    fn unknown_keys_are_ignored() {
+        let mut settings = Settings::default();
+        assert!(!settings.apply(&json!({ "ota_url": "http://x" })).unwrap());
+    }
+
+    #[test]
+    fn invalid_value_leaves_settings_untouched() {
+        let mut settings = Settings::default();
+        assert!(settings
+            .apply(&json!({ "publish_latest": false, "burst_samples": 300 }))
+            .is_err());
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn reported_values() {
+        let mut settings = Settings::default();
+        assert!(!settings.reported_values.raw());
+        settings
+            .apply(&json!({ "reported_values": "both" }))
+            .unwrap();
+        assert!(settings.reported_values.converted() && settings.reported_values.raw());
+        settings
+            .apply(&json!({ "reported_values": "raw" }))
+            .unwrap();
+        assert!(!settings.reported_values.converted());
+    }
+
+    #[test]
+    fn digital_inputs_are_replaced_as_a_whole() {
+        let mut settings = Settings::default();
+        settings
+            .apply(&json!({ "digital_inputs": [
+                { "pin": 4, "label": "door", "invert": true, "pull": "up" },
+                { "pin": 36, "label": "float" },
+            ] }))
+            .unwrap();
+        assert_eq!(settings.digital_inputs.len(), 2);
+        assert_eq!(settings.digital_inputs[0].pull, Pull::Up);
+
+        settings
+            .apply(&json!({ "digital_inputs": [{ "pin": 5, "label": "tamper" }] }))
+            .unwrap();
+        assert_eq!(settings.digital_inputs.len(), 1);
+        assert_eq!(settings.digital_inputs[0].label, "tamper");
+    }
+
+    #[test]
+    fn transport_by_name() {
+        let mut settings = Settings::default();
+        settings
+            .apply(&json!({ "transport": "coap", "coap_url": "coap://gw/readings" }))
+            .unwrap();
+        assert_eq!(settings.transport, Transport::Coap);
+        assert!(settings.apply(&json!({ "transport": "lora" })).is_err());
+    }
+
+    #[test]
+    fn sensor_profile() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.sensor_profile, SensorProfile::Standard);
+        assert!(settings
+            .apply(&json!({ "sensor_profile": "gas_disabled" }))
+            .unwrap());
+        assert_eq!(settings.sensor_profile, SensorProfile::GasDisabled);
+        assert!(settings.apply(&json!({ "sensor_profile": "eco" })).is_err());
+    }
+
+    fn settings() -> impl Strategy<Value = Settings> {
+        (
+            any::<[bool; 3]>(),
+            any::<u32>(),
+            any::<bool>(),
+            any::<u8>(),
+            any::<u32>(),
+            prop_oneof![Just(Transport::Mqtt), Just(Transport::Coap)],
+            "\\PC{0,32}",
+            proptest::collection::vec(digital_input(), 0..3),
+            proptest::collection::vec(pulse_counter(), 0..3),
+            weather_station(),
+            summary_schedule(),
+            (
+                any::<u16>(),
+                any::<bool>(),
+                prop_oneof![
+                    Just(SensorProfile::Standard),
+                    Just(SensorProfile::GasDisabled)
+                ],
+                any::<[bool; 4]>(),
+                prop_oneof![
+                    Just(ReportedValues::Converted),
+                    Just(ReportedValues::Both),
+                    Just(ReportedValues::Raw)
+                ],
+                any::<u32>(),
+                rapid_change(),
+                any::<[u32; 2]>(),
+                sitewise(),
+                proptest::collection::vec("[a-z0-9-]{1,8}", 0..3),
+            ),
+        )
+            .prop_map(
+                |(
+                    [health_reports, input_events, sensor_events],
+                    cert_expiry_warn_days,
+                    publish_latest,
+                    burst_samples,
+                    battery_capacity_mah,
+                    transport,
+                    coap_url,
+                    digital_inputs,
+                    pulse_counters,
+                    weather_station,
+                    daily_summary,
+                    (
+                        trend_window_min,
+                        sign_payloads,
+                        sensor_profile,
+                        [client_id_fallback, persistent_session, unlocked, allow_writes],
+                        reported_values,
+                        command_window_s,
+                        rapid_change,
+                        [wifi_max_delay_s, mqtt_max_delay_s],
+                        sitewise,
+                        lora_nodes,
+                    ),
+                )| Settings {
+                    features: FeatureFlags {
+                        health_reports,
+                        input_events,
+                        sensor_events,
+                    },
+                    cert_expiry_warn_days,
+                    publish_latest,
+                    burst_samples,
+                    battery_capacity_mah,
+                    transport,
+                    coap_url,
+                    digital_inputs,
+                    pulse_counters,
+                    weather_station,
+                    daily_summary,
+                    trend_window_min,
+                    sign_payloads,
+                    sensor_profile,
+                    client_id_fallback,
+                    persistent_session,
+                    reported_values,
+                    diagnostics: Diagnostics {
+                        unlocked,
+                        allow_writes,
+                    },
+                    command_window_s,
+                    rapid_change,
+                    startup: Startup {
+                        wifi_max_delay_s,
+                        mqtt_max_delay_s,
+                    },
+                    sitewise,
+                    lora_nodes,
+                },
+            )
+    }
+
+    fn sitewise() -> impl Strategy<Value = SiteWise> {
+        (
+            any::<bool>(),
+            "[a-z/-]{0,24}",
+            proptest::collection::vec(
+                ("[a-z_]{1,8}", "[a-z/-]{1,24}")
+                    .prop_map(|(metric, alias)| Alias { metric, alias }),
+                0..3,
+            ),
+        )
+            .prop_map(|(enabled, alias_prefix, aliases)| SiteWise {
+                enabled,
+                alias_prefix,
+                aliases,
+            })
+    }
+
+    fn rapid_change() -> impl Strategy<Value = RapidChange> {
+        (
+            prop_oneof![
+                Just(Deployment::Off),
+                Just(Deployment::Outdoor),
+                Just(Deployment::Indoor),
+                Just(Deployment::Enclosure),
+                Just(Deployment::Custom)
+            ],
+            any::<u32>(),
+            0f32..10.0,
+            0f32..100.0,
+        )
+            .prop_map(
+                |(deployment, window_s, pressure_hpa, humidity_pct)| RapidChange {
+                    deployment,
+                    custom: Thresholds {
+                        window_s,
+                        pressure_hpa,
+                        humidity_pct,
+                    },
+                },
+            )
+    }
+
+    fn digital_input() -> impl Strategy<Value = DigitalInput> {
+        (
+            any::<u8>(),
+            "[a-z]{1,8}",
+            any::<bool>(),
+            prop_oneof![Just(Pull::None), Just(Pull::Up), Just(Pull::Down)],
+            any::<u32>(),
+        )
+            .prop_map(|(pin, label, invert, pull, debounce_ms)| DigitalInput {
+                pin,
+                label,
+                invert,
+                pull,
+                debounce_ms,
+            })
+    }
+
+    fn pulse_counter() -> impl Strategy<Value = PulseCounter> {
+        (
+            any::<u8>(),
+            "[a-z]{1,8}",
+            0.001f64..100_000.0,
+            "[a-zA-Z]{0,4}",
+            any::<u32>(),
+        )
+            .prop_map(
+                |(pin, label, pulses_per_unit, unit, filter_ns)| PulseCounter {
+                    pin,
+                    label,
+                    pulses_per_unit,
+                    unit,
+                    filter_ns,
+                },
+            )
+    }
+
+    fn weather_station() -> impl Strategy<Value = WeatherStation> {
+        (
+            any::<[bool; 2]>(),
+            any::<[u8; 3]>(),
+            0.01f64..10.0,
+            0.1f64..10.0,
+            any::<u32>(),
+        )
+            .prop_map(
+                |(
+                    [enabled, vane],
+                    [rain_pin, anemometer_pin, vane_pin],
+                    rain_mm_per_tip,
+                    wind_kmh_per_hz,
+                    vane_pullup_ohm,
+                )| WeatherStation {
+                    enabled,
+                    rain_pin,
+                    rain_mm_per_tip,
+                    anemometer_pin,
+                    wind_kmh_per_hz,
+                    vane,
+                    vane_pin,
+                    vane_pullup_ohm,
+                },
+            )
+    }
+
+    fn summary_schedule() -> impl Strategy<Value = SummarySchedule> {
+        (any::<bool>(), any::<u8>(), any::<u8>(), any::<i16>()).prop_map(
+            |(enabled, hour, minute, utc_offset_min)| SummarySchedule {
+                enabled,
+                hour,
+                minute,
+                utc_offset_min,
+            },
+        )
+    }
+
+    /// Desired state documents as the shadow could send them, mostly with known keys.
+    fn patch() -> impl Strategy<Value = Value> {
+        let key = prop_oneof![
+            4 => proptest::sample::select(KEYS).prop_map(String::from),
+            1 => "[a-z_]{1,24}",
+        ];
+        prop_oneof![
+            4 => proptest::collection::vec((key, json_value()), 0..6)
+                .prop_map(|entries| Value::Object(entries.into_iter().collect())),
+            1 => json_value(),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn apply_never_panics(mut current in settings(), desired in patch()) {
+            let _ = current.apply(&desired);
+        }
+
+        #[test]
+        fn failed_apply_leaves_settings_untouched(mut current in settings(), desired in patch()) {
+            let before = current.clone();
+            if current.apply(&desired).is_err() {
+                prop_assert_eq!(current, before);
+            }
+        }
+
+        #[test]
+        fn reported_change_matches(mut current in settings(), desired in patch()) {
+            let before = current.clone();
+            if let Ok(changed) = current.apply(&desired) {
+                prop_assert_eq!(changed, current != before);
+            }
+        }
+
+        #[test]
+        fn apply_is_idempotent(mut current in settings(), desired in patch()) {
+            if current.apply(&desired).is_ok() {
+                let once = current.clone();
+                prop_assert!(!current.apply(&desired).unwrap());
+                prop_assert_eq!(current, once);
+            }
+        }
+
+        #[test]
+        fn full_document_replaces_settings(mut current in settings(), desired in settings()) {
+            current.apply(&serde_json::to_value(&desired).unwrap()).unwrap();
+            prop_assert_eq!(current, desired);
+        }
+
+        #[test]
+        fn nulls_change_nothing(mut current in settings(), keys in proptest::sample::subsequence(KEYS, 0..KEYS.len())) {
+            let before = current.clone();
+            let desired = Value::Object(keys.into_iter().map(|key| (key.into(), Value::Null)).collect());
+            prop_assert!(!current.apply(&desired).unwrap());
+            prop_assert_eq!(current, before);
+        }
+    }
+}
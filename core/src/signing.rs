@@ -0,0 +1,124 @@
+use alloc::vec::Vec;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Member the signature is appended as, always the last one of the object.
+pub const SIGNATURE_FIELD: &str = "sig";
+
+fn mac(key: &[u8], message: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac
+}
+
+/// Appends `"sig": "<base64 HMAC-SHA256>"` to a JSON object payload.
+///
+/// The HMAC covers the payload exactly as it was before the signature was
+/// added, which is what is left after removing `,"sig":"..."` before the closing
+/// brace. Consumers verify that way without re-encoding any JSON. `None` when
+/// the payload is not a JSON object.
+pub fn sign(payload: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+    let body = payload.strip_suffix(b"}")?;
+    if !payload.starts_with(b"{") {
+        return None;
+    }
+
+    let signature = STANDARD.encode(mac(key, payload).finalize().into_bytes());
+
+    let mut signed = Vec::with_capacity(payload.len() + signature.len() + 10);
+    signed.extend_from_slice(body);
+    if body != b"{" {
+        signed.push(b',');
+    }
+    signed.extend_from_slice(b"\"");
+    signed.extend_from_slice(SIGNATURE_FIELD.as_bytes());
+    signed.extend_from_slice(b"\":\"");
+    signed.extend_from_slice(signature.as_bytes());
+    signed.extend_from_slice(b"\"}");
+    Some(signed)
+}
+
+/// Checks the signature `sign` appended, in constant time.
+pub fn verify(signed: &[u8], key: &[u8]) -> bool {
+    let marker = [b"\"", SIGNATURE_FIELD.as_bytes(), b"\":\""].concat();
+    let Some(rest) = signed.strip_suffix(b"\"}") else {
+        return false;
+    };
+    // Base64 has no quotes, so the last occurrence is the appended member
+    let Some(start) = rest.windows(marker.len()).rposition(|w| w == marker) else {
+        return false;
+    };
+    let Ok(signature) = STANDARD.decode(&rest[start + marker.len()..]) else {
+        return false;
+    };
+
+    let body = &signed[..start];
+    let body = body.strip_suffix(b",").unwrap_or(body);
+    let payload = [body, b"}"].concat();
+    mac(key, &payload).verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+
+    const KEY: &[u8] = b"device key";
+
+    #[test]
+    fn signature_is_the_last_member() {
+        let signed = sign(br#"{"temperature":21,"humidity":40}"#, KEY).unwrap();
+        let value: Value = serde_json::from_slice(&signed).unwrap();
+        assert_eq!(value["temperature"], 21);
+        assert!(value[SIGNATURE_FIELD].is_string());
+        assert!(signed.starts_with(br#"{"temperature":21,"humidity":40,"sig":""#));
+    }
+
+    #[test]
+    fn known_signature() {
+        // echo -n '{"a":1}' | openssl dgst -sha256 -hmac key -binary | base64
+        assert_eq!(
+            sign(br#"{"a":1}"#, b"key").unwrap(),
+            br#"{"a":1,"sig":"iKZ/JLvNrtDmyZdAS7eadDuvRMa6svTCcyjjAJ0i40I="}"#
+        );
+    }
+
+    #[test]
+    fn empty_object() {
+        let signed = sign(b"{}", KEY).unwrap();
+        assert!(signed.starts_with(br#"{"sig":""#));
+        assert!(verify(&signed, KEY));
+    }
+
+    #[test]
+    fn only_objects_are_signed() {
+        assert_eq!(sign(b"[1,2]", KEY), None);
+        assert_eq!(sign(b"21", KEY), None);
+        assert_eq!(sign(b"", KEY), None);
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let signed = sign(br#"{"temperature":21}"#, KEY).unwrap();
+        assert!(verify(&signed, KEY));
+        assert!(!verify(&signed, b"other key"));
+
+        let tampered = String::from_utf8(signed).unwrap().replace("21", "22");
+        assert!(!verify(tampered.as_bytes(), KEY));
+        assert!(!verify(br#"{"temperature":21}"#, KEY));
+    }
+
+    proptest! {
+        #[test]
+        fn signed_payloads_verify(payload in crate::strategies::json_value(), key in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let payload = serde_json::to_vec(&json!({ "reading": payload })).unwrap();
+            let signed = sign(&payload, &key).unwrap();
+
+            prop_assert!(verify(&signed, &key));
+            let value: Value = serde_json::from_slice(&signed).unwrap();
+            prop_assert_eq!(&value["reading"], &serde_json::from_slice::<Value>(&payload).unwrap()["reading"]);
+        }
+    }
+}
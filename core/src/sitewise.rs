@@ -0,0 +1,316 @@
+//! Readings as AWS IoT SiteWise property values, in the shape of a
+//! `BatchPutAssetPropertyValue` request, so an IoT Core rule can forward them
+//! into SiteWise without transforming them first.
+
+use alloc::{format, string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::telemetry::SensorData;
+
+/// Entries SiteWise accepts in one request.
+pub const MAX_ENTRIES: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SiteWise {
+    /// Also publish readings to `<pub_topic>/sitewise`
+    pub enabled: bool,
+    /// Put in front of the metric names for the property aliases, e.g.
+    /// `/acme/plant-1/boiler-room/`
+    pub alias_prefix: String,
+    /// Aliases of single metrics, replacing the prefixed ones. A list rather
+    /// than a map so that a desired state replaces it as a whole.
+    pub aliases: Vec<Alias>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Alias {
+    pub metric: String,
+    pub alias: String,
+}
+
+impl SiteWise {
+    pub fn alias(&self, metric: &str) -> String {
+        match self.aliases.iter().find(|alias| alias.metric == metric) {
+            Some(alias) => alias.alias.clone(),
+            None => format!("{}{}", self.alias_prefix, metric),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BatchPut {
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// The metric name, unique within a request
+    pub entry_id: String,
+    pub property_alias: String,
+    pub property_values: Vec<PropertyValue>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PropertyValue {
+    pub value: Variant,
+    pub timestamp: Timestamp,
+    pub quality: Quality,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Variant {
+    DoubleValue(f64),
+    BooleanValue(bool),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Timestamp {
+    pub time_in_seconds: u64,
+    pub offset_in_nanos: u32,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Quality {
+    Good,
+    /// The clock drifted too fast for the timestamp to be trusted
+    Uncertain,
+}
+
+/// The values of `reading` by metric name. Inputs are `input_<label>`, pulse
+/// counters `<label>_total` and `<label>_rate`.
+pub fn values(reading: &SensorData) -> Vec<(String, Variant)> {
+    let mut values = Vec::new();
+    let converted = [
+        ("temperature", reading.temperature),
+        ("humidity", reading.humidity),
+        ("pressure", reading.pressure),
+        ("gas_resistance", reading.gas_resistance),
+    ];
+    for (metric, value) in converted {
+        if let Some(value) = value {
+            values.push((metric.into(), Variant::DoubleValue(widen(value))));
+        }
+    }
+    for (label, state) in &reading.inputs {
+        values.push((format!("input_{}", label), Variant::BooleanValue(*state)));
+    }
+    for (label, pulses) in &reading.pulses {
+        values.push((
+            format!("{}_total", label),
+            Variant::DoubleValue(pulses.total),
+        ));
+        values.push((
+            format!("{}_rate", label),
+            Variant::DoubleValue(widen(pulses.rate)),
+        ));
+    }
+    values
+}
+
+/// The reading as requests of at most `MAX_ENTRIES` entries, `None` without a
+/// timestamp as SiteWise needs one for every value.
+pub fn batches(reading: &SensorData, sitewise: &SiteWise) -> Option<Vec<BatchPut>> {
+    let timestamp = Timestamp {
+        time_in_seconds: reading.timestamp?,
+        offset_in_nanos: 0,
+    };
+    let quality = if reading.clock_suspect {
+        Quality::Uncertain
+    } else {
+        Quality::Good
+    };
+
+    let entries: Vec<Entry> = values(reading)
+        .into_iter()
+        .map(|(metric, value)| Entry {
+            entry_id: entry_id(&metric),
+            property_alias: sitewise.alias(&metric),
+            property_values: alloc::vec![PropertyValue {
+                value,
+                timestamp,
+                quality,
+            }],
+        })
+        .collect();
+
+    Some(
+        entries
+            .chunks(MAX_ENTRIES)
+            .map(|entries| BatchPut {
+                entries: entries.to_vec(),
+            })
+            .collect(),
+    )
+}
+
+// The f64 closest to the decimal the f32 prints as, 21.48 rather than the
+// 21.479999542236328 a cast gives
+fn widen(value: f32) -> f64 {
+    format!("{}", value).parse().unwrap_or(value as f64)
+}
+
+// Entry IDs are limited to letters, digits, `_` and `-`, up to 64 characters
+fn entry_id(metric: &str) -> String {
+    metric
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse::PulseReading;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    fn reading() -> SensorData {
+        SensorData {
+            gas_resistance: None,
+            timestamp: Some(1_735_689_600),
+            ..SensorData::sample()
+        }
+    }
+
+    fn sitewise() -> SiteWise {
+        SiteWise {
+            enabled: true,
+            alias_prefix: "/acme/plant-1/boiler-room/".into(),
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn entries_in_the_request_shape() {
+        let batches = batches(&reading(), &sitewise()).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&batches[0]).unwrap()["entries"][0],
+            json!({
+                "entryId": "temperature",
+                "propertyAlias": "/acme/plant-1/boiler-room/temperature",
+                "propertyValues": [{
+                    "value": { "doubleValue": 21.5 },
+                    "timestamp": { "timeInSeconds": 1_735_689_600u64, "offsetInNanos": 0 },
+                    "quality": "GOOD",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn aliases_replace_the_prefixed_name() {
+        let mut sitewise = sitewise();
+        sitewise.aliases.push(Alias {
+            metric: "pressure".into(),
+            alias: "/acme/shared/barometer".into(),
+        });
+        assert_eq!(sitewise.alias("pressure"), "/acme/shared/barometer");
+        assert_eq!(
+            sitewise.alias("humidity"),
+            "/acme/plant-1/boiler-room/humidity"
+        );
+    }
+
+    #[test]
+    fn no_timestamp_no_entries() {
+        let reading = SensorData {
+            timestamp: None,
+            ..reading()
+        };
+        assert_eq!(batches(&reading, &sitewise()), None);
+    }
+
+    #[test]
+    fn suspect_clock_is_uncertain() {
+        let reading = SensorData {
+            clock_suspect: true,
+            ..reading()
+        };
+        let batches = batches(&reading, &sitewise()).unwrap();
+        assert_eq!(
+            batches[0].entries[0].property_values[0].quality,
+            Quality::Uncertain
+        );
+    }
+
+    #[test]
+    fn inputs_and_pulses() {
+        let mut reading = reading();
+        reading.inputs.insert("door 1".into(), true);
+        reading.pulses.insert(
+            "water".into(),
+            PulseReading {
+                count: 1200,
+                total: 1.2,
+                rate: 0.3,
+                unit: "m3".into(),
+            },
+        );
+        let batches = batches(&reading, &sitewise()).unwrap();
+        let ids: Vec<_> = batches[0]
+            .entries
+            .iter()
+            .map(|entry| entry.entry_id.as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "temperature",
+                "humidity",
+                "pressure",
+                "input_door_1",
+                "water_total",
+                "water_rate"
+            ]
+        );
+        assert_eq!(
+            batches[0].entries[3].property_values[0].value,
+            Variant::BooleanValue(true)
+        );
+    }
+
+    #[test]
+    fn values_keep_the_decimals_of_the_reading() {
+        let reading = SensorData {
+            temperature: Some(21.48),
+            ..reading()
+        };
+        assert_eq!(
+            values(&reading)[0],
+            ("temperature".into(), Variant::DoubleValue(21.48))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn widening_keeps_the_value(value in any::<f32>().prop_filter("finite", |value| value.is_finite())) {
+            prop_assert_eq!(widen(value) as f32, value);
+        }
+
+        #[test]
+        fn batches_respect_the_entry_limit(labels in proptest::collection::btree_set("[a-z]{1,8}", 0..30)) {
+            let mut reading = reading();
+            for label in labels {
+                reading.inputs.insert(label, false);
+            }
+            let count = values(&reading).len();
+            let batches = batches(&reading, &sitewise()).unwrap();
+            prop_assert!(batches.iter().all(|batch| batch.entries.len() <= MAX_ENTRIES));
+            prop_assert_eq!(batches.iter().map(|batch| batch.entries.len()).sum::<usize>(), count);
+        }
+    }
+}
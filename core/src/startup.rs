@@ -0,0 +1,123 @@
+//! Random waits before the network comes up, so devices powered back on
+//! together after an outage do not all hit the access point and the broker at
+//! the same moment.
+
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Startup {
+    /// Longest wait before Wi-Fi comes up, 0 starts it right away
+    pub wifi_max_delay_s: u32,
+    /// Longest wait between Wi-Fi being up and connecting to MQTT
+    pub mqtt_max_delay_s: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Wifi,
+    Mqtt,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Wifi => "wifi",
+            Stage::Mqtt => "mqtt",
+        }
+    }
+}
+
+/// Waits of one device, derived from its ID so they stay the same across
+/// boots and spread a fleet evenly over the configured maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jitter {
+    seed: u64,
+}
+
+impl Jitter {
+    pub fn new(device_id: &[u8]) -> Self {
+        // FNV-1a
+        let seed = device_id
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        Jitter { seed }
+    }
+
+    /// Wait before `stage`, at most `max_delay_s`.
+    pub fn delay(&self, stage: Stage, max_delay_s: u32) -> Duration {
+        if max_delay_s == 0 {
+            return Duration::ZERO;
+        }
+        let max_ms = max_delay_s as u64 * 1000;
+        Duration::from_millis(splitmix64(self.seed ^ stage as u64) % (max_ms + 1))
+    }
+}
+
+// Spreads nearby seeds, such as MAC addresses of one batch, over the full range
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn no_wait_by_default() {
+        let startup = Startup::default();
+        let jitter = Jitter::new(&[0x24, 0x0a, 0xc4, 0x12, 0x34, 0x56]);
+        assert_eq!(
+            jitter.delay(Stage::Wifi, startup.wifi_max_delay_s),
+            Duration::ZERO
+        );
+        assert_eq!(
+            jitter.delay(Stage::Mqtt, startup.mqtt_max_delay_s),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn same_device_same_delay() {
+        let mac = [0x24, 0x0a, 0xc4, 0x12, 0x34, 0x56];
+        assert_eq!(
+            Jitter::new(&mac).delay(Stage::Wifi, 60),
+            Jitter::new(&mac).delay(Stage::Wifi, 60)
+        );
+        assert_ne!(
+            Jitter::new(&mac).delay(Stage::Wifi, 60),
+            Jitter::new(&mac).delay(Stage::Mqtt, 60)
+        );
+    }
+
+    #[test]
+    fn batch_spreads_over_the_window() {
+        // MAC addresses of one batch differ in the last byte only
+        let mut buckets = [0u32; 10];
+        for last in 0..=255u8 {
+            let jitter = Jitter::new(&[0x24, 0x0a, 0xc4, 0x12, 0x34, last]);
+            let delay = jitter.delay(Stage::Wifi, 100);
+            buckets[(delay.as_secs() as usize).min(99) / 10] += 1;
+        }
+        for count in buckets {
+            assert!((10..=45).contains(&count), "{:?}", buckets);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn delay_stays_within_the_maximum(id in any::<[u8; 6]>(), max_delay_s in any::<u32>()) {
+            let jitter = Jitter::new(&id);
+            for stage in [Stage::Wifi, Stage::Mqtt] {
+                prop_assert!(jitter.delay(stage, max_delay_s) <= Duration::from_secs(max_delay_s as u64));
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use alloc::{collections::BTreeMap, string::String};
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Local time of day the daily summary is published at.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SummarySchedule {
+    pub enabled: bool,
+    pub hour: u8,
+    pub minute: u8,
+    /// Offset of local time to UTC, there is no time zone database on the device
+    pub utc_offset_min: i16,
+}
+
+impl Default for SummarySchedule {
+    fn default() -> Self {
+        SummarySchedule {
+            enabled: true,
+            hour: 0,
+            minute: 0,
+            utc_offset_min: 0,
+        }
+    }
+}
+
+impl SummarySchedule {
+    /// Unix time of the first scheduled run after `now`.
+    pub fn next_run(&self, now: u64) -> u64 {
+        let local = (self.hour.min(23) as i64 * 60 + self.minute.min(59) as i64) * 60;
+        let utc = (local - self.utc_offset_min as i64 * 60).rem_euclid(SECONDS_PER_DAY);
+
+        let now = now as i64;
+        let next = now - now.rem_euclid(SECONDS_PER_DAY) + utc;
+        if next > now {
+            next as u64
+        } else {
+            (next + SECONDS_PER_DAY) as u64
+        }
+    }
+}
+
+/// Fires once a day at the scheduled time, the first time on the day after the
+/// clock became valid rather than right away. Stays due until [`DailyTrigger::mark`]
+/// is called, so a run that failed is tried again.
+#[derive(Debug, Clone, Default)]
+pub struct DailyTrigger {
+    // Schedule the next run was computed from, and that run
+    next: Option<(SummarySchedule, u64)>,
+}
+
+impl DailyTrigger {
+    pub fn is_due(&mut self, schedule: &SummarySchedule, now: u64) -> bool {
+        if !schedule.enabled {
+            self.next = None;
+            return false;
+        }
+
+        match self.next {
+            Some((scheduled, next)) if scheduled == *schedule => now >= next,
+            _ => {
+                self.next = Some((*schedule, schedule.next_run(now)));
+                false
+            }
+        }
+    }
+
+    /// Records the run done at `now` and schedules the next one.
+    pub fn mark(&mut self, now: u64) {
+        // Runs missed while offline are not repeated
+        if let Some((schedule, _)) = self.next {
+            self.next = Some((schedule, schedule.next_run(now)));
+        }
+    }
+}
+
+/// Min, max and mean of one metric over the summary period.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct MetricSummary {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+    pub samples: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+    min: f32,
+    max: f32,
+    sum: f64,
+    samples: u32,
+}
+
+impl Accumulator {
+    const fn new() -> Self {
+        Accumulator {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            samples: 0,
+        }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as f64;
+        self.samples += 1;
+    }
+
+    fn summary(&self) -> Option<MetricSummary> {
+        (self.samples > 0).then(|| MetricSummary {
+            min: self.min,
+            max: self.max,
+            avg: (self.sum / self.samples as f64) as f32,
+            samples: self.samples,
+        })
+    }
+}
+
+/// Published on `<pub_topic>/summary` once a day.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DailySummary {
+    pub period_start: u64,
+    pub period_end: u64,
+    pub uptime_s: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<MetricSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<MetricSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<MetricSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_resistance: Option<MetricSummary>,
+    pub publishes: u32,
+    pub failed_publishes: u32,
+    /// Share of the readings that were handed to the transport
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_success_rate: Option<f32>,
+    /// Alerts raised by kind
+    pub alerts: BTreeMap<String, u32>,
+}
+
+/// What happened since the previous summary.
+#[derive(Debug, Clone)]
+pub struct DailyStats {
+    // Unix time of the previous summary, `None` since boot
+    since: Option<u64>,
+    temperature: Accumulator,
+    humidity: Accumulator,
+    pressure: Accumulator,
+    gas_resistance: Accumulator,
+    publishes: u32,
+    failed_publishes: u32,
+    alerts: BTreeMap<String, u32>,
+}
+
+impl Default for DailyStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DailyStats {
+    pub const fn new() -> Self {
+        DailyStats {
+            since: None,
+            temperature: Accumulator::new(),
+            humidity: Accumulator::new(),
+            pressure: Accumulator::new(),
+            gas_resistance: Accumulator::new(),
+            publishes: 0,
+            failed_publishes: 0,
+            alerts: BTreeMap::new(),
+        }
+    }
+
+    pub fn record_reading(
+        &mut self,
+        temperature: f32,
+        humidity: f32,
+        pressure: f32,
+        gas_resistance: Option<f32>,
+    ) {
+        self.temperature.add(temperature);
+        self.humidity.add(humidity);
+        self.pressure.add(pressure);
+        if let Some(gas_resistance) = gas_resistance {
+            self.gas_resistance.add(gas_resistance);
+        }
+    }
+
+    pub fn record_publish(&mut self, ok: bool) {
+        self.publishes += 1;
+        if !ok {
+            self.failed_publishes += 1;
+        }
+    }
+
+    pub fn record_alert(&mut self, kind: &str) {
+        *self.alerts.entry(kind.into()).or_insert(0) += 1;
+    }
+
+    /// The summary up to `now`, the period starts at boot for the first one.
+    pub fn summary(&self, now: u64, uptime_s: u64) -> DailySummary {
+        let succeeded = self.publishes - self.failed_publishes;
+
+        DailySummary {
+            period_start: self.since.unwrap_or(now.saturating_sub(uptime_s)),
+            period_end: now,
+            uptime_s,
+            temperature: self.temperature.summary(),
+            humidity: self.humidity.summary(),
+            pressure: self.pressure.summary(),
+            gas_resistance: self.gas_resistance.summary(),
+            publishes: self.publishes,
+            failed_publishes: self.failed_publishes,
+            publish_success_rate: (self.publishes > 0)
+                .then(|| succeeded as f32 / self.publishes as f32),
+            alerts: self.alerts.clone(),
+        }
+    }
+
+    /// Starts the next period at `now`, after its summary went out.
+    pub fn reset(&mut self, now: u64) {
+        *self = DailyStats {
+            since: Some(now),
+            ..DailyStats::new()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // 2025-01-01 00:00 UTC
+    const NEW_YEAR: u64 = 1_735_689_600;
+
+    fn at(hour: u8, minute: u8, utc_offset_min: i16) -> SummarySchedule {
+        SummarySchedule {
+            enabled: true,
+            hour,
+            minute,
+            utc_offset_min,
+        }
+    }
+
+    #[test]
+    fn next_run_is_later_today_or_tomorrow() {
+        let schedule = at(6, 30, 0);
+        assert_eq!(schedule.next_run(NEW_YEAR), NEW_YEAR + 6 * 3600 + 1800);
+        assert_eq!(
+            schedule.next_run(NEW_YEAR + 6 * 3600 + 1800),
+            NEW_YEAR + 86400 + 6 * 3600 + 1800
+        );
+    }
+
+    #[test]
+    fn local_time_is_shifted_to_utc() {
+        // Midnight in UTC+7 is 17:00 UTC the day before
+        assert_eq!(at(0, 0, 420).next_run(NEW_YEAR), NEW_YEAR + 17 * 3600);
+        // Midnight in UTC-5 is 05:00 UTC
+        assert_eq!(at(0, 0, -300).next_run(NEW_YEAR), NEW_YEAR + 5 * 3600);
+    }
+
+    #[test]
+    fn trigger_waits_for_the_first_scheduled_time() {
+        let schedule = at(6, 0, 0);
+        let mut trigger = DailyTrigger::default();
+
+        assert!(!trigger.is_due(&schedule, NEW_YEAR + 7 * 3600));
+        assert!(!trigger.is_due(&schedule, NEW_YEAR + 86400 + 5 * 3600));
+        assert!(trigger.is_due(&schedule, NEW_YEAR + 86400 + 6 * 3600));
+        trigger.mark(NEW_YEAR + 86400 + 6 * 3600);
+        assert!(!trigger.is_due(&schedule, NEW_YEAR + 86400 + 6 * 3600 + 5));
+        assert!(trigger.is_due(&schedule, NEW_YEAR + 2 * 86400 + 6 * 3600));
+    }
+
+    #[test]
+    fn trigger_stays_due_until_marked() {
+        let schedule = at(6, 0, 0);
+        let mut trigger = DailyTrigger::default();
+        assert!(!trigger.is_due(&schedule, NEW_YEAR));
+
+        assert!(trigger.is_due(&schedule, NEW_YEAR + 6 * 3600));
+        assert!(trigger.is_due(&schedule, NEW_YEAR + 6 * 3600 + 60));
+        trigger.mark(NEW_YEAR + 6 * 3600 + 60);
+        assert!(!trigger.is_due(&schedule, NEW_YEAR + 6 * 3600 + 120));
+        assert!(trigger.is_due(&schedule, NEW_YEAR + 86400 + 6 * 3600));
+    }
+
+    #[test]
+    fn changed_schedule_restarts_trigger() {
+        let mut trigger = DailyTrigger::default();
+        assert!(!trigger.is_due(&at(6, 0, 0), NEW_YEAR));
+        assert!(!trigger.is_due(&at(1, 0, 0), NEW_YEAR + 2 * 3600));
+        assert!(trigger.is_due(&at(1, 0, 0), NEW_YEAR + 86400 + 3600));
+        trigger.mark(NEW_YEAR + 86400 + 3600);
+
+        let disabled = SummarySchedule {
+            enabled: false,
+            ..at(1, 0, 0)
+        };
+        assert!(!trigger.is_due(&disabled, NEW_YEAR + 2 * 86400 + 3600));
+    }
+
+    #[test]
+    fn summary_of_readings_and_publishes() {
+        let mut stats = DailyStats::new();
+        stats.record_reading(20.0, 40.0, 1010.0, Some(100_000.0));
+        stats.record_reading(24.0, 50.0, 1012.0, None);
+        stats.record_publish(true);
+        stats.record_publish(true);
+        stats.record_publish(true);
+        stats.record_publish(false);
+        stats.record_alert("cert_expiry");
+        stats.record_alert("clock_drift");
+        stats.record_alert("clock_drift");
+
+        let summary = stats.summary(NEW_YEAR, 3600);
+        assert_eq!(summary.period_start, NEW_YEAR - 3600);
+        assert_eq!(
+            summary.temperature,
+            Some(MetricSummary {
+                min: 20.0,
+                max: 24.0,
+                avg: 22.0,
+                samples: 2,
+            })
+        );
+        assert_eq!(summary.gas_resistance.unwrap().samples, 1);
+        assert_eq!(summary.publish_success_rate, Some(0.75));
+        assert_eq!(summary.alerts["clock_drift"], 2);
+    }
+
+    #[test]
+    fn reset_starts_the_next_period() {
+        let mut stats = DailyStats::new();
+        stats.record_reading(20.0, 40.0, 1010.0, Some(100_000.0));
+        stats.record_publish(false);
+        stats.reset(NEW_YEAR);
+
+        let summary = stats.summary(NEW_YEAR + 86400, 2 * 86400);
+        assert_eq!(summary.period_start, NEW_YEAR);
+        assert_eq!(summary.temperature, None);
+        assert_eq!(summary.publish_success_rate, None);
+
+        let value = serde_json::to_value(&summary).unwrap();
+        assert!(value.get("temperature").is_none());
+        assert_eq!(value["publishes"], 0);
+    }
+
+    proptest! {
+        #[test]
+        fn next_run_is_within_a_day(
+            now in NEW_YEAR..NEW_YEAR + 365 * 86400,
+            hour in 0u8..30,
+            minute in 0u8..70,
+            utc_offset_min in -720i16..=840,
+        ) {
+            let next = at(hour, minute, utc_offset_min).next_run(now);
+            prop_assert!(next > now);
+            prop_assert!(next <= now + 86400);
+        }
+
+        #[test]
+        fn mean_lies_between_min_and_max(values in proptest::collection::vec(-40f32..85.0, 1..50)) {
+            let mut stats = DailyStats::new();
+            for &value in &values {
+                stats.record_reading(value, value, value, Some(value));
+            }
+            let temperature = stats.summary(NEW_YEAR, 0).temperature.unwrap();
+            prop_assert!(temperature.min <= temperature.avg + 1e-3);
+            prop_assert!(temperature.avg <= temperature.max + 1e-3);
+            prop_assert_eq!(temperature.samples as usize, values.len());
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use alloc::{collections::BTreeMap, string::String};
+use serde::Serialize;
+
+use crate::{pulse::PulseReading, raw::RawValues, trend::Trend};
+
+/// A reading as published on the telemetry topic.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SensorData {
+    /// In °C. The converted values are unset when only raw values are
+    /// reported or the sensor could not be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// In %
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f32>,
+    /// In hPa
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f32>,
+    /// In Ω, unset while the gas heater is off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_resistance: Option<f32>,
+    /// ADC counts behind the converted values, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawValues>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Set while the clock drifts too fast for `timestamp` to be trusted
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    pub clock_suspect: bool,
+    /// Present when the reading is the trimmed mean of a burst of samples
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<BurstStats>,
+    /// Change per hour over the trend window, once there is enough history
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trend: Option<Trend>,
+    /// States of the digital inputs by label
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, bool>,
+    /// Counts and rates of the pulse counters by label
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub pulses: BTreeMap<String, PulseReading>,
+}
+
+/// Spread (max - min) per metric of the samples averaged into a reading.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct BurstStats {
+    pub samples: usize,
+    pub temperature_spread: f32,
+    pub humidity_spread: f32,
+    pub pressure_spread: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_resistance_spread: Option<f32>,
+}
+
+#[cfg(test)]
+impl SensorData {
+    /// A converted reading with everything optional left out, for the tests
+    /// to start from.
+    pub(crate) fn sample() -> Self {
+        SensorData {
+            temperature: Some(21.5),
+            humidity: Some(40.25),
+            pressure: Some(1013.25),
+            gas_resistance: Some(120_000.0),
+            raw: None,
+            timestamp: None,
+            clock_suspect: false,
+            burst: None,
+            trend: None,
+            inputs: BTreeMap::new(),
+            pulses: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_fields_are_omitted() {
+        assert_eq!(
+            serde_json::to_string(&SensorData::sample()).unwrap(),
+            r#"{"temperature":21.5,"humidity":40.25,"pressure":1013.25,"gas_resistance":120000.0}"#
+        );
+    }
+
+    #[test]
+    fn optional_fields_are_included_when_set() {
+        let data = SensorData {
+            timestamp: Some(1_735_689_600),
+            clock_suspect: true,
+            burst: Some(BurstStats {
+                samples: 4,
+                temperature_spread: 0.5,
+                humidity_spread: 1.0,
+                pressure_spread: 0.25,
+                gas_resistance_spread: Some(100.0),
+            }),
+            trend: Some(Trend {
+                temperature: 0.5,
+                humidity: -2.0,
+                pressure: -1.25,
+                span_s: 1800,
+            }),
+            ..SensorData::sample()
+        };
+        let value = serde_json::to_value(&data).unwrap();
+
+        assert_eq!(value["timestamp"], 1_735_689_600);
+        assert_eq!(value["clock_suspect"], true);
+        assert_eq!(value["burst"]["samples"], 4);
+        assert_eq!(value["burst"]["temperature_spread"], 0.5);
+        assert_eq!(value["trend"]["pressure"], -1.25);
+    }
+
+    #[test]
+    fn gas_resistance_is_omitted_without_heater() {
+        let data = SensorData {
+            gas_resistance: None,
+            burst: Some(BurstStats {
+                samples: 2,
+                temperature_spread: 0.5,
+                humidity_spread: 1.0,
+                pressure_spread: 0.25,
+                gas_resistance_spread: None,
+            }),
+            ..SensorData::sample()
+        };
+        let value = serde_json::to_value(&data).unwrap();
+
+        assert!(value.get("gas_resistance").is_none());
+        assert!(value["burst"].get("gas_resistance_spread").is_none());
+    }
+
+    #[test]
+    fn raw_values_instead_of_converted() {
+        let data = SensorData {
+            temperature: None,
+            humidity: None,
+            pressure: None,
+            gas_resistance: None,
+            raw: Some(RawValues {
+                temperature_adc: 516_550,
+                pressure_adc: 324_519,
+                humidity_adc: 23_585,
+                gas_adc: Some(717),
+                gas_range: Some(4),
+            }),
+            ..SensorData::sample()
+        };
+
+        assert_eq!(
+            serde_json::to_string(&data).unwrap(),
+            r#"{"raw":{"temperature_adc":516550,"pressure_adc":324519,"humidity_adc":23585,"gas_adc":717,"gas_range":4}}"#
+        );
+    }
+
+    #[test]
+    fn inputs_by_label() {
+        let data = SensorData {
+            inputs: BTreeMap::from([("door".into(), true), ("float".into(), false)]),
+            ..SensorData::sample()
+        };
+        let value = serde_json::to_value(&data).unwrap();
+
+        assert_eq!(value["inputs"]["door"], true);
+        assert_eq!(value["inputs"]["float"], false);
+    }
+
+    #[test]
+    fn pulses_by_label() {
+        let reading = PulseReading {
+            count: 1500,
+            total: 1.5,
+            rate: 0.25,
+            unit: "kWh".into(),
+        };
+        let data = SensorData {
+            pulses: BTreeMap::from([("energy".into(), reading)]),
+            ..SensorData::sample()
+        };
+        let value = serde_json::to_value(&data).unwrap();
+
+        assert_eq!(value["pulses"]["energy"]["count"], 1500);
+        assert_eq!(value["pulses"]["energy"]["total"], 1.5);
+        assert_eq!(value["pulses"]["energy"]["unit"], "kWh");
+    }
+}
@@ -0,0 +1,86 @@
+// Drift rate above which the local clock is not trusted between syncs
+pub const MAX_DRIFT_PPM: i64 = 100;
+
+/// Whether a reading lies within the BME680 operating range.
+pub fn is_plausible(temperature_celsius: f32, humidity_percent: f32, pressure_hpa: f32) -> bool {
+    (-40.0..=85.0).contains(&temperature_celsius)
+        && (0.0..=100.0).contains(&humidity_percent)
+        && (300.0..=1100.0).contains(&pressure_hpa)
+}
+
+/// Drift rate in parts per million of the elapsed time.
+pub fn drift_ppm(drift_ms: i64, elapsed_ms: i64) -> i64 {
+    if elapsed_ms > 0 {
+        drift_ms * 1_000_000 / elapsed_ms
+    } else {
+        0
+    }
+}
+
+pub fn is_drift_suspect(drift_ppm: i64) -> bool {
+    drift_ppm.abs() > MAX_DRIFT_PPM
+}
+
+/// Whether a certificate with `days_remaining` is within the warning window.
+pub fn cert_expiry_due(days_remaining: i64, warn_days: u32) -> bool {
+    days_remaining <= warn_days as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typical_indoor_reading_is_plausible() {
+        assert!(is_plausible(21.5, 45.0, 1013.0));
+    }
+
+    #[test]
+    fn range_limits_are_plausible() {
+        assert!(is_plausible(-40.0, 0.0, 300.0));
+        assert!(is_plausible(85.0, 100.0, 1100.0));
+    }
+
+    #[test]
+    fn out_of_range_readings_are_not_plausible() {
+        assert!(!is_plausible(-41.0, 45.0, 1013.0));
+        assert!(!is_plausible(21.5, 100.5, 1013.0));
+        assert!(!is_plausible(21.5, 45.0, 0.0));
+        assert!(!is_plausible(f32::NAN, 45.0, 1013.0));
+    }
+
+    #[test]
+    fn drift_is_scaled_to_ppm() {
+        // 36 ms over an hour
+        assert_eq!(drift_ppm(36, 3_600_000), 10);
+        assert_eq!(drift_ppm(-360, 3_600_000), -100);
+    }
+
+    #[test]
+    fn drift_without_elapsed_time_is_zero() {
+        assert_eq!(drift_ppm(500, 0), 0);
+        assert_eq!(drift_ppm(500, -1), 0);
+    }
+
+    #[test]
+    fn drift_above_limit_is_suspect() {
+        assert!(!is_drift_suspect(MAX_DRIFT_PPM));
+        assert!(!is_drift_suspect(-MAX_DRIFT_PPM));
+        assert!(is_drift_suspect(MAX_DRIFT_PPM + 1));
+        assert!(is_drift_suspect(-MAX_DRIFT_PPM - 1));
+    }
+
+    #[test]
+    fn cert_expiry_warns_within_window() {
+        assert!(!cert_expiry_due(31, 30));
+        assert!(cert_expiry_due(30, 30));
+        assert!(cert_expiry_due(0, 30));
+        assert!(cert_expiry_due(-5, 30));
+    }
+
+    #[test]
+    fn cert_expiry_with_zero_window_warns_on_last_day() {
+        assert!(!cert_expiry_due(1, 0));
+        assert!(cert_expiry_due(0, 0));
+    }
+}
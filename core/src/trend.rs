@@ -0,0 +1,217 @@
+use alloc::collections::VecDeque;
+use core::time::Duration;
+use serde::Serialize;
+
+/// Samples kept per window, readings coming in faster are thinned out.
+pub const MAX_SAMPLES: usize = 64;
+// Fewer samples, or a shorter span of the window, give too noisy a slope
+const MIN_SAMPLES: usize = 3;
+
+const SECONDS_PER_HOUR: f32 = 3600.0;
+
+/// Least squares slope of each metric over the trend window, per hour.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Trend {
+    pub temperature: f32,
+    pub humidity: f32,
+    pub pressure: f32,
+    /// Time between the oldest and the newest sample the slopes are based on
+    pub span_s: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Duration,
+    values: [f32; 3],
+}
+
+/// Recent readings of temperature, humidity and pressure for their trends.
+#[derive(Debug, Clone)]
+pub struct TrendTracker {
+    window: Duration,
+    samples: VecDeque<Sample>,
+}
+
+impl TrendTracker {
+    /// A zero `window` disables the trends.
+    pub fn new(window: Duration) -> Self {
+        TrendTracker {
+            window,
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+        }
+    }
+
+    /// Changes the window, the samples taken so far are dropped when it changes.
+    pub fn set_window(&mut self, window: Duration) {
+        if window != self.window {
+            self.window = window;
+            self.samples.clear();
+        }
+    }
+
+    pub fn add(&mut self, now: Duration, temperature: f32, humidity: f32, pressure: f32) {
+        if self.window.is_zero() {
+            return;
+        }
+
+        while let Some(oldest) = self.samples.front() {
+            if now.saturating_sub(oldest.at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        let spacing = self.window / MAX_SAMPLES as u32;
+        if let Some(newest) = self.samples.back() {
+            if now.saturating_sub(newest.at) < spacing {
+                return;
+            }
+        }
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            at: now,
+            values: [temperature, humidity, pressure],
+        });
+    }
+
+    /// The trends once the samples cover at least half the window.
+    pub fn trend(&self) -> Option<Trend> {
+        let (oldest, newest) = (self.samples.front()?, self.samples.back()?);
+        let span = newest.at - oldest.at;
+        if self.samples.len() < MIN_SAMPLES || span < self.window / 2 {
+            return None;
+        }
+
+        let slope = |metric: usize| {
+            let points = self.samples.iter().map(|sample| {
+                let t = (sample.at - oldest.at).as_secs_f32();
+                (t, sample.values[metric])
+            });
+            slope(points, self.samples.len()) * SECONDS_PER_HOUR
+        };
+
+        Some(Trend {
+            temperature: slope(0),
+            humidity: slope(1),
+            pressure: slope(2),
+            span_s: span.as_secs() as u32,
+        })
+    }
+}
+
+/// Least squares slope of `n` points, zero when they all share one time.
+fn slope(points: impl Iterator<Item = (f32, f32)> + Clone, n: usize) -> f32 {
+    let n = n as f32;
+    let mean_t = points.clone().map(|(t, _)| t).sum::<f32>() / n;
+    let mean_v = points.clone().map(|(_, v)| v).sum::<f32>() / n;
+
+    let (covariance, variance) = points.fold((0.0, 0.0), |(cov, var), (t, v)| {
+        let dt = t - mean_t;
+        (cov + dt * (v - mean_v), var + dt * dt)
+    });
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn linear_change_gives_its_slope() {
+        let mut tracker = TrendTracker::new(secs(1800));
+        // Falling 2 hPa and rising 1 °C per hour
+        for minute in 0..=30 {
+            let t = minute as f32 / 60.0;
+            tracker.add(secs(minute * 60), 20.0 + t, 50.0, 1013.0 - 2.0 * t);
+        }
+
+        let trend = tracker.trend().unwrap();
+        assert!((trend.temperature - 1.0).abs() < 1e-3);
+        assert!(trend.humidity.abs() < 1e-3);
+        assert!((trend.pressure + 2.0).abs() < 1e-2);
+        assert_eq!(trend.span_s, 1800);
+    }
+
+    #[test]
+    fn no_trend_before_half_the_window() {
+        let mut tracker = TrendTracker::new(secs(1800));
+        for minute in 0..10 {
+            tracker.add(secs(minute * 60), 20.0, 50.0, 1013.0);
+        }
+        assert_eq!(tracker.trend(), None);
+
+        for minute in 10..=15 {
+            tracker.add(secs(minute * 60), 20.0, 50.0, 1013.0);
+        }
+        assert!(tracker.trend().is_some());
+    }
+
+    #[test]
+    fn zero_window_disables_trends() {
+        let mut tracker = TrendTracker::new(Duration::ZERO);
+        for minute in 0..10 {
+            tracker.add(secs(minute * 60), 20.0, 50.0, 1013.0);
+        }
+        assert_eq!(tracker.trend(), None);
+    }
+
+    #[test]
+    fn changed_window_starts_over() {
+        let mut tracker = TrendTracker::new(secs(600));
+        for minute in 0..=10 {
+            tracker.add(secs(minute * 60), 20.0, 50.0, 1013.0);
+        }
+        assert!(tracker.trend().is_some());
+
+        tracker.set_window(secs(1200));
+        assert_eq!(tracker.trend(), None);
+    }
+
+    #[test]
+    fn old_samples_leave_the_window() {
+        let mut tracker = TrendTracker::new(secs(600));
+        // A jump long ago must not show in the trend of a steady last ten minutes
+        tracker.add(secs(0), 0.0, 0.0, 0.0);
+        for minute in 60..=70 {
+            tracker.add(secs(minute * 60), 20.0, 50.0, 1013.0);
+        }
+        assert_eq!(tracker.trend().unwrap().temperature, 0.0);
+    }
+
+    proptest! {
+        #[test]
+        fn samples_stay_bounded(steps in proptest::collection::vec(0u64..5000, 0..500), window in 1u64..20_000) {
+            let mut tracker = TrendTracker::new(Duration::from_millis(window * 100));
+            let mut now = Duration::ZERO;
+            for step in steps {
+                now += Duration::from_millis(step);
+                tracker.add(now, 20.0, 50.0, 1013.0);
+                prop_assert!(tracker.samples.len() <= MAX_SAMPLES);
+            }
+        }
+
+        #[test]
+        fn constant_readings_have_no_trend(steps in proptest::collection::vec(1u64..120, 3..200), value in -40f32..85.0) {
+            let mut tracker = TrendTracker::new(secs(600));
+            let mut now = Duration::ZERO;
+            for step in steps {
+                now += secs(step);
+                tracker.add(now, value, value, value);
+            }
+            if let Some(trend) = tracker.trend() {
+                prop_assert!(trend.temperature.abs() < 1e-2);
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use serde_json::Value;
+
+/// Desired settings received from the shadow or twin.
+#[derive(Debug, PartialEq)]
+pub struct DesiredUpdate {
+    /// `None` when the document has no desired state
+    pub state: Option<Value>,
+    /// Version of the document, increasing with every change in the cloud
+    pub version: Option<u64>,
+    /// The whole desired document, rather than the part one change touched
+    pub complete: bool,
+}
+
+impl DesiredUpdate {
+    /// Whether the update is older than `applied`, the version the settings in
+    /// effect came from.
+    ///
+    /// Changes can arrive out of order around a reconnect, and an older one would
+    /// undo a newer one. Complete documents are always taken, they are what the
+    /// cloud holds now, even when the versions started over on a recreated shadow.
+    pub fn is_stale(&self, applied: Option<u64>) -> bool {
+        match (self.complete, self.version, applied) {
+            (false, Some(version), Some(applied)) => version <= applied,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn change(version: Option<u64>) -> DesiredUpdate {
+        DesiredUpdate {
+            state: Some(Value::Null),
+            version,
+            complete: false,
+        }
+    }
+
+    #[test]
+    fn newer_change_is_applied() {
+        assert!(!change(Some(5)).is_stale(Some(4)));
+    }
+
+    #[test]
+    fn older_or_repeated_change_is_stale() {
+        assert!(change(Some(3)).is_stale(Some(4)));
+        assert!(change(Some(4)).is_stale(Some(4)));
+    }
+
+    #[test]
+    fn unversioned_changes_are_applied() {
+        assert!(!change(None).is_stale(Some(4)));
+        assert!(!change(Some(1)).is_stale(None));
+    }
+
+    proptest! {
+        #[test]
+        fn complete_document_is_never_stale(version: Option<u64>, applied: Option<u64>) {
+            let update = DesiredUpdate { state: None, version, complete: true };
+            prop_assert!(!update.is_stale(applied));
+        }
+    }
+}
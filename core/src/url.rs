@@ -0,0 +1,173 @@
+//! The parts of `scheme://host[:port][/path]` URLs the firmware connects to,
+//! the MQTT broker and the CoAP gateway. IPv6 hosts are written as `[address]`.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlError {
+    MissingScheme,
+    MissingHost,
+    InvalidPort,
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UrlError::MissingScheme => write!(f, "URL without a scheme"),
+            UrlError::MissingHost => write!(f, "URL without a host"),
+            UrlError::InvalidPort => write!(f, "Invalid port in URL"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UrlError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Url<'a> {
+    pub scheme: &'a str,
+    /// Without the brackets of an IPv6 address
+    pub host: &'a str,
+    /// `None` leaves the default of the scheme
+    pub port: Option<u16>,
+    /// Everything after the authority, without the leading `/`
+    pub path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    pub fn parse(url: &'a str) -> Result<Self, UrlError> {
+        let (scheme, rest) = url.split_once("://").ok_or(UrlError::MissingScheme)?;
+        if scheme.is_empty() {
+            return Err(UrlError::MissingScheme);
+        }
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+        // IPv6 addresses are bracketed, their colons are not the port separator
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed.split_once(']').ok_or(UrlError::MissingHost)?;
+                match rest {
+                    "" => (host, None),
+                    _ => (
+                        host,
+                        Some(rest.strip_prefix(':').ok_or(UrlError::InvalidPort)?),
+                    ),
+                }
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => Some(port.parse().map_err(|_| UrlError::InvalidPort)?),
+            None => None,
+        };
+        if host.is_empty() {
+            return Err(UrlError::MissingHost);
+        }
+
+        Ok(Url {
+            scheme,
+            host,
+            port,
+            path,
+        })
+    }
+
+    /// The non-empty segments of the path.
+    pub fn segments(&self) -> impl Iterator<Item = &'a str> {
+        self.path.split('/').filter(|segment| !segment.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, vec::Vec};
+    use proptest::prelude::*;
+
+    #[test]
+    fn broker_url() {
+        assert_eq!(
+            Url::parse("mqtts://a1b2c3-ats.iot.eu-west-1.amazonaws.com:8883"),
+            Ok(Url {
+                scheme: "mqtts",
+                host: "a1b2c3-ats.iot.eu-west-1.amazonaws.com",
+                port: Some(8883),
+                path: "",
+            })
+        );
+    }
+
+    #[test]
+    fn gateway_url_with_path() {
+        let url = Url::parse("coaps://192.168.1.10/telemetry//node-1/").unwrap();
+        assert_eq!(url.host, "192.168.1.10");
+        assert_eq!(url.port, None);
+        assert_eq!(url.segments().collect::<Vec<_>>(), ["telemetry", "node-1"]);
+    }
+
+    #[test]
+    fn ipv6_hosts() {
+        let url = Url::parse("coaps://[fe80::1]/x").unwrap();
+        assert_eq!(url.host, "fe80::1");
+        assert_eq!(url.port, None);
+        assert_eq!(url.path, "x");
+
+        let url = Url::parse("mqtts://[2001:db8::10]:8883").unwrap();
+        assert_eq!(url.host, "2001:db8::10");
+        assert_eq!(url.port, Some(8883));
+
+        assert_eq!(Url::parse("coaps://[fe80::1/x"), Err(UrlError::MissingHost));
+        assert_eq!(Url::parse("coaps://[]:5684"), Err(UrlError::MissingHost));
+        assert_eq!(
+            Url::parse("coaps://[fe80::1]5684"),
+            Err(UrlError::InvalidPort)
+        );
+        assert_eq!(Url::parse("coaps://[fe80::1]:"), Err(UrlError::InvalidPort));
+    }
+
+    #[test]
+    fn malformed_urls() {
+        assert_eq!(Url::parse("broker:8883"), Err(UrlError::MissingScheme));
+        assert_eq!(Url::parse("://broker"), Err(UrlError::MissingScheme));
+        assert_eq!(Url::parse("mqtts://:8883"), Err(UrlError::MissingHost));
+        assert_eq!(Url::parse("mqtts:///path"), Err(UrlError::MissingHost));
+        assert_eq!(Url::parse("mqtts://broker:"), Err(UrlError::InvalidPort));
+        assert_eq!(
+            Url::parse("mqtts://broker:88830"),
+            Err(UrlError::InvalidPort)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn parts_are_read_back(
+            scheme in "[a-z]{1,8}",
+            host in "[a-z0-9.-]{1,32}",
+            port in proptest::option::of(any::<u16>()),
+            path in "[a-z0-9/]{0,32}",
+        ) {
+            let port_part = port.map(|port| format!(":{}", port)).unwrap_or_default();
+            let url = format!("{}://{}{}/{}", scheme, host, port_part, path);
+            prop_assert_eq!(Url::parse(&url), Ok(Url { scheme: &scheme, host: &host, port, path: &path }));
+        }
+
+        #[test]
+        fn ipv6_parts_are_read_back(
+            groups in proptest::collection::vec("[0-9a-f]{1,4}", 2..8),
+            port in proptest::option::of(any::<u16>()),
+        ) {
+            let host = groups.join(":");
+            let port_part = port.map(|port| format!(":{}", port)).unwrap_or_default();
+            let url = format!("coaps://[{}]{}/telemetry", host, port_part);
+            prop_assert_eq!(Url::parse(&url), Ok(Url { scheme: "coaps", host: &host, port, path: "telemetry" }));
+        }
+
+        #[test]
+        fn parse_never_panics(url in "\\PC{0,64}") {
+            let _ = Url::parse(&url);
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use alloc::{collections::BTreeMap, string::String};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::pulse::{PulseCounter, PulseReading, MAX_FILTER_NS};
+
+/// Labels of the station's pulse counters, next to the ones of `pulse_counters`.
+pub const RAIN_LABEL: &str = "rain";
+pub const WIND_LABEL: &str = "wind";
+
+// Vane resistance per direction from north clockwise, common to the reed switch
+// vanes of the Davis, SparkFun and Misol kits
+const VANE_OHMS: [u32; 16] = [
+    33_000, 6_570, 8_200, 891, 1_000, 688, 2_200, 1_410, 3_900, 3_140, 16_000, 14_120, 120_000,
+    42_120, 64_900, 21_880,
+];
+const COMPASS_POINTS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+const SECONDS_PER_HOUR: f64 = 3600.0;
+
+/// Tipping bucket rain gauge, cup anemometer and wind vane of an outdoor station,
+/// configured through the shadow. The default pins are free on the devkit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WeatherStation {
+    pub enabled: bool,
+    /// Reed switch of the tipping bucket
+    pub rain_pin: u8,
+    pub rain_mm_per_tip: f64,
+    /// Reed switch of the anemometer
+    pub anemometer_pin: u8,
+    /// Wind speed at one switch closure per second
+    pub wind_kmh_per_hz: f64,
+    /// Read the wind direction from a vane on `vane_pin`
+    pub vane: bool,
+    /// ADC1 pin the vane divides the supply on
+    pub vane_pin: u8,
+    /// Resistor between the supply and the vane
+    pub vane_pullup_ohm: u32,
+}
+
+impl Default for WeatherStation {
+    fn default() -> Self {
+        // Calibration of the SparkFun and Misol kits
+        WeatherStation {
+            enabled: false,
+            rain_pin: 25,
+            rain_mm_per_tip: 0.2794,
+            anemometer_pin: 26,
+            wind_kmh_per_hz: 2.4,
+            vane: true,
+            vane_pin: 35,
+            vane_pullup_ohm: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// Only ADC1 can be read while Wi-Fi is on
+    NotAdcPin(u8),
+    /// The vane shares a pin with one of the switches
+    PinInUse(u8),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::NotAdcPin(pin) => write!(f, "GPIO {} is not an ADC1 pin", pin),
+            ConfigError::PinInUse(pin) => write!(f, "GPIO {} configured twice", pin),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConfigError {}
+
+impl WeatherStation {
+    /// Checks the vane pin, the switches are checked with the other pulse counters.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.vane {
+            return Ok(());
+        }
+        let pin = self.vane_pin;
+        if !(32..=39).contains(&pin) {
+            return Err(ConfigError::NotAdcPin(pin));
+        }
+        if pin == self.rain_pin || pin == self.anemometer_pin {
+            return Err(ConfigError::PinInUse(pin));
+        }
+        Ok(())
+    }
+
+    /// Pulse counters of the rain gauge, totalling mm, and of the anemometer,
+    /// totalling the wind run in km so that its rate is the wind speed.
+    pub fn counters(&self) -> [PulseCounter; 2] {
+        [
+            PulseCounter {
+                pin: self.rain_pin,
+                label: RAIN_LABEL.into(),
+                pulses_per_unit: 1.0 / self.rain_mm_per_tip,
+                unit: "mm".into(),
+                filter_ns: MAX_FILTER_NS,
+            },
+            PulseCounter {
+                pin: self.anemometer_pin,
+                label: WIND_LABEL.into(),
+                pulses_per_unit: SECONDS_PER_HOUR / self.wind_kmh_per_hz,
+                unit: "km".into(),
+                filter_ns: MAX_FILTER_NS,
+            },
+        ]
+    }
+
+    /// Takes the readings of the station's counters out of `pulses` and combines
+    /// them with the air measurements, if the BME680 delivered any, into the
+    /// weather payload.
+    pub fn report(
+        &self,
+        pulses: &mut BTreeMap<String, PulseReading>,
+        air: Option<Air>,
+        vane_mv: Option<(u16, u16)>,
+        timestamp: Option<u64>,
+    ) -> WeatherReport {
+        let rain = pulses.remove(RAIN_LABEL);
+        let wind = pulses.remove(WIND_LABEL);
+        let direction =
+            vane_mv.and_then(|(mv, supply_mv)| wind_direction(mv, supply_mv, self.vane_pullup_ohm));
+
+        WeatherReport {
+            timestamp,
+            temperature_c: air.map(|air| air.temperature_c),
+            humidity_pct: air.map(|air| air.humidity_pct),
+            pressure_hpa: air.map(|air| air.pressure_hpa),
+            wind_speed_kmh: wind.as_ref().map_or(0.0, |wind| wind.rate),
+            wind_direction_deg: direction,
+            wind_direction: direction.map(compass_point),
+            rain_rate_mm_h: rain.as_ref().map_or(0.0, |rain| rain.rate),
+            rain_total_mm: rain.as_ref().map_or(0.0, |rain| rain.total),
+        }
+    }
+}
+
+/// Measurements of the BME680 that go into the weather payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Air {
+    pub temperature_c: f32,
+    pub humidity_pct: f32,
+    pub pressure_hpa: f32,
+}
+
+/// Published on `<pub_topic>/weather` with every reading of a weather station.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WeatherReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Left out while the BME680 is missing or fails to read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature_c: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity_pct: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure_hpa: Option<f32>,
+    /// Mean since the previous report
+    pub wind_speed_kmh: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_direction_deg: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_direction: Option<&'static str>,
+    /// Mean since the previous report
+    pub rain_rate_mm_h: f32,
+    /// Rain since the gauge was first configured
+    pub rain_total_mm: f64,
+}
+
+/// Direction the vane points to in degrees, from the voltage it divides `supply_mv`
+/// down to. `None` when the vane is disconnected or shorted.
+pub fn wind_direction(mv: u16, supply_mv: u16, pullup_ohm: u32) -> Option<f32> {
+    if supply_mv == 0 {
+        return None;
+    }
+    let ratio = mv as f32 / supply_mv as f32;
+    // Beyond the highest and lowest positions with some margin
+    if !(0.02..=0.97).contains(&ratio) {
+        return None;
+    }
+
+    // No float `abs` without std
+    let distance = |ohm: u32| {
+        let expected = ohm as f32 / (ohm + pullup_ohm) as f32;
+        if expected > ratio {
+            expected - ratio
+        } else {
+            ratio - expected
+        }
+    };
+    let (position, _) = VANE_OHMS
+        .iter()
+        .enumerate()
+        .map(|(i, &ohm)| (i, distance(ohm)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    Some(position as f32 * 22.5)
+}
+
+/// The closest of the 16 compass points to `degrees`.
+pub fn compass_point(degrees: f32) -> &'static str {
+    let degrees = degrees % 360.0;
+    let degrees = if degrees < 0.0 {
+        degrees + 360.0
+    } else {
+        degrees
+    };
+    let sector = (degrees / 22.5 + 0.5) as usize % 16;
+    COMPASS_POINTS[sector]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station() -> WeatherStation {
+        serde_json::from_str(r#"{"enabled": true}"#).unwrap()
+    }
+
+    fn supply_divided(ohm: u32) -> u16 {
+        (3300 * ohm / (ohm + 10_000)) as u16
+    }
+
+    #[test]
+    fn defaults_of_common_kits() {
+        let station = station();
+        assert_eq!((station.rain_pin, station.anemometer_pin), (25, 26));
+        assert_eq!((station.vane, station.vane_pin), (true, 35));
+        assert_eq!(station.rain_mm_per_tip, 0.2794);
+        assert_eq!(station.wind_kmh_per_hz, 2.4);
+        assert_eq!(station.vane_pullup_ohm, 10_000);
+        assert_eq!(station.validate(), Ok(()));
+    }
+
+    #[test]
+    fn vane_needs_a_free_adc1_pin() {
+        let station = WeatherStation {
+            vane_pin: 4,
+            ..self::station()
+        };
+        assert_eq!(station.validate(), Err(ConfigError::NotAdcPin(4)));
+
+        let station = WeatherStation {
+            vane_pin: 32,
+            anemometer_pin: 32,
+            ..self::station()
+        };
+        assert_eq!(station.validate(), Err(ConfigError::PinInUse(32)));
+
+        let station = WeatherStation {
+            vane: false,
+            vane_pin: 4,
+            ..self::station()
+        };
+        assert_eq!(station.validate(), Ok(()));
+    }
+
+    #[test]
+    fn counter_rates_are_rain_rate_and_wind_speed() {
+        let [rain, wind] = station().counters();
+        assert_eq!((rain.label.as_str(), rain.pin), (RAIN_LABEL, 25));
+        assert_eq!((wind.label.as_str(), wind.pin), (WIND_LABEL, 26));
+
+        // 10 tips in an hour
+        let reading = rain.reading(10, 10, 3_600_000);
+        assert!((reading.rate - 2.794).abs() < 1e-3);
+        assert!((reading.total - 2.794).abs() < 1e-3);
+
+        // 5 Hz for 10 s
+        let reading = wind.reading(50, 50, 10_000);
+        assert!((reading.rate - 12.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn every_vane_position_is_found() {
+        for (i, &ohm) in VANE_OHMS.iter().enumerate() {
+            assert_eq!(
+                wind_direction(supply_divided(ohm), 3300, 10_000),
+                Some(i as f32 * 22.5)
+            );
+        }
+    }
+
+    #[test]
+    fn disconnected_vane_has_no_direction() {
+        assert_eq!(wind_direction(3300, 3300, 10_000), None);
+        assert_eq!(wind_direction(0, 3300, 10_000), None);
+        assert_eq!(wind_direction(1000, 0, 10_000), None);
+    }
+
+    #[test]
+    fn compass_points() {
+        assert_eq!(compass_point(0.0), "N");
+        assert_eq!(compass_point(22.5), "NNE");
+        assert_eq!(compass_point(350.0), "N");
+        assert_eq!(compass_point(270.0), "W");
+        assert_eq!(compass_point(-90.0), "W");
+    }
+
+    #[test]
+    fn report_takes_the_station_counters() {
+        let station = station();
+        let [rain, wind] = station.counters();
+        let mut pulses = BTreeMap::from([
+            (RAIN_LABEL.into(), rain.reading(100, 10, 3_600_000)),
+            (WIND_LABEL.into(), wind.reading(500, 50, 10_000)),
+            ("water".into(), PulseCounter::reading(&rain, 1, 1, 1)),
+        ]);
+        let air = Air {
+            temperature_c: 12.5,
+            humidity_pct: 80.0,
+            pressure_hpa: 1002.0,
+        };
+
+        let report = station.report(
+            &mut pulses,
+            Some(air),
+            Some((supply_divided(1_000), 3300)),
+            None,
+        );
+        assert_eq!(pulses.keys().collect::<alloc::vec::Vec<_>>(), ["water"]);
+        assert!((report.wind_speed_kmh - 12.0).abs() < 1e-3);
+        assert!((report.rain_total_mm - 27.94).abs() < 1e-3);
+        assert_eq!(report.wind_direction_deg, Some(90.0));
+        assert_eq!(report.wind_direction, Some("E"));
+        assert_eq!(report.temperature_c, Some(12.5));
+    }
+
+    #[test]
+    fn report_without_vane_omits_direction() {
+        let air = Air {
+            temperature_c: 12.5,
+            humidity_pct: 80.0,
+            pressure_hpa: 1002.0,
+        };
+        let report = station().report(&mut BTreeMap::new(), Some(air), None, Some(1_735_689_600));
+        let value = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(value["timestamp"], 1_735_689_600);
+        assert_eq!(value["wind_speed_kmh"], 0.0);
+        assert!(value.get("wind_direction").is_none());
+    }
+    #[test]
+    fn report_without_air_keeps_wind_and_rain() {
+        let station = station();
+        let [rain, _] = station.counters();
+        let mut pulses = BTreeMap::from([(RAIN_LABEL.into(), rain.reading(100, 10, 3_600_000))]);
+
+        let report = station.report(&mut pulses, None, None, None);
+        let value = serde_json::to_value(&report).unwrap();
+        assert!(pulses.is_empty());
+        assert!(value.get("temperature_c").is_none());
+        assert!(value.get("pressure_hpa").is_none());
+        assert!((report.rain_total_mm - 27.94).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use esp32_aws_core::outbox::Priority;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{clock, journal, publisher, structs::Config, summary};
+
+#[derive(Serialize, Debug)]
+pub struct Alert {
+    pub kind: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl Alert {
+    pub fn new(kind: &'static str, message: String) -> Self {
+        Alert {
+            kind,
+            message,
+            timestamp: clock::unix_now(),
+        }
+    }
+}
+
+/// Publishes an alert to `<pub_topic>/alerts`.
+pub fn raise(client: &mut EspMqttClient<'static>, config: &Config, alert: Alert) -> Result<()> {
+    warn!("Alert {}: {}", alert.kind, alert.message);
+    summary::record_alert(alert.kind);
+    journal::record(
+        "alert",
+        json!({ "kind": alert.kind, "message": alert.message }),
+    );
+
+    publisher::publish(
+        client,
+        Priority::Alert,
+        &config.topic("alerts"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(&alert)?.as_bytes(),
+    )?;
+    Ok(())
+}
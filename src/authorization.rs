@@ -0,0 +1,70 @@
+use anyhow::Result;
+use esp32_aws_core::authorization::{authorize, NonceLog, Rejected, Validity};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::{error, warn};
+
+use crate::clock;
+
+const NVS_NAMESPACE: &str = "nonces";
+const LOG_KEY: &str = "log";
+
+/// Nonces of the commands that ran, kept in NVS so a retained command does not
+/// run again after a reboot.
+pub struct NonceStore {
+    nvs: Option<EspNvs<NvsDefault>>,
+    log: NonceLog,
+}
+
+impl NonceStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+        let log = match read(&nvs) {
+            Ok(log) => log,
+            Err(e) => {
+                warn!("Ignoring stored nonces: {:?}", e);
+                NonceLog::new()
+            }
+        };
+
+        Ok(NonceStore {
+            nvs: Some(nvs),
+            log,
+        })
+    }
+
+    /// Without NVS nonces are only remembered until the next reboot.
+    pub fn in_memory() -> Self {
+        NonceStore {
+            nvs: None,
+            log: NonceLog::new(),
+        }
+    }
+
+    /// Whether a command received over MQTT may run, records its nonce if so.
+    pub fn check(&mut self, validity: &Validity, window_s: u32) -> Result<(), Rejected> {
+        authorize(validity, window_s, clock::unix_now(), &mut self.log)?;
+        if let (Some(nvs), Some(_)) = (self.nvs.as_mut(), &validity.nonce) {
+            if let Err(e) = store(nvs, &self.log) {
+                error!("Failed to store nonces: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read(nvs: &EspNvs<NvsDefault>) -> Result<NonceLog> {
+    let Some(len) = nvs.blob_len(LOG_KEY)? else {
+        return Ok(NonceLog::new());
+    };
+    let mut buf = vec![0; len];
+    let Some(data) = nvs.get_blob(LOG_KEY, &mut buf)? else {
+        return Ok(NonceLog::new());
+    };
+
+    Ok(serde_json::from_slice(data)?)
+}
+
+fn store(nvs: &mut EspNvs<NvsDefault>, log: &NonceLog) -> Result<()> {
+    nvs.set_blob(LOG_KEY, &serde_json::to_vec(log)?)?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use esp32_aws_core::readiness::{Component, Readiness, State};
+use log::{error, info, warn};
+
+/// The subsystems in the order `main` brings them up, with what each needs first.
+const COMPONENTS: &[Component] = &[
+    Component::new("nvs", &[]),
+    Component::new("journal", &["nvs"]),
+    Component::new("credentials", &["nvs"]),
+    Component::new("settings", &["nvs"]),
+    Component::new("inputs", &[]),
+    Component::new("pulses", &["nvs"]),
+    Component::new("sensor_bus", &[]),
+    Component::new("console", &[]),
+    Component::new("wifi", &["nvs"]),
+    Component::new("sntp", &["wifi"]),
+    // Ready once the time is synced, until then readings carry no timestamp
+    Component::new("clock", &["sntp"]),
+    Component::new("dns_cache", &["nvs"]),
+    Component::new("energy", &["nvs"]),
+    Component::new("nonces", &["nvs"]),
+    Component::new("mqtt", &["wifi", "credentials"]),
+];
+
+/// Brings up the subsystems in dependency order and tracks which are ready.
+pub struct Boot {
+    readiness: Readiness,
+}
+
+impl Boot {
+    pub fn new() -> Result<Self> {
+        Ok(Boot {
+            readiness: Readiness::new(COMPONENTS)?,
+        })
+    }
+
+    /// Starts a component the firmware cannot run without, startup ends when it fails.
+    pub fn require<T>(
+        &mut self,
+        name: &'static str,
+        start: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        self.readiness
+            .gate(name)
+            .map_err(|dependency| anyhow!("Cannot start {}, {} is not ready", name, dependency))?;
+
+        match start() {
+            Ok(value) => {
+                self.readiness.set(name, State::Ready);
+                Ok(value)
+            }
+            Err(e) => {
+                self.readiness.set(name, State::Failed);
+                Err(e.context(format!("Failed to start {}", name)))
+            }
+        }
+    }
+
+    /// Starts an optional component, the firmware goes on without it when it
+    /// fails or what it depends on is not ready.
+    pub fn start<T>(&mut self, name: &'static str, start: impl FnOnce() -> Result<T>) -> Option<T> {
+        if let Err(dependency) = self.readiness.gate(name) {
+            warn!("Not starting {}, {} is not ready", name, dependency);
+            return None;
+        }
+
+        match start() {
+            Ok(value) => {
+                self.readiness.set(name, State::Ready);
+                Some(value)
+            }
+            Err(e) => {
+                self.readiness.set(name, State::Failed);
+                error!("Continuing without {}: {:?}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Marks a component ready that only becomes so after startup.
+    pub fn mark_ready(&mut self, name: &'static str) {
+        if self.readiness.gate(name).is_ok() {
+            info!("{} ready", name);
+            self.readiness.set(name, State::Ready);
+        }
+    }
+
+    /// Marks a component failed that stopped working after startup.
+    pub fn mark_failed(&mut self, name: &'static str) {
+        if self.readiness.is_ready(name) {
+            warn!("{} failed", name);
+            self.readiness.set(name, State::Failed);
+        }
+    }
+
+    pub fn is_ready(&self, name: &str) -> bool {
+        self.readiness.is_ready(name)
+    }
+
+    pub fn readiness(&self) -> &Readiness {
+        &self.readiness
+    }
+}
@@ -0,0 +1,47 @@
+use esp_idf_svc::sys::esp_get_idf_version;
+use log::info;
+use serde::Serialize;
+use std::ffi::CStr;
+
+/// Identifies the exact build running on the device, embedded by `build.rs`.
+#[derive(Serialize, Debug, Clone)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_timestamp: u64,
+    pub idf_version: String,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn get() -> Self {
+        let idf_version = unsafe { CStr::from_ptr(esp_get_idf_version()) };
+
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("BUILD_GIT_COMMIT"),
+            build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+            idf_version: idf_version.to_string_lossy().into_owned(),
+            features: env!("BUILD_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn log_banner(&self) {
+        info!("==============================");
+        info!(
+            "{} {} ({})",
+            env!("CARGO_PKG_NAME"),
+            self.version,
+            self.git_commit
+        );
+        info!(
+            "Built at {}, ESP-IDF {}",
+            self.build_timestamp, self.idf_version
+        );
+        info!("Features: [{}]", self.features.join(", "));
+        info!("==============================");
+    }
+}
@@ -0,0 +1,48 @@
+use bme680::FieldData;
+use esp32_aws_core::{
+    aggregate::{trimmed, Trimmed},
+    telemetry::BurstStats,
+};
+
+/// Upper bound for the samples of one burst, each forced measurement heats the gas plate
+pub const MAX_BURST_SAMPLES: u8 = 8;
+
+pub struct Summary {
+    pub temperature: Trimmed,
+    pub humidity: Trimmed,
+    pub pressure: Trimmed,
+    /// Over the samples with a valid gas measurement, none while the heater is off
+    pub gas_resistance: Option<Trimmed>,
+    pub stats: BurstStats,
+}
+
+pub fn summarize(samples: &[FieldData]) -> Summary {
+    let metric = |value: fn(&FieldData) -> f32| {
+        let mut values: Vec<f32> = samples.iter().map(value).collect();
+        trimmed(&mut values)
+    };
+
+    let temperature = metric(FieldData::temperature_celsius);
+    let humidity = metric(FieldData::humidity_percent);
+    let pressure = metric(FieldData::pressure_hpa);
+    let mut gas: Vec<f32> = samples
+        .iter()
+        .filter(|data| data.gas_valid())
+        .map(|data| data.gas_resistance_ohm() as f32)
+        .collect();
+    let gas_resistance = (!gas.is_empty()).then(|| trimmed(&mut gas));
+
+    Summary {
+        temperature,
+        humidity,
+        pressure,
+        gas_resistance,
+        stats: BurstStats {
+            samples: samples.len(),
+            temperature_spread: temperature.spread,
+            humidity_spread: humidity.spread,
+            pressure_spread: pressure.spread,
+            gas_resistance_spread: gas_resistance.map(|gas| gas.spread),
+        },
+    }
+}
@@ -0,0 +1,130 @@
+use esp32_aws_core::threshold::{drift_ppm, is_drift_suspect};
+use esp_idf_svc::sys::{sntp_get_sync_interval, sntp_restart, sntp_set_sync_interval};
+use log::info;
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// 2024-01-01, anything earlier means the clock has not been set since boot
+const MIN_VALID_TIME: u64 = 1_704_067_200;
+
+const NORMAL_SYNC_INTERVAL_MS: u32 = 60 * 60 * 1000;
+const FAST_SYNC_INTERVAL_MS: u32 = 10 * 60 * 1000;
+
+/// Drift of the local clock as measured on the last SNTP syncs.
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct ClockStatus {
+    pub last_drift_ms: Option<i64>,
+    pub drift_ppm: Option<i64>,
+    /// Set while the drift exceeds `MAX_DRIFT_PPM`, until a sync shows it back in range
+    pub suspect: bool,
+    /// Unix time of the last sync that found the clock in range
+    pub last_good_sync: Option<u64>,
+}
+
+/// Reported once when a sync finds the clock drifting too fast.
+#[derive(Debug, Clone, Copy)]
+pub struct DriftEvent {
+    pub drift_ms: i64,
+    pub drift_ppm: i64,
+    /// Timestamps published after this (Unix time) are skewed by up to `drift_ms`
+    pub since: Option<u64>,
+}
+
+struct SyncPoint {
+    instant: Instant,
+    unix_ms: i64,
+}
+
+struct ClockState {
+    last_sync: Option<SyncPoint>,
+    status: ClockStatus,
+    event: Option<DriftEvent>,
+}
+
+static STATE: Mutex<ClockState> = Mutex::new(ClockState {
+    last_sync: None,
+    status: ClockStatus {
+        last_drift_ms: None,
+        drift_ppm: None,
+        suspect: false,
+        last_good_sync: None,
+    },
+    event: None,
+});
+
+/// Current Unix time in seconds, or `None` while the clock is not set.
+pub fn unix_now() -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (now >= MIN_VALID_TIME).then_some(now)
+}
+
+/// SNTP sync callback, compares the synced time with what the local clock
+/// expected based on the time elapsed since the previous sync.
+pub fn on_sync(synced: Duration) {
+    let now = Instant::now();
+    let unix_ms = synced.as_millis() as i64;
+    let mut state = STATE.lock().unwrap();
+
+    if let Some(last) = &state.last_sync {
+        let elapsed_ms = now.duration_since(last.instant).as_millis() as i64;
+        let drift_ms = unix_ms - (last.unix_ms + elapsed_ms);
+        let drift_ppm = drift_ppm(drift_ms, elapsed_ms);
+
+        info!(
+            "Clock drift {} ms over {} s ({} ppm)",
+            drift_ms,
+            elapsed_ms / 1000,
+            drift_ppm
+        );
+
+        let suspect = is_drift_suspect(drift_ppm);
+        if suspect {
+            state.event = Some(DriftEvent {
+                drift_ms,
+                drift_ppm,
+                since: state.status.last_good_sync,
+            });
+        }
+
+        state.status.last_drift_ms = Some(drift_ms);
+        state.status.drift_ppm = Some(drift_ppm);
+        state.status.suspect = suspect;
+    }
+
+    if !state.status.suspect {
+        state.status.last_good_sync = Some(synced.as_secs());
+    }
+    state.last_sync = Some(SyncPoint {
+        instant: now,
+        unix_ms,
+    });
+}
+
+pub fn status() -> ClockStatus {
+    STATE.lock().unwrap().status
+}
+
+/// Takes the pending drift event, if the last sync produced one.
+pub fn take_drift_event() -> Option<DriftEvent> {
+    STATE.lock().unwrap().event.take()
+}
+
+/// Syncs more often while the clock is suspect, back to normal once it is in range.
+pub fn update_sync_interval() {
+    let interval = if status().suspect {
+        FAST_SYNC_INTERVAL_MS
+    } else {
+        NORMAL_SYNC_INTERVAL_MS
+    };
+
+    if unsafe { sntp_get_sync_interval() } != interval {
+        info!("Setting SNTP sync interval to {} s", interval / 1000);
+        unsafe {
+            sntp_set_sync_interval(interval);
+            sntp_restart();
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use anyhow::Result;
+use esp32_aws_core::cloud::Kind;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+
+const NVS_NAMESPACE: &str = "cloud";
+const BACKEND_KEY: &str = "backend";
+
+/// The cloud backend stored in NVS, AWS IoT unless one was chosen.
+pub fn kind(partition: &EspDefaultNvsPartition) -> Result<Kind> {
+    let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+    Ok(nvs
+        .get_u8(BACKEND_KEY)?
+        .and_then(Kind::from_u8)
+        .unwrap_or_default())
+}
+
+/// Stores the backend, it takes effect on the next boot.
+pub fn set_kind(partition: &EspDefaultNvsPartition, kind: Kind) -> Result<()> {
+    let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+    nvs.set_u8(BACKEND_KEY, kind as u8)?;
+    Ok(())
+}
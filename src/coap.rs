@@ -0,0 +1,221 @@
+use anyhow::{anyhow, bail, Result};
+use esp32_aws_core::{
+    backoff::Backoff,
+    coap::{encode_post, parse_response, Response},
+    url::Url,
+};
+use esp_idf_svc::sys::esp_timer_get_time;
+use log::{error, info, warn};
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, SyncSender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    codec,
+    dtls::{DtlsSession, Psk},
+    summary,
+};
+
+const DEFAULT_PORT: u16 = 5683;
+const DEFAULT_SECURE_PORT: u16 = 5684;
+
+// The DTLS handshake runs on this stack
+const COAP_STACK_SIZE: usize = 10 * 1024;
+// Readings waiting for the gateway, further ones are dropped
+const QUEUE_LEN: usize = 4;
+
+// Retransmission parameters from RFC 7252 section 4.8, without the random factor
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RETRANSMIT: u32 = 4;
+
+/// A reading for the gateway at `url`.
+pub struct Push {
+    pub url: String,
+    pub payload: Vec<u8>,
+}
+
+/// Starts the thread pushing readings to the gateway, so waiting for
+/// acknowledgements does not hold up the main loop. Each push counts in the
+/// daily summary once it was acknowledged or given up on.
+pub fn spawn(psk: Option<Psk>) -> Result<SyncSender<Push>> {
+    let (pushes, queue) = mpsc::sync_channel::<Push>(QUEUE_LEN);
+
+    thread::Builder::new()
+        .stack_size(COAP_STACK_SIZE)
+        .spawn(move || {
+            let mut client = None;
+
+            for push in queue {
+                let pushed = post(&mut client, &push, psk.as_ref());
+                summary::record_publish(pushed.is_ok());
+                match pushed {
+                    Ok(_) => info!("Successfully pushed sensor data to the CoAP gateway"),
+                    Err(e) => {
+                        error!("Failed to push sensor data over CoAP: {:?}", e);
+                        // The next reading opens the client again, with a new DTLS session
+                        client = None;
+                    }
+                }
+            }
+        })?;
+
+    Ok(pushes)
+}
+
+/// Posts a reading, opening the client again when the URL changed.
+fn post(client: &mut Option<CoapClient>, push: &Push, psk: Option<&Psk>) -> Result<()> {
+    if !matches!(client, Some(client) if client.url() == push.url) {
+        *client = Some(CoapClient::new(&push.url, psk)?);
+    }
+
+    match client {
+        Some(client) => client.post(&push.payload),
+        None => Ok(()),
+    }
+}
+
+/// Pushes payloads to a CoAP gateway as confirmable POST requests, over DTLS
+/// with a pre-shared key for `coaps://` URLs.
+pub struct CoapClient {
+    link: Link,
+    url: String,
+    path: Vec<String>,
+    message_id: u16,
+}
+
+impl CoapClient {
+    /// Opens a client for a `coap://host[:port]/path` or `coaps://` URL, the
+    /// latter running the DTLS handshake with `psk`.
+    pub fn new(url: &str, psk: Option<&Psk>) -> Result<Self> {
+        let (gateway, path, secure) = parse_url(url)?;
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(gateway)?;
+        let link = if secure {
+            let psk = psk.ok_or_else(|| anyhow!("No coap_psk provisioned for {}", url))?;
+            Link::Dtls(DtlsSession::connect(socket, psk)?)
+        } else {
+            Link::Plain(socket)
+        };
+        info!("CoAP gateway {} at {}", url, gateway);
+
+        Ok(CoapClient {
+            link,
+            url: url.into(),
+            path,
+            // Boot time in µs varies with the network bring-up, so IDs differ across reboots
+            message_id: unsafe { esp_timer_get_time() } as u16,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// POSTs the payload and waits for the gateway to acknowledge it,
+    /// retransmitting with exponential back-off.
+    pub fn post(&mut self, payload: &[u8]) -> Result<()> {
+        self.message_id = self.message_id.wrapping_add(1);
+        let path: Vec<&str> = self.path.iter().map(String::as_str).collect();
+        let request = encode_post(self.message_id, &path, codec::CONTENT_FORMAT, payload);
+
+        let timeouts = Backoff::exponential(ACK_TIMEOUT, 2, Duration::MAX, MAX_RETRANSMIT + 1);
+        for (attempt, timeout) in timeouts.enumerate() {
+            if attempt > 0 {
+                warn!("No CoAP acknowledgement, retransmitting ({})", attempt);
+            }
+            self.link.send(&request)?;
+
+            match self.wait_response(timeout)? {
+                Some(Response::Delivered) => return Ok(()),
+                Some(Response::Rejected { class, detail }) => {
+                    bail!("CoAP gateway answered {}.{:02}", class, detail)
+                }
+                Some(Response::Reset) => bail!("CoAP gateway reset the request"),
+                None => {}
+            }
+        }
+
+        bail!(
+            "CoAP gateway did not acknowledge after {} retransmissions",
+            MAX_RETRANSMIT
+        )
+    }
+
+    /// Waits for the response to the current message, `None` on timeout.
+    fn wait_response(&mut self, timeout: Duration) -> Result<Option<Response>> {
+        let mut buf = [0u8; 256];
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            let Some(len) = self.link.recv(&mut buf, left)? else {
+                return Ok(None);
+            };
+
+            if let Some(response) = parse_response(&buf[..len], self.message_id) {
+                return Ok(Some(response));
+            }
+        }
+    }
+}
+
+/// The socket, or the DTLS session on top of it.
+enum Link {
+    Plain(UdpSocket),
+    Dtls(DtlsSession),
+}
+
+impl Link {
+    fn send(&mut self, datagram: &[u8]) -> Result<()> {
+        match self {
+            Link::Plain(socket) => {
+                socket.send(datagram)?;
+                Ok(())
+            }
+            Link::Dtls(session) => session.send(datagram),
+        }
+    }
+
+    /// One datagram, `None` when none arrived within `timeout`.
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>> {
+        match self {
+            Link::Plain(socket) => {
+                socket.set_read_timeout(Some(timeout))?;
+                match socket.recv(buf) {
+                    Ok(len) => Ok(Some(len)),
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        Ok(None)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Link::Dtls(session) => session.recv(buf, timeout),
+        }
+    }
+}
+
+/// The gateway address, the URI path and whether it is a `coaps://` URL.
+fn parse_url(url: &str) -> Result<(SocketAddr, Vec<String>, bool)> {
+    let url = Url::parse(url)?;
+    let (secure, default_port) = match url.scheme {
+        "coap" => (false, DEFAULT_PORT),
+        "coaps" => (true, DEFAULT_SECURE_PORT),
+        scheme => bail!("Unsupported CoAP scheme {}", scheme),
+    };
+
+    let gateway = (url.host, url.port.unwrap_or(default_port))
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("No address for {}", url.host))?;
+    let path = url.segments().map(String::from).collect();
+
+    Ok((gateway, path, secure))
+}
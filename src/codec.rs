@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Result};
+use esp32_aws_core::signing;
+use serde::Serialize;
+
+#[cfg(feature = "lora")]
+use {
+    crate::structs::SensorData,
+    esp32_aws_core::frame::{self, Received, Sequences},
+};
+
+/// CoAP Content-Format number of the encoding below (`application/json`)
+pub const CONTENT_FORMAT: u16 = 50;
+
+/// Encodes a payload the same way for every transport, so a gateway
+/// relaying CoAP readings into the backend sees what MQTT would have sent.
+pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(value)?)
+}
+
+/// Appends the HMAC of `key` to an encoded payload, see `esp32_aws_core::signing`.
+/// Fails without a key rather than sending readings consumers would reject.
+pub fn sign(payload: &[u8], key: Option<&[u8]>) -> Result<Vec<u8>> {
+    let key = key.ok_or_else(|| anyhow!("No signing key provisioned"))?;
+    signing::sign(payload, key).ok_or_else(|| anyhow!("Only JSON objects can be signed"))
+}
+
+/// Compact binary frame of a reading for the LoRa link, see `esp32_aws_core::frame`.
+#[cfg(feature = "lora")]
+pub fn encode_frame(
+    node_id: &str,
+    reading: &SensorData,
+    sequence: u32,
+    node_key: &[u8],
+) -> Result<Vec<u8>> {
+    Ok(frame::encode(node_id, reading, sequence, node_key)?)
+}
+
+/// Decodes a frame a gateway received and checks it with `frame::receive`.
+#[cfg(feature = "lora")]
+pub fn receive_frame(
+    data: &[u8],
+    gateway_key: &[u8],
+    allowed: &[String],
+    sequences: &mut Sequences,
+) -> Result<Received> {
+    Ok(frame::receive(data, gateway_key, allowed, sequences)?)
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use esp32_aws_core::collision::{self, CollisionDetector, SHORT_SESSIONS, WINDOW};
+use esp_idf_svc::{
+    mqtt::client::EspMqttClient,
+    sys::{esp, esp_efuse_mac_get_default, esp_timer_get_time},
+};
+use log::{error, info};
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    alerts::{self, Alert},
+    journal,
+    structs::Config,
+};
+
+static DETECTOR: Mutex<CollisionDetector> = Mutex::new(CollisionDetector::new());
+// Set on the MQTT task, taken by the main loop which can publish the alert
+static SUSPECTED: AtomicBool = AtomicBool::new(false);
+
+fn uptime() -> Duration {
+    Duration::from_micros(unsafe { esp_timer_get_time() } as u64)
+}
+
+/// Called from the MQTT callback when the client connects.
+pub fn record_connected() {
+    DETECTOR.lock().unwrap().connected(uptime());
+}
+
+/// Called from the MQTT callback when the client loses its connection.
+pub fn record_disconnected() {
+    if DETECTOR.lock().unwrap().disconnected(uptime()) {
+        SUSPECTED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Raises an alert when another device seems to use the client ID and, when
+/// `fallback` is set, switches to a suffixed one. True when the client has to
+/// be created again for the new ID to be used.
+pub fn check(client: &mut EspMqttClient<'static>, config: &mut Config, fallback: bool) -> bool {
+    if !SUSPECTED.swap(false, Ordering::Relaxed) {
+        return false;
+    }
+
+    let alert = Alert::new(
+        "client_id_collision",
+        format!(
+            "Disconnected {} times within {} s right after connecting, another device may be using client ID {}",
+            SHORT_SESSIONS,
+            WINDOW.as_secs(),
+            config.client_id
+        ),
+    );
+    if let Err(e) = alerts::raise(client, config, alert) {
+        error!("Failed to raise client ID collision alert: {:?}", e);
+    }
+
+    if !fallback {
+        return false;
+    }
+    let client_id = match fallback_client_id(&config.client_id) {
+        Ok(client_id) if client_id != config.client_id => client_id,
+        Ok(_) => return false,
+        Err(e) => {
+            error!("Failed to derive fallback client ID: {:?}", e);
+            return false;
+        }
+    };
+
+    info!("Falling back to client ID {}", client_id);
+    journal::record(
+        "client_id_fallback",
+        json!({ "from": config.client_id, "to": client_id }),
+    );
+    config.client_id = client_id;
+    true
+}
+
+fn fallback_client_id(client_id: &str) -> Result<String> {
+    let mut mac = [0u8; 6];
+    esp!(unsafe { esp_efuse_mac_get_default(mac.as_mut_ptr()) })?;
+    Ok(collision::fallback_client_id(client_id, &mac))
+}
@@ -0,0 +1,48 @@
+use anyhow::Result;
+use esp_idf_svc::hal::delay::FreeRtos;
+use log::{error, info};
+use std::{
+    io::{self, BufRead},
+    sync::mpsc::Sender,
+    thread,
+};
+
+use esp32_aws_core::command::Command;
+
+const CONSOLE_STACK_SIZE: usize = 4096;
+const CONSOLE_POLL_MS: u32 = 100;
+
+/// Starts a thread reading commands from the UART console.
+pub fn spawn(commands: Sender<Command>) -> Result<()> {
+    thread::Builder::new()
+        .stack_size(CONSOLE_STACK_SIZE)
+        .spawn(move || {
+            let stdin = io::stdin();
+            let mut line = String::new();
+
+            loop {
+                // Without a UART driver installed stdin is non-blocking, so poll it
+                match stdin.lock().read_line(&mut line) {
+                    Ok(_) if line.ends_with('\n') => {
+                        if !line.trim().is_empty() {
+                            match Command::from_console(&line) {
+                                Ok(command) => {
+                                    info!("Console command: {:?}", command);
+                                    if commands.send(command).is_err() {
+                                        return;
+                                    }
+                                }
+                                Err(e) => {
+                                    error!("Invalid console command {:?}: {}", line.trim(), e)
+                                }
+                            }
+                        }
+                        line.clear();
+                    }
+                    _ => FreeRtos::delay_ms(CONSOLE_POLL_MS),
+                }
+            }
+        })?;
+
+    Ok(())
+}
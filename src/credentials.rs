@@ -0,0 +1,96 @@
+use anyhow::Result;
+#[cfg(feature = "encrypted-credentials")]
+use anyhow::{anyhow, bail};
+#[cfg(feature = "encrypted-credentials")]
+use esp_idf_svc::nvs::{EspEncryptedNvsPartition, EspNvs, NvsEncrypted};
+
+#[cfg(feature = "encrypted-credentials")]
+use crate::security::{self, FlashEncryption};
+
+#[cfg(feature = "encrypted-credentials")]
+const PARTITION: &str = "creds";
+#[cfg(feature = "encrypted-credentials")]
+const KEYS_PARTITION: &str = "nvs_keys";
+#[cfg(feature = "encrypted-credentials")]
+const NVS_NAMESPACE: &str = "creds";
+
+/// PEM encoded broker CA, device certificate and private key.
+pub struct Credentials {
+    pub server_cert: &'static [u8],
+    pub client_cert: &'static [u8],
+    pub private_key: &'static [u8],
+    /// Azure IoT Hub shared access key, the device authenticates with SAS tokens when set
+    pub sas_key: Option<&'static [u8]>,
+    /// Key of the HMAC appended to readings when `sign_payloads` is set
+    pub signing_key: Option<&'static [u8]>,
+    /// Identity and pre-shared key of the DTLS session with a `coaps://` gateway
+    pub coap_psk_identity: Option<&'static [u8]>,
+    pub coap_psk: Option<&'static [u8]>,
+    /// On a LoRa node the key its frames are signed with, on a gateway the key
+    /// the node keys are derived from
+    #[cfg(feature = "lora")]
+    pub lora_key: Option<&'static [u8]>,
+}
+
+/// The credentials compiled into the firmware image from `aws/`.
+#[cfg(not(feature = "encrypted-credentials"))]
+pub fn load() -> Result<Credentials> {
+    Ok(Credentials {
+        server_cert: include_bytes!("../aws/AmazonRootCA1.pem"),
+        client_cert: include_bytes!("../aws/device.crt"),
+        private_key: include_bytes!("../aws/private.key"),
+        sas_key: None,
+        signing_key: None,
+        coap_psk_identity: None,
+        coap_psk: None,
+        #[cfg(feature = "lora")]
+        lora_key: None,
+    })
+}
+
+/// The credentials flashed to the encrypted `creds` NVS partition during provisioning.
+///
+/// The image itself carries no secrets. The NVS keys are only protected while flash
+/// encryption is on, so without it the credentials are not read at all.
+#[cfg(feature = "encrypted-credentials")]
+pub fn load() -> Result<Credentials> {
+    if security::flash_encryption() == FlashEncryption::Disabled {
+        bail!("Flash encryption is disabled, refusing to read credentials");
+    }
+
+    let partition = EspEncryptedNvsPartition::take(PARTITION, Some(KEYS_PARTITION))?;
+    let nvs = EspNvs::new(partition, NVS_NAMESPACE, false)?;
+
+    Ok(Credentials {
+        server_cert: read(&nvs, "server_ca")?,
+        client_cert: read(&nvs, "device_crt")?,
+        private_key: read(&nvs, "private_key")?,
+        sas_key: read_optional(&nvs, "sas_key")?,
+        signing_key: read_optional(&nvs, "signing_key")?,
+        coap_psk_identity: read_optional(&nvs, "coap_psk_id")?,
+        coap_psk: read_optional(&nvs, "coap_psk")?,
+        #[cfg(feature = "lora")]
+        lora_key: read_optional(&nvs, "lora_key")?,
+    })
+}
+
+#[cfg(feature = "encrypted-credentials")]
+fn read(nvs: &EspNvs<NvsEncrypted>, key: &str) -> Result<&'static [u8]> {
+    let len = nvs
+        .blob_len(key)?
+        .ok_or_else(|| anyhow!("No {} in the credentials partition", key))?;
+    let mut buf = vec![0; len];
+    nvs.get_blob(key, &mut buf)?;
+
+    // Lives as long as the firmware, like the embedded credentials
+    Ok(Box::leak(buf.into_boxed_slice()))
+}
+
+#[cfg(feature = "encrypted-credentials")]
+fn read_optional(nvs: &EspNvs<NvsEncrypted>, key: &str) -> Result<Option<&'static [u8]>> {
+    if nvs.contains(key)? {
+        Ok(Some(read(nvs, key)?))
+    } else {
+        Ok(None)
+    }
+}
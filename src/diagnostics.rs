@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use esp32_aws_core::{command::Command, diagnostics::Denied, errors::ErrorCode, outbox::Priority};
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    errors::{self, DeviceError},
+    i2c::SharedI2c,
+    journal, publisher,
+    structs::Config,
+};
+
+/// Outcome of a diagnostic command, published to `<pub_topic>/diagnostics`.
+#[derive(Serialize, Debug)]
+struct Report<'a> {
+    command: &'static str,
+    address: u8,
+    register: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a [u8]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn publish(client: &mut EspMqttClient<'static>, config: &Config, report: &Report) -> Result<()> {
+    publisher::publish(
+        client,
+        Priority::Response,
+        &config.topic("diagnostics"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(report)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Records and answers a diagnostic command that was not allowed to run.
+pub fn deny(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    command: &Command,
+    denied: Denied,
+) -> Result<()> {
+    let (command, address, register) = match *command {
+        Command::I2cRead {
+            address, register, ..
+        } => ("i2c_read", address, register),
+        Command::I2cWrite {
+            address, register, ..
+        } => ("i2c_write", address, register),
+        _ => return Ok(()),
+    };
+    journal::record(
+        "diagnostics_denied",
+        json!({ "command": command, "address": address, "register": register, "reason": denied.to_string() }),
+    );
+    let device_error = DeviceError::new(
+        ErrorCode::from(denied),
+        format!("Refused {} of {:#04x}: {}", command, address, denied),
+        json!({ "command": command, "address": address, "register": register }),
+    );
+    errors::report(client, config, device_error);
+
+    publish(
+        client,
+        config,
+        &Report {
+            command,
+            address,
+            register,
+            data: None,
+            error: Some(denied.to_string()),
+        },
+    )
+}
+
+/// Reads `len` registers and publishes their contents.
+pub fn i2c_read(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    bus: &mut SharedI2c,
+    address: u8,
+    register: u8,
+    len: u8,
+) -> Result<()> {
+    warn!(
+        "Diagnostics: reading {} registers of {:#04x} from {:#04x}",
+        len, address, register
+    );
+    journal::record(
+        "i2c_read",
+        json!({ "address": address, "register": register, "len": len }),
+    );
+
+    let mut data = vec![0u8; len as usize];
+    let result = bus
+        .write_read(address, &[register], &mut data)
+        .map_err(|e| anyhow!("{:?}", e));
+    publish(
+        client,
+        config,
+        &Report {
+            command: "i2c_read",
+            address,
+            register,
+            data: result.is_ok().then_some(data.as_slice()),
+            error: result.err().map(|e| e.to_string()),
+        },
+    )
+}
+
+/// Writes `data` to the registers from `register` on. Drivers of the device are
+/// not told, their view of its configuration can be off afterwards.
+pub fn i2c_write(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    bus: &mut SharedI2c,
+    address: u8,
+    register: u8,
+    data: &[u8],
+) -> Result<()> {
+    warn!(
+        "Diagnostics: writing {:02x?} to {:#04x} from {:#04x}",
+        data, address, register
+    );
+    journal::record(
+        "i2c_write",
+        json!({ "address": address, "register": register, "data": data }),
+    );
+
+    let mut bytes = Vec::with_capacity(data.len() + 1);
+    bytes.push(register);
+    bytes.extend_from_slice(data);
+    let result = bus.write(address, &bytes).map_err(|e| anyhow!("{:?}", e));
+    publish(
+        client,
+        config,
+        &Report {
+            command: "i2c_write",
+            address,
+            register,
+            data: None,
+            error: result.err().map(|e| e.to_string()),
+        },
+    )
+}
@@ -0,0 +1,172 @@
+use anyhow::{bail, Result};
+use esp32_aws_core::digital::{self, Debouncer, DigitalInput, Pull};
+use esp_idf_svc::hal::gpio::{self, AnyIOPin, Input, PinDriver};
+use log::{error, info, warn};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::clock;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// The BME680 bus, and the LoRa radio when it is built in
+#[cfg(not(feature = "lora"))]
+pub const RESERVED_PINS: &[u8] = &[22, 23];
+#[cfg(feature = "lora")]
+pub const RESERVED_PINS: &[u8] = &[5, 14, 18, 19, 22, 23, 27];
+
+/// A debounced change of an input.
+pub struct Edge {
+    pub label: String,
+    pub pin: u8,
+    pub state: bool,
+    pub timestamp: Option<u64>,
+}
+
+struct Slot {
+    input: DigitalInput,
+    driver: PinDriver<'static, AnyIOPin, Input>,
+    debouncer: Debouncer,
+}
+
+impl Slot {
+    fn open(input: &DigitalInput) -> Result<Self> {
+        // Only pins `validate` accepted get here, none of them is driven by another peripheral
+        let mut driver = PinDriver::input(unsafe { AnyIOPin::new(input.pin as i32) })?;
+        match input.pull {
+            Pull::None => {}
+            Pull::Up => driver.set_pull(gpio::Pull::Up)?,
+            Pull::Down => driver.set_pull(gpio::Pull::Down)?,
+        }
+
+        let level = driver.is_high() != input.invert;
+        Ok(Slot {
+            input: input.clone(),
+            driver,
+            debouncer: Debouncer::new(level, input.debounce_ms),
+        })
+    }
+
+    fn level(&self) -> bool {
+        self.driver.is_high() != self.input.invert
+    }
+}
+
+/// Handle to the thread polling the digital inputs.
+pub struct DigitalInputs {
+    configured: Vec<DigitalInput>,
+    config_tx: Sender<Vec<DigitalInput>>,
+    states: Arc<Mutex<BTreeMap<String, bool>>>,
+}
+
+pub fn spawn(edges: SyncSender<Edge>) -> Result<DigitalInputs> {
+    let (config_tx, config_rx) = mpsc::channel();
+    let states = Arc::new(Mutex::new(BTreeMap::new()));
+    let shared = states.clone();
+
+    thread::Builder::new()
+        .name("inputs".into())
+        .stack_size(4096)
+        .spawn(move || poll(config_rx, shared, edges))?;
+
+    Ok(DigitalInputs {
+        configured: Vec::new(),
+        config_tx,
+        states,
+    })
+}
+
+impl DigitalInputs {
+    /// Hands a changed configuration to the polling thread. An invalid one is
+    /// rejected as a whole and the inputs keep running as before.
+    pub fn configure(&mut self, inputs: &[DigitalInput]) {
+        if inputs == self.configured {
+            return;
+        }
+        self.configured = inputs.to_vec();
+
+        match validate(inputs) {
+            Ok(_) => {
+                info!("Configuring {} digital inputs", inputs.len());
+                let _ = self.config_tx.send(inputs.to_vec());
+            }
+            Err(e) => error!("Digital inputs not changed: {}", e),
+        }
+    }
+
+    /// Debounced state of every input by label.
+    pub fn states(&self) -> BTreeMap<String, bool> {
+        self.states.lock().unwrap().clone()
+    }
+}
+
+fn validate(inputs: &[DigitalInput]) -> Result<()> {
+    digital::validate(inputs)?;
+
+    if let Some(input) = inputs
+        .iter()
+        .find(|input| RESERVED_PINS.contains(&input.pin))
+    {
+        bail!("GPIO {} of {} is already in use", input.pin, input.label);
+    }
+    Ok(())
+}
+
+fn poll(
+    config_rx: Receiver<Vec<DigitalInput>>,
+    states: Arc<Mutex<BTreeMap<String, bool>>>,
+    edges: SyncSender<Edge>,
+) {
+    let started = Instant::now();
+    let mut slots: Vec<Slot> = Vec::new();
+
+    loop {
+        if let Some(inputs) = config_rx.try_iter().last() {
+            // Releases the pins before they are opened again
+            slots.clear();
+            for input in &inputs {
+                match Slot::open(input) {
+                    Ok(slot) => slots.push(slot),
+                    Err(e) => error!(
+                        "Failed to set up input {} on GPIO {}: {:?}",
+                        input.label, input.pin, e
+                    ),
+                }
+            }
+            *states.lock().unwrap() = slots
+                .iter()
+                .map(|slot| (slot.input.label.clone(), slot.debouncer.state()))
+                .collect();
+        }
+
+        let now_ms = started.elapsed().as_millis() as u64;
+        for slot in &mut slots {
+            let level = slot.level();
+            let Some(state) = slot.debouncer.update(level, now_ms) else {
+                continue;
+            };
+
+            states
+                .lock()
+                .unwrap()
+                .insert(slot.input.label.clone(), state);
+            let edge = Edge {
+                label: slot.input.label.clone(),
+                pin: slot.input.pin,
+                state,
+                timestamp: clock::unix_now(),
+            };
+            if edges.try_send(edge).is_err() {
+                warn!("Edge queue full, dropping change of {}", slot.input.label);
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
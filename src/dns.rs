@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use esp32_aws_core::url::Url;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    net::{IpAddr, ToSocketAddrs},
+    sync::Mutex,
+};
+
+use crate::clock;
+
+const NVS_NAMESPACE: &str = "dns";
+const HOST_KEY: &str = "host";
+const IP_KEY: &str = "ip";
+const RESOLVED_AT_KEY: &str = "resolved_at";
+// lwIP does not expose the record TTL, so cached addresses expire after a fixed time
+const CACHE_TTL_S: u64 = 24 * 60 * 60;
+const DEFAULT_PORT: u16 = 8883;
+
+// netconn_gethostbyname_addrtype() asking for an IPv6 address only
+const NETCONN_DNS_IPV6: u8 = 1;
+
+// From lwip/ip_addr.h, the argument is an ip_addr_t
+extern "C" {
+    fn ipaddr_aton(cp: *const c_char, addr: *mut c_void) -> c_int;
+}
+
+/// Host name answered from the cache instead of DNS, with its address.
+static PINNED: Mutex<Option<(String, CString)>> = Mutex::new(None);
+
+/// The host name of `url`.
+pub fn host(url: &str) -> Result<&str> {
+    Ok(Url::parse(url)
+        .map_err(|e| anyhow!("Invalid broker URL {}: {}", url, e))?
+        .host)
+}
+
+pub fn resolve(host: &str) -> Result<IpAddr> {
+    (host, DEFAULT_PORT)
+        .to_socket_addrs()?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| anyhow!("No address for {}", host))
+}
+
+/// Answers lookups of `host` with `ip` until [`unpin`] is called.
+///
+/// TLS still sees the host name, so the broker certificate is checked against it.
+pub fn pin(host: &str, ip: IpAddr) {
+    let ip = CString::new(ip.to_string()).expect("IP addresses have no NUL");
+    *PINNED.lock().unwrap() = Some((host.into(), ip));
+}
+
+pub fn unpin() {
+    *PINNED.lock().unwrap() = None;
+}
+
+/// Called by lwIP before every DNS lookup, needs
+/// `CONFIG_LWIP_HOOK_NETCONN_EXT_RESOLVE_CUSTOM`. Returning 0 lets the lookup go
+/// to the DNS server.
+///
+/// # Safety
+///
+/// `name` is a NUL terminated string, `addr` an `ip_addr_t` and `err` an `err_t`.
+#[no_mangle]
+pub unsafe extern "C" fn lwip_hook_netconn_external_resolve(
+    name: *const c_char,
+    addr: *mut c_void,
+    addrtype: u8,
+    err: *mut i8,
+) -> c_int {
+    let Ok(pinned) = PINNED.lock() else {
+        return 0;
+    };
+    let Some((host, ip)) = pinned.as_ref() else {
+        return 0;
+    };
+    if CStr::from_ptr(name).to_bytes() != host.as_bytes() {
+        return 0;
+    }
+    // An IPv4 address cannot answer a lookup for IPv6 only
+    if addrtype == NETCONN_DNS_IPV6 && !ip.to_bytes().contains(&b':') {
+        return 0;
+    }
+    if ipaddr_aton(ip.as_ptr(), addr) == 0 {
+        return 0;
+    }
+
+    *err = 0;
+    1
+}
+
+/// Last resolved broker address, kept in NVS so it survives reboots.
+pub struct DnsCache {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl DnsCache {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(DnsCache {
+            nvs: EspNvs::new(partition, NVS_NAMESPACE, true)?,
+        })
+    }
+
+    /// The cached address of `host`, unless it has expired.
+    pub fn get(&self, host: &str) -> Option<IpAddr> {
+        let mut buf = [0u8; 128];
+        if self.nvs.get_str(HOST_KEY, &mut buf).ok()?? != host {
+            return None;
+        }
+
+        // Without a synced clock the entry is used anyway, a stale one only costs the fallback
+        let resolved_at = self.nvs.get_u64(RESOLVED_AT_KEY).ok()??;
+        if let Some(now) = clock::unix_now() {
+            if resolved_at == 0 || now.saturating_sub(resolved_at) > CACHE_TTL_S {
+                return None;
+            }
+        }
+
+        let mut buf = [0u8; 64];
+        self.nvs.get_str(IP_KEY, &mut buf).ok()??.parse().ok()
+    }
+
+    pub fn store(&mut self, host: &str, ip: IpAddr) -> Result<()> {
+        self.nvs.set_str(HOST_KEY, host)?;
+        self.nvs.set_str(IP_KEY, &ip.to_string())?;
+        self.nvs
+            .set_u64(RESOLVED_AT_KEY, clock::unix_now().unwrap_or(0))?;
+        Ok(())
+    }
+
+    pub fn invalidate(&mut self) -> Result<()> {
+        self.nvs.remove(HOST_KEY)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::sys::{
+    esp_fill_random, mbedtls_ssl_close_notify, mbedtls_ssl_conf_handshake_timeout,
+    mbedtls_ssl_conf_psk, mbedtls_ssl_conf_read_timeout, mbedtls_ssl_conf_rng, mbedtls_ssl_config,
+    mbedtls_ssl_config_defaults, mbedtls_ssl_config_free, mbedtls_ssl_config_init,
+    mbedtls_ssl_context, mbedtls_ssl_free, mbedtls_ssl_handshake, mbedtls_ssl_init,
+    mbedtls_ssl_read, mbedtls_ssl_set_bio, mbedtls_ssl_set_timer_cb, mbedtls_ssl_setup,
+    mbedtls_ssl_write, MBEDTLS_ERR_SSL_PEER_CLOSE_NOTIFY, MBEDTLS_ERR_SSL_TIMEOUT,
+    MBEDTLS_ERR_SSL_WANT_READ, MBEDTLS_ERR_SSL_WANT_WRITE, MBEDTLS_SSL_IS_CLIENT,
+    MBEDTLS_SSL_PRESET_DEFAULT, MBEDTLS_SSL_TRANSPORT_DATAGRAM,
+};
+use std::{
+    ffi::{c_int, c_uchar, c_void},
+    io::ErrorKind,
+    mem,
+    net::UdpSocket,
+    ptr, slice,
+    time::{Duration, Instant},
+};
+
+// Handshake flights are resent after 1 s, doubling up to 16 s (RFC 6347 section 4.2.4.1)
+const HANDSHAKE_TIMEOUT_MIN_MS: u32 = 1000;
+const HANDSHAKE_TIMEOUT_MAX_MS: u32 = 16000;
+
+// From mbedtls/net_sockets.h, which the bindings do not include
+const ERR_NET_SEND_FAILED: c_int = -0x004e;
+const ERR_NET_RECV_FAILED: c_int = -0x004c;
+
+/// Pre-shared key the gateway knows the device by.
+#[derive(Clone, Copy)]
+pub struct Psk {
+    pub identity: &'static [u8],
+    pub key: &'static [u8],
+}
+
+/// A DTLS 1.2 session with the peer of a connected UDP socket, authenticated
+/// with a pre-shared key.
+pub struct DtlsSession {
+    // mbedtls keeps pointers to the config, the socket and the timer, so they
+    // must not move
+    inner: Box<Inner>,
+}
+
+struct Inner {
+    ssl: mbedtls_ssl_context,
+    conf: mbedtls_ssl_config,
+    socket: UdpSocket,
+    timer: Timer,
+}
+
+impl DtlsSession {
+    /// Runs the handshake with the peer `socket` is connected to.
+    pub fn connect(socket: UdpSocket, psk: &Psk) -> Result<Self> {
+        let mut inner = Box::new(Inner {
+            ssl: unsafe { mem::zeroed() },
+            conf: unsafe { mem::zeroed() },
+            socket,
+            timer: Timer::default(),
+        });
+        unsafe {
+            mbedtls_ssl_init(&mut inner.ssl);
+            mbedtls_ssl_config_init(&mut inner.conf);
+        }
+        // Freed on drop from here on, also when the handshake fails
+        let mut session = DtlsSession { inner };
+        session.setup(psk)?;
+        session.handshake()?;
+        Ok(session)
+    }
+
+    fn setup(&mut self, psk: &Psk) -> Result<()> {
+        let inner = &mut *self.inner;
+        unsafe {
+            check(
+                "config",
+                mbedtls_ssl_config_defaults(
+                    &mut inner.conf,
+                    MBEDTLS_SSL_IS_CLIENT as c_int,
+                    MBEDTLS_SSL_TRANSPORT_DATAGRAM as c_int,
+                    MBEDTLS_SSL_PRESET_DEFAULT as c_int,
+                ),
+            )?;
+            mbedtls_ssl_conf_rng(&mut inner.conf, Some(random), ptr::null_mut());
+            check(
+                "PSK",
+                mbedtls_ssl_conf_psk(
+                    &mut inner.conf,
+                    psk.key.as_ptr(),
+                    psk.key.len(),
+                    psk.identity.as_ptr(),
+                    psk.identity.len(),
+                ),
+            )?;
+            mbedtls_ssl_conf_handshake_timeout(
+                &mut inner.conf,
+                HANDSHAKE_TIMEOUT_MIN_MS,
+                HANDSHAKE_TIMEOUT_MAX_MS,
+            );
+            check("setup", mbedtls_ssl_setup(&mut inner.ssl, &inner.conf))?;
+
+            let socket = &mut inner.socket as *mut UdpSocket as *mut c_void;
+            mbedtls_ssl_set_bio(&mut inner.ssl, socket, Some(send), None, Some(recv));
+            let timer = &mut inner.timer as *mut Timer as *mut c_void;
+            mbedtls_ssl_set_timer_cb(&mut inner.ssl, timer, Some(set_timer), Some(get_timer));
+        }
+        Ok(())
+    }
+
+    fn handshake(&mut self) -> Result<()> {
+        loop {
+            match unsafe { mbedtls_ssl_handshake(&mut self.inner.ssl) } {
+                0 => return Ok(()),
+                MBEDTLS_ERR_SSL_WANT_READ | MBEDTLS_ERR_SSL_WANT_WRITE => {}
+                ret => check("handshake", ret)?,
+            }
+        }
+    }
+
+    /// Sends one datagram.
+    pub fn send(&mut self, datagram: &[u8]) -> Result<()> {
+        let ret =
+            unsafe { mbedtls_ssl_write(&mut self.inner.ssl, datagram.as_ptr(), datagram.len()) };
+        if ret < 0 {
+            check("write", ret)?;
+        }
+        Ok(())
+    }
+
+    /// Receives one datagram, `None` when none arrived within `timeout`.
+    pub fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>> {
+        // 0 would wait forever
+        let timeout_ms = (timeout.as_millis() as u32).max(1);
+        unsafe { mbedtls_ssl_conf_read_timeout(&mut self.inner.conf, timeout_ms) };
+
+        match unsafe { mbedtls_ssl_read(&mut self.inner.ssl, buf.as_mut_ptr(), buf.len()) } {
+            len if len > 0 => Ok(Some(len as usize)),
+            MBEDTLS_ERR_SSL_TIMEOUT | MBEDTLS_ERR_SSL_WANT_READ => Ok(None),
+            0 | MBEDTLS_ERR_SSL_PEER_CLOSE_NOTIFY => bail!("Gateway closed the DTLS session"),
+            ret => check("read", ret).map(|_| None),
+        }
+    }
+}
+
+impl Drop for DtlsSession {
+    fn drop(&mut self) {
+        unsafe {
+            // Best effort, the gateway times the session out otherwise
+            mbedtls_ssl_close_notify(&mut self.inner.ssl);
+            mbedtls_ssl_free(&mut self.inner.ssl);
+            mbedtls_ssl_config_free(&mut self.inner.conf);
+        }
+    }
+}
+
+fn check(step: &str, ret: c_int) -> Result<()> {
+    if ret != 0 {
+        bail!("DTLS {} failed: -0x{:04x}", step, ret.unsigned_abs());
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn random(_: *mut c_void, output: *mut c_uchar, len: usize) -> c_int {
+    esp_fill_random(output as *mut c_void, len);
+    0
+}
+
+unsafe extern "C" fn send(socket: *mut c_void, buf: *const c_uchar, len: usize) -> c_int {
+    let socket = &*(socket as *const UdpSocket);
+    match socket.send(slice::from_raw_parts(buf, len)) {
+        Ok(sent) => sent as c_int,
+        Err(_) => ERR_NET_SEND_FAILED,
+    }
+}
+
+unsafe extern "C" fn recv(
+    socket: *mut c_void,
+    buf: *mut c_uchar,
+    len: usize,
+    timeout_ms: u32,
+) -> c_int {
+    let socket = &*(socket as *const UdpSocket);
+    let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64));
+    if socket.set_read_timeout(timeout).is_err() {
+        return ERR_NET_RECV_FAILED;
+    }
+    match socket.recv(slice::from_raw_parts_mut(buf, len)) {
+        Ok(received) => received as c_int,
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            MBEDTLS_ERR_SSL_TIMEOUT
+        }
+        Err(_) => ERR_NET_RECV_FAILED,
+    }
+}
+
+/// The two delays mbedtls times retransmissions with.
+#[derive(Default)]
+struct Timer {
+    started: Option<Instant>,
+    intermediate: Duration,
+    last: Duration,
+}
+
+unsafe extern "C" fn set_timer(timer: *mut c_void, intermediate_ms: u32, last_ms: u32) {
+    let timer = &mut *(timer as *mut Timer);
+    // A final delay of 0 cancels the timer
+    timer.started = (last_ms > 0).then(Instant::now);
+    timer.intermediate = Duration::from_millis(intermediate_ms as u64);
+    timer.last = Duration::from_millis(last_ms as u64);
+}
+
+unsafe extern "C" fn get_timer(timer: *mut c_void) -> c_int {
+    let timer = &*(timer as *const Timer);
+    match timer.started {
+        None => -1,
+        Some(started) if started.elapsed() >= timer.last => 2,
+        Some(started) if started.elapsed() >= timer.intermediate => 1,
+        Some(_) => 0,
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::esp_timer_get_time,
+};
+use log::error;
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const NVS_NAMESPACE: &str = "energy";
+// Charge drawn since the battery was replaced, in µAh
+const USED_KEY: &str = "used_uah";
+
+// Average supply current per state, rough figures for an ESP32 devkit with a BME680.
+// Radio current comes on top of the CPU current while Wi-Fi is associated
+const IDLE_MA: f32 = 20.0;
+const ACTIVE_MA: f32 = 45.0;
+const RADIO_MA: f32 = 25.0;
+// Charge of the transmit burst of one acknowledged publish
+const PUBLISH_MAS: f32 = 10.0;
+
+struct Counters {
+    idle: Duration,
+    radio_on: Duration,
+    last_sample: Option<Instant>,
+    cycles: u32,
+}
+
+static COUNTERS: Mutex<Counters> = Mutex::new(Counters {
+    idle: Duration::ZERO,
+    radio_on: Duration::ZERO,
+    last_sample: None,
+    cycles: 0,
+});
+
+static PUBLISHES: AtomicU32 = AtomicU32::new(0);
+
+/// Energy use since boot, and the battery estimate when a capacity is configured.
+#[derive(Serialize, Debug, Clone)]
+pub struct EnergyReport {
+    pub awake_s: u64,
+    /// Awake time not spent waiting between main loop cycles
+    pub active_s: u64,
+    pub radio_on_s: u64,
+    pub publishes: u32,
+    pub cycles: u32,
+    pub avg_current_ma: f32,
+    /// Average charge drawn per main loop cycle
+    pub cycle_mas: f32,
+    /// Charge drawn since the battery was replaced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used_mah: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_remaining_mah: Option<f32>,
+    /// Hours left at the average current since boot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_life_h: Option<f32>,
+}
+
+/// Called once per main loop cycle with the time spent waiting for the next one.
+pub fn record_cycle(idle: Duration, radio_on: bool) {
+    let now = Instant::now();
+    let mut counters = COUNTERS.lock().unwrap();
+
+    if radio_on {
+        if let Some(last) = counters.last_sample {
+            counters.radio_on += now.duration_since(last);
+        }
+    }
+    counters.idle += idle;
+    counters.last_sample = Some(now);
+    counters.cycles += 1;
+}
+
+/// Called from the MQTT event callback for every acknowledged publish.
+pub fn record_publish() {
+    PUBLISHES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Marks a fresh battery, the drawn charge starts over from zero.
+pub fn reset_battery(partition: &EspDefaultNvsPartition) -> Result<()> {
+    EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?.set_u64(USED_KEY, 0)?;
+    Ok(())
+}
+
+/// Adds up the estimated charge drawn and keeps the total in NVS, so the
+/// battery estimate survives reboots.
+pub struct EnergyMeter {
+    nvs: EspNvs<NvsDefault>,
+    // Charge since boot (mAs) already added to the stored total
+    persisted_mas: f32,
+}
+
+impl EnergyMeter {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(EnergyMeter {
+            nvs: EspNvs::new(partition, NVS_NAMESPACE, true)?,
+            persisted_mas: 0.0,
+        })
+    }
+
+    pub fn report(&mut self, battery_capacity_mah: u32) -> EnergyReport {
+        let awake = Duration::from_micros(unsafe { esp_timer_get_time() } as u64);
+        let publishes = PUBLISHES.load(Ordering::Relaxed);
+        let (idle, radio_on, cycles) = {
+            let counters = COUNTERS.lock().unwrap();
+            (counters.idle.min(awake), counters.radio_on, counters.cycles)
+        };
+        let active = awake - idle;
+
+        let used_mas = idle.as_secs_f32() * IDLE_MA
+            + active.as_secs_f32() * ACTIVE_MA
+            + radio_on.as_secs_f32() * RADIO_MA
+            + publishes as f32 * PUBLISH_MAS;
+        let avg_current_ma = used_mas / awake.as_secs_f32().max(1.0);
+
+        let used_mah = match self.persist(used_mas) {
+            Ok(used_mah) => Some(used_mah),
+            Err(e) => {
+                error!("Failed to store energy use: {:?}", e);
+                None
+            }
+        };
+        let battery_remaining_mah = used_mah
+            .filter(|_| battery_capacity_mah > 0)
+            .map(|used| (battery_capacity_mah as f32 - used).max(0.0));
+
+        EnergyReport {
+            awake_s: awake.as_secs(),
+            active_s: active.as_secs(),
+            radio_on_s: radio_on.as_secs(),
+            publishes,
+            cycles,
+            avg_current_ma,
+            cycle_mas: used_mas / cycles.max(1) as f32,
+            used_mah,
+            battery_remaining_mah,
+            battery_life_h: battery_remaining_mah.map(|remaining| remaining / avg_current_ma),
+        }
+    }
+
+    /// Adds the charge drawn since the last call to the stored total and returns it in mAh.
+    fn persist(&mut self, used_mas: f32) -> Result<f32> {
+        let delta_uah = ((used_mas - self.persisted_mas) * 1000.0 / 3600.0) as u64;
+        let total_uah = self.nvs.get_u64(USED_KEY)?.unwrap_or(0) + delta_uah;
+        self.nvs.set_u64(USED_KEY, total_uah)?;
+        // Only what was actually stored, the remainder is carried to the next call
+        self.persisted_mas += delta_uah as f32 * 3600.0 / 1000.0;
+
+        Ok(total_uah as f32 / 1000.0)
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use esp32_aws_core::{
+    errors::{ErrorCode, Throttle},
+    outbox::Priority,
+};
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, QoS},
+    sys::esp_timer_get_time,
+};
+use log::error;
+use serde::Serialize;
+use serde_json::Value;
+use std::{sync::Mutex, time::Duration};
+
+use crate::{clock, publisher, structs::Config};
+
+// A sensor that fails every reading would report every 5 s otherwise
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+static THROTTLE: Mutex<Throttle> = Mutex::new(Throttle::new(PUBLISH_INTERVAL));
+
+/// An error of the device, published to `<pub_topic>/errors`.
+#[derive(Serialize, Debug)]
+pub struct DeviceError {
+    #[serde(skip)]
+    kind: ErrorCode,
+    pub code: u16,
+    pub error: &'static str,
+    pub message: String,
+    /// Errors of this code held back since the last one was published
+    #[serde(skip_serializing_if = "is_zero")]
+    pub suppressed: u32,
+    /// What the error happened on, e.g. the topic or the command
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub context: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl DeviceError {
+    pub fn new(code: ErrorCode, message: String, context: Value) -> Self {
+        DeviceError {
+            kind: code,
+            code: code.code(),
+            error: code.name(),
+            message,
+            suppressed: 0,
+            context,
+            timestamp: clock::unix_now(),
+        }
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Logs an error and publishes it unless one of its code went out within
+/// `PUBLISH_INTERVAL`, failing to publish is only logged.
+pub fn report(client: &mut EspMqttClient<'static>, config: &Config, mut device_error: DeviceError) {
+    error!(
+        "Error {} ({}): {}",
+        device_error.code, device_error.error, device_error.message
+    );
+    let now = Duration::from_micros(unsafe { esp_timer_get_time() } as u64);
+    let Some(suppressed) = THROTTLE.lock().unwrap().admit(device_error.kind, now) else {
+        return;
+    };
+    device_error.suppressed = suppressed;
+    if let Err(e) = publish(client, config, &device_error) {
+        error!("Failed to publish error {}: {:?}", device_error.code, e);
+    }
+}
+
+fn publish(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    device_error: &DeviceError,
+) -> Result<()> {
+    publisher::publish(
+        client,
+        Priority::Response,
+        &config.topic("errors"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(device_error)?.as_bytes(),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use esp32_aws_core::outbox::Priority;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{clock, publisher, structs::Config};
+
+/// Something that happened on the device, as opposed to a periodic reading.
+#[derive(Serialize, Debug)]
+pub struct Event {
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl Event {
+    pub fn new(event: &'static str, details: Value) -> Self {
+        Event {
+            event,
+            details,
+            timestamp: clock::unix_now(),
+        }
+    }
+}
+
+/// Publishes an event to `<pub_topic>/events`.
+pub fn publish(client: &mut EspMqttClient<'static>, config: &Config, event: Event) -> Result<()> {
+    info!("Event {}: {}", event.event, event.details);
+
+    publisher::publish(
+        client,
+        Priority::Telemetry,
+        &config.topic("events"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(&event)?.as_bytes(),
+    )?;
+    Ok(())
+}
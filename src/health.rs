@@ -0,0 +1,77 @@
+use anyhow::Result;
+use esp32_aws_core::{cert::Validity, outbox::Priority, readiness::Readiness};
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, QoS},
+    sys::{esp_get_free_heap_size, esp_timer_get_time},
+};
+use serde::Serialize;
+
+use crate::{
+    build_info::BuildInfo,
+    clock::{self, ClockStatus},
+    energy::EnergyReport,
+    metrics::{self, MqttMetrics},
+    publisher::{self, OutboxMetrics},
+    security::{self, SecurityStatus},
+    structs::Config,
+};
+
+#[derive(Serialize, Debug)]
+pub struct HealthReport<'a> {
+    pub build: &'a BuildInfo,
+    pub uptime_s: u64,
+    pub free_heap: u32,
+    /// Days until the device certificate expires, once the time is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_days_remaining: Option<i64>,
+    pub clock: ClockStatus,
+    /// Left out when the energy meter could not start
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub energy: Option<EnergyReport>,
+    pub mqtt: MqttMetrics,
+    pub outbox: OutboxMetrics,
+    pub security: SecurityStatus,
+    /// Readiness of the subsystems brought up at startup
+    pub components: &'a Readiness,
+}
+
+impl<'a> HealthReport<'a> {
+    pub fn collect(
+        build: &'a BuildInfo,
+        cert_validity: Option<&Validity>,
+        energy: Option<EnergyReport>,
+        components: &'a Readiness,
+    ) -> Self {
+        let now = clock::unix_now();
+
+        HealthReport {
+            build,
+            uptime_s: unsafe { esp_timer_get_time() } as u64 / 1_000_000,
+            free_heap: unsafe { esp_get_free_heap_size() },
+            cert_days_remaining: cert_validity.zip(now).map(|(v, now)| v.days_remaining(now)),
+            clock: clock::status(),
+            energy,
+            mqtt: metrics::snapshot(),
+            outbox: publisher::snapshot(),
+            security: security::status(),
+            components,
+        }
+    }
+}
+
+/// Publishes the report to `<pub_topic>/health`.
+pub fn publish(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    report: &HealthReport,
+) -> Result<()> {
+    publisher::publish(
+        client,
+        Priority::Telemetry,
+        &config.topic("health"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(report)?.as_bytes(),
+    )?;
+    Ok(())
+}
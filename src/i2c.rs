@@ -0,0 +1,45 @@
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+use esp_idf_svc::hal::i2c::{I2cDriver, I2cError};
+use std::sync::{Arc, Mutex};
+
+/// Handle to the I2C bus that can be shared between drivers.
+///
+/// Drivers such as the BME680 take ownership of their bus, sharing it lets the
+/// firmware keep probing the bus after a driver went away with its handle.
+#[derive(Clone)]
+pub struct SharedI2c(Arc<Mutex<I2cDriver<'static>>>);
+
+impl SharedI2c {
+    pub fn new(driver: I2cDriver<'static>) -> Self {
+        SharedI2c(Arc::new(Mutex::new(driver)))
+    }
+}
+
+impl Read for SharedI2c {
+    type Error = I2cError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        Read::read(&mut *self.0.lock().unwrap(), address, buffer)
+    }
+}
+
+impl Write for SharedI2c {
+    type Error = I2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        Write::write(&mut *self.0.lock().unwrap(), address, bytes)
+    }
+}
+
+impl WriteRead for SharedI2c {
+    type Error = I2cError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        WriteRead::write_read(&mut *self.0.lock().unwrap(), address, bytes, buffer)
+    }
+}
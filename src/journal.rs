@@ -0,0 +1,153 @@
+use anyhow::Result;
+use esp32_aws_core::{
+    journal::{self, Entry},
+    outbox::Priority,
+};
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, QoS},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    sys::{self, esp_reset_reason, esp_timer_get_time},
+};
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+use crate::{clock, publisher, structs::Config};
+
+const NVS_NAMESPACE: &str = "journal";
+const NEXT_KEY: &str = "next";
+// Version of the firmware that booted last, to tell when it was updated
+const VERSION_KEY: &str = "version";
+
+/// Entries are stored one per key, so recording one rewrites only that slot.
+struct Journal {
+    nvs: EspNvs<NvsDefault>,
+    next: u32,
+}
+
+impl Journal {
+    fn append(&mut self, event: &str, details: Value) -> Result<()> {
+        let entry = Entry {
+            seq: self.next,
+            timestamp: clock::unix_now(),
+            uptime_s: unsafe { esp_timer_get_time() } as u64 / 1_000_000,
+            event: event.into(),
+            details,
+        };
+        self.nvs
+            .set_blob(&slot_key(entry.seq), &serde_json::to_vec(&entry)?)?;
+        self.next += 1;
+        self.nvs.set_u32(NEXT_KEY, self.next)?;
+        Ok(())
+    }
+
+    fn read(&self, seq: u32) -> Result<Option<Entry>> {
+        let key = slot_key(seq);
+        let Some(len) = self.nvs.blob_len(&key)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0; len];
+        let Some(data) = self.nvs.get_blob(&key, &mut buf)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(data)?))
+    }
+}
+
+static JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+
+fn slot_key(seq: u32) -> String {
+    format!("e{}", journal::slot(seq))
+}
+
+/// Opens the journal and records the boot, along with a firmware update when
+/// the version changed since the previous one.
+pub fn init(partition: EspDefaultNvsPartition, version: &str) -> Result<()> {
+    let mut nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+    let next = nvs.get_u32(NEXT_KEY)?.unwrap_or(0);
+
+    let mut buf = [0; 32];
+    let previous = nvs.get_str(VERSION_KEY, &mut buf)?.map(str::to_owned);
+    if previous.as_deref() != Some(version) {
+        nvs.set_str(VERSION_KEY, version)?;
+    }
+
+    *JOURNAL.lock().unwrap() = Some(Journal { nvs, next });
+
+    record(
+        "boot",
+        json!({ "version": version, "reset_reason": reset_reason() }),
+    );
+    if let Some(previous) = previous.filter(|previous| previous != version) {
+        record(
+            "firmware_updated",
+            json!({ "from": previous, "to": version }),
+        );
+    }
+    Ok(())
+}
+
+/// Adds an entry, dropped with an error logged when it cannot be stored.
+pub fn record(event: &str, details: Value) {
+    let mut journal = JOURNAL.lock().unwrap();
+    let Some(journal) = journal.as_mut() else {
+        warn!("Journal not open, dropping {} entry", event);
+        return;
+    };
+
+    if let Err(e) = journal.append(event, details) {
+        error!("Failed to record {} in the journal: {:?}", event, e);
+    }
+}
+
+/// The last `count` entries, newest first.
+pub fn latest(count: u16) -> Result<Vec<Entry>> {
+    let journal = JOURNAL.lock().unwrap();
+    let Some(journal) = journal.as_ref() else {
+        return Ok(Vec::new());
+    };
+
+    let mut entries = Vec::new();
+    for seq in journal::latest(journal.next, count).rev() {
+        match journal.read(seq) {
+            // A slot still holding an older entry was not written completely
+            Ok(Some(entry)) if entry.seq == seq => entries.push(entry),
+            Ok(_) => warn!("Journal entry {} missing", seq),
+            Err(e) => warn!("Skipping unreadable journal entry {}: {:?}", seq, e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Publishes the last `count` entries to `<pub_topic>/journal`.
+pub fn publish(client: &mut EspMqttClient<'static>, config: &Config, count: u16) -> Result<()> {
+    let entries = latest(count)?;
+
+    publisher::publish(
+        client,
+        Priority::Response,
+        &config.topic("journal"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(&json!({ "entries": entries }))?.as_bytes(),
+    )?;
+    info!("Published {} journal entries", entries.len());
+    Ok(())
+}
+
+fn reset_reason() -> &'static str {
+    match unsafe { esp_reset_reason() } {
+        sys::esp_reset_reason_t_ESP_RST_POWERON => "power_on",
+        sys::esp_reset_reason_t_ESP_RST_EXT => "external",
+        sys::esp_reset_reason_t_ESP_RST_SW => "software",
+        sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt_watchdog",
+        sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task_watchdog",
+        sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep_sleep",
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        sys::esp_reset_reason_t_ESP_RST_SDIO => "sdio",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Result};
+use esp32_aws_core::schedule::TokenBucket;
+use esp_idf_svc::{
+    log::{set_target_level, EspLogger},
+    sys::esp_log_timestamp,
+};
+use log::{info, Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Subsystems whose log level can be changed at runtime, with the log targets
+/// (Rust module paths and ESP-IDF component tags) each of them covers.
+const MODULES: &[(&str, &[&str])] = &[
+    (
+        "wifi",
+        &[
+            "esp32_aws::wifi",
+            "esp32_aws::dns",
+            "wifi",
+            "esp_netif_handlers",
+        ],
+    ),
+    (
+        "mqtt",
+        &[
+            "esp32_aws::mqtt",
+            "esp32_aws::cloud",
+            "mqtt_client",
+            "esp-tls",
+        ],
+    ),
+    ("sensor", &["esp32_aws::sensor", "bme680"]),
+    ("ota", &["esp_https_ota", "esp_ota_ops"]),
+];
+
+// Lines kept around to start a tail session with
+const RECENT_LINES: usize = 50;
+// Lines waiting to be published during a tail session
+const MAX_PENDING_LINES: usize = 100;
+pub const MAX_TAIL_DURATION: Duration = Duration::from_secs(600);
+pub const MAX_TAIL_RATE: u32 = 20;
+
+/// Forwards to the ESP-IDF logger and keeps the lines for tail sessions.
+///
+/// Only the Rust `log` records are captured, output of the ESP-IDF components
+/// goes straight to the UART.
+struct Logger {
+    inner: EspLogger,
+    state: Mutex<State>,
+}
+
+struct State {
+    // Levels raised or lowered through `set_module_level`, mirroring ESP-IDF's tag levels
+    levels: Vec<(&'static str, LevelFilter)>,
+    default_level: LevelFilter,
+    recent: VecDeque<String>,
+    session: Option<Session>,
+}
+
+struct Session {
+    until: Instant,
+    started: Instant,
+    limit: TokenBucket,
+    pending: VecDeque<String>,
+    dropped: u32,
+}
+
+/// Log lines of a tail session, published to `<pub_topic>/logs`.
+#[derive(Serialize, Debug)]
+pub struct LogBatch {
+    pub lines: Vec<String>,
+    /// Lines lost to the rate limit or a full queue since the previous batch
+    pub dropped: u32,
+    /// Set on the last batch of the session
+    pub done: bool,
+}
+
+static LOGGER: Logger = Logger {
+    inner: EspLogger,
+    state: Mutex::new(State {
+        levels: Vec::new(),
+        default_level: LevelFilter::Info,
+        recent: VecDeque::new(),
+        session: None,
+    }),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+
+        // Never log from here, the state lock is not reentrant
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if record.level() > state.level(record.target()) {
+            return;
+        }
+
+        let line = format!(
+            "{} ({}) {}: {}",
+            marker(record.level()),
+            unsafe { esp_log_timestamp() },
+            record.target(),
+            record.args()
+        );
+
+        if let Some(session) = state.session.as_mut() {
+            session.push(line.clone());
+        }
+
+        if state.recent.len() == RECENT_LINES {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(line);
+    }
+
+    fn flush(&self) {}
+}
+
+impl State {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.levels
+            .iter()
+            .find(|(t, _)| *t == target)
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default_level)
+    }
+}
+
+impl Session {
+    fn push(&mut self, line: String) {
+        let now = Instant::now();
+        if now >= self.until {
+            return;
+        }
+
+        if self.pending.len() == MAX_PENDING_LINES
+            || !self.limit.try_take(now.duration_since(self.started))
+        {
+            self.dropped += 1;
+            return;
+        }
+
+        self.pending.push_back(line);
+    }
+}
+
+fn marker(level: Level) -> &'static str {
+    match level {
+        Level::Error => "E",
+        Level::Warn => "W",
+        Level::Info => "I",
+        Level::Debug => "D",
+        Level::Trace => "V",
+    }
+}
+
+pub fn init() {
+    log::set_logger(&LOGGER)
+        .map(|()| LOGGER.inner.initialize())
+        .unwrap();
+}
+
+/// Sets the log level of one module, or of everything when `module` is `*`.
+///
+/// Levels above `CONFIG_LOG_MAXIMUM_LEVEL` are compiled out and cannot be enabled.
+pub fn set_module_level(module: &str, level: &str) -> Result<()> {
+    let level: LevelFilter = level
+        .parse()
+        .map_err(|_| anyhow!("Unknown log level {}", level))?;
+
+    let targets = if module == "*" {
+        &["*"][..]
+    } else {
+        MODULES
+            .iter()
+            .find(|(name, _)| *name == module)
+            .map(|(_, targets)| *targets)
+            .ok_or_else(|| anyhow!("Unknown log module {}", module))?
+    };
+
+    for target in targets {
+        set_target_level(target, level)?;
+    }
+
+    {
+        let mut state = LOGGER.state.lock().unwrap();
+        if module == "*" {
+            state.default_level = level;
+            state.levels.clear();
+        } else {
+            state.levels.retain(|(t, _)| !targets.contains(t));
+            state.levels.extend(targets.iter().map(|t| (*t, level)));
+        }
+    }
+
+    info!("Log level of {} set to {}", module, level);
+    Ok(())
+}
+
+/// Starts streaming log lines, beginning with the most recent ones.
+/// A running session is replaced.
+pub fn start_tail(duration: Duration, rate: u32) {
+    let now = Instant::now();
+    let rate = rate.clamp(1, MAX_TAIL_RATE);
+
+    {
+        let mut state = LOGGER.state.lock().unwrap();
+        let pending = state.recent.clone();
+        state.session = Some(Session {
+            until: now + duration.min(MAX_TAIL_DURATION),
+            started: now,
+            // Bursts of up to five seconds worth of lines
+            limit: TokenBucket::new(rate, rate * 5, Duration::ZERO),
+            pending,
+            dropped: 0,
+        });
+    }
+
+    info!(
+        "Tailing logs for {} s at up to {} lines/s",
+        duration.min(MAX_TAIL_DURATION).as_secs(),
+        rate
+    );
+}
+
+/// Ends the running session, its last batch is still returned by `take_tail`.
+pub fn stop_tail() {
+    if let Some(session) = LOGGER.state.lock().unwrap().session.as_mut() {
+        session.until = Instant::now();
+    }
+}
+
+/// Takes the lines collected since the previous call while a session is running.
+pub fn take_tail() -> Option<LogBatch> {
+    let mut state = LOGGER.state.lock().unwrap();
+    let session = state.session.as_mut()?;
+
+    let done = Instant::now() >= session.until;
+    let batch = LogBatch {
+        lines: session.pending.drain(..).collect(),
+        dropped: mem::take(&mut session.dropped),
+        done,
+    };
+
+    if done {
+        state.session = None;
+    } else if batch.lines.is_empty() && batch.dropped == 0 {
+        return None;
+    }
+
+    Some(batch)
+}
@@ -0,0 +1,505 @@
+use anyhow::{bail, Result};
+use esp32_aws_core::{
+    command::{Command, LoraRole},
+    frame::{self, Sequences},
+    outbox::Priority,
+};
+use esp_idf_svc::{
+    hal::{
+        delay::{Delay, FreeRtos},
+        gpio::{OutputPin, PinDriver},
+        peripheral::Peripheral,
+        spi::SpiSingleDeviceDriver,
+    },
+    mqtt::client::{EspMqttClient, QoS},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+};
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    sync::mpsc::{Receiver, SyncSender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    burst, clock, codec, journal, publisher,
+    sensor::SensorSlot,
+    structs::{Config, SensorData},
+};
+
+const NVS_NAMESPACE: &str = "lora";
+const ROLE_KEY: &str = "role";
+const SEQUENCE_KEY: &str = "sequence";
+const SEQUENCES_KEY: &str = "sequences";
+
+// Sequence numbers a node reserves with one flash write, the ones of a block
+// not used before a reboot are skipped
+const SEQUENCE_BLOCK: u32 = 64;
+// Longest time the gateway keeps newer sequence numbers in memory only, frames
+// taken within it can be replayed once after a reboot
+const SEQUENCES_STORE_INTERVAL: Duration = Duration::from_secs(60);
+
+// EU868 channel, use 915 MHz parts and a matching frequency in the Americas
+const FREQUENCY_HZ: u64 = 868_100_000;
+// Spreading factor 9 trades data rate for range, about 200 ms airtime per reading
+const SPREADING_FACTOR: u8 = 9;
+// Private network sync word, so LoRaWAN gateways nearby ignore our frames
+const SYNC_WORD: u8 = 0x12;
+const TX_POWER_DBM: u8 = 17;
+const TX_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Keeps the transmitter well below the 1% duty cycle limit of the EU868 band
+const NODE_INTERVAL: Duration = Duration::from_secs(60);
+const GATEWAY_STACK_SIZE: usize = 4096;
+const GATEWAY_POLL_MS: u32 = 10;
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_LNA: u8 = 0x0c;
+const REG_FIFO_ADDR_PTR: u8 = 0x0d;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0e;
+const REG_FIFO_RX_BASE_ADDR: u8 = 0x0f;
+const REG_FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_RX_NB_BYTES: u8 = 0x13;
+const REG_PKT_SNR_VALUE: u8 = 0x19;
+const REG_PKT_RSSI_VALUE: u8 = 0x1a;
+const REG_MODEM_CONFIG_1: u8 = 0x1d;
+const REG_MODEM_CONFIG_2: u8 = 0x1e;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_VERSION: u8 = 0x42;
+
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+const IRQ_TX_DONE: u8 = 0x08;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+const IRQ_RX_DONE: u8 = 0x40;
+
+const SX127X_VERSION: u8 = 0x12;
+// Packet RSSI offset of the high frequency port
+const RSSI_OFFSET: i16 = -157;
+
+pub fn role(partition: &EspDefaultNvsPartition) -> Result<LoraRole> {
+    let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+    Ok(match nvs.get_u8(ROLE_KEY)? {
+        Some(1) => LoraRole::Node,
+        Some(2) => LoraRole::Gateway,
+        _ => LoraRole::Off,
+    })
+}
+
+/// Stores the role, it takes effect on the next boot.
+pub fn set_role(partition: &EspDefaultNvsPartition, role: LoraRole) -> Result<()> {
+    let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+    nvs.set_u8(ROLE_KEY, role as u8)?;
+    Ok(())
+}
+
+/// A frame received by the gateway.
+pub struct Packet {
+    pub data: Vec<u8>,
+    pub rssi: i16,
+    pub snr: f32,
+}
+
+/// A node reading as the gateway publishes it, with the link quality it arrived at.
+#[derive(Serialize, Debug)]
+pub struct BridgedReading {
+    pub node: String,
+    #[serde(flatten)]
+    pub reading: SensorData,
+    pub rssi: i16,
+    pub snr: f32,
+}
+
+impl Packet {
+    /// The reading carried by the frame of a node in `allowed`, stamped with
+    /// the gateway clock.
+    pub fn bridged(&self, gateway: &mut Gateway, allowed: &[String]) -> Result<BridgedReading> {
+        let received = gateway.receive(&self.data, allowed)?;
+        let mut reading = received.reading;
+        reading.timestamp = clock::unix_now();
+        reading.clock_suspect = clock::status().suspect;
+
+        Ok(BridgedReading {
+            node: received.node_id,
+            reading,
+            rssi: self.rssi,
+            snr: self.snr,
+        })
+    }
+}
+
+/// The gateway key and the sequence numbers taken from each node, kept in NVS
+/// so that frames from before a reboot cannot be replayed.
+pub struct Gateway {
+    key: &'static [u8],
+    sequences: Sequences,
+    nvs: EspNvs<NvsDefault>,
+    stored_at: Option<Instant>,
+    dirty: bool,
+}
+
+impl Gateway {
+    pub fn new(partition: &EspDefaultNvsPartition, key: &'static [u8]) -> Result<Self> {
+        let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+        let sequences = match read_sequences(&nvs) {
+            Ok(sequences) => sequences,
+            Err(e) => {
+                warn!("Ignoring stored LoRa sequence numbers: {:?}", e);
+                Sequences::new()
+            }
+        };
+
+        Ok(Gateway {
+            key,
+            sequences,
+            nvs,
+            stored_at: None,
+            dirty: false,
+        })
+    }
+
+    fn receive(&mut self, data: &[u8], allowed: &[String]) -> Result<frame::Received> {
+        self.sequences.retain(allowed);
+        let received = codec::receive_frame(data, self.key, allowed, &mut self.sequences)?;
+        self.dirty = true;
+        self.flush();
+        Ok(received)
+    }
+
+    /// Stores the sequence numbers taken since the last write, at most once
+    /// per `SEQUENCES_STORE_INTERVAL` as every frame would wear the flash out.
+    /// Also called when no frames arrive, so the last ones are not left unsaved.
+    pub fn flush(&mut self) {
+        let due = self.stored_at.map_or(true, |stored_at| {
+            stored_at.elapsed() >= SEQUENCES_STORE_INTERVAL
+        });
+        if self.dirty && due {
+            match store_sequences(&mut self.nvs, &self.sequences) {
+                Ok(_) => self.dirty = false,
+                Err(e) => error!("Failed to store LoRa sequence numbers: {:?}", e),
+            }
+            self.stored_at = Some(Instant::now());
+        }
+    }
+}
+
+fn read_sequences(nvs: &EspNvs<NvsDefault>) -> Result<Sequences> {
+    let Some(len) = nvs.blob_len(SEQUENCES_KEY)? else {
+        return Ok(Sequences::new());
+    };
+    let mut buf = vec![0; len];
+    let Some(data) = nvs.get_blob(SEQUENCES_KEY, &mut buf)? else {
+        return Ok(Sequences::new());
+    };
+
+    Ok(serde_json::from_slice(data)?)
+}
+
+fn store_sequences(nvs: &mut EspNvs<NvsDefault>, sequences: &Sequences) -> Result<()> {
+    nvs.set_blob(SEQUENCES_KEY, &serde_json::to_vec(sequences)?)?;
+    Ok(())
+}
+
+/// Sequence numbers of the frames a node sends, growing across reboots.
+struct SequenceCounter {
+    nvs: EspNvs<NvsDefault>,
+    next: u32,
+    reserved: u32,
+}
+
+impl SequenceCounter {
+    fn new(partition: &EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+        // The end of the block reserved before the reboot
+        let next = nvs.get_u32(SEQUENCE_KEY)?.unwrap_or(0);
+        Ok(SequenceCounter {
+            nvs,
+            next,
+            reserved: next,
+        })
+    }
+
+    fn next(&mut self) -> Result<u32> {
+        if self.next == self.reserved {
+            let Some(reserved) = self.reserved.checked_add(SEQUENCE_BLOCK) else {
+                bail!("LoRa sequence numbers used up, provision a new node key");
+            };
+            self.nvs.set_u32(SEQUENCE_KEY, reserved)?;
+            self.reserved = reserved;
+        }
+        let sequence = self.next;
+        self.next += 1;
+        Ok(sequence)
+    }
+}
+
+/// Raw LoRa on an SX1276/77/78/79 transceiver, explicit header with CRC.
+pub struct Sx127x {
+    spi: SpiSingleDeviceDriver<'static>,
+}
+
+impl Sx127x {
+    /// Resets the transceiver and configures the modem, leaving it in standby.
+    pub fn new(
+        spi: SpiSingleDeviceDriver<'static>,
+        reset: impl Peripheral<P = impl OutputPin>,
+        delay: &mut Delay,
+    ) -> Result<Self> {
+        let mut reset = PinDriver::output(reset)?;
+        reset.set_low()?;
+        delay.delay_ms(1);
+        reset.set_high()?;
+        delay.delay_ms(10);
+
+        let mut radio = Sx127x { spi };
+
+        let version = radio.read_register(REG_VERSION)?;
+        if version != SX127X_VERSION {
+            bail!("No SX127x found, version register reads {:#04x}", version);
+        }
+
+        // The LoRa mode bit can only be changed in sleep
+        radio.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+
+        let frf = (FREQUENCY_HZ << 19) / 32_000_000;
+        radio.write_registers(
+            REG_FRF_MSB,
+            &[(frf >> 16) as u8, (frf >> 8) as u8, frf as u8],
+        )?;
+
+        radio.write_register(REG_FIFO_TX_BASE_ADDR, 0)?;
+        radio.write_register(REG_FIFO_RX_BASE_ADDR, 0)?;
+        // LNA gain set by the AGC, with the high frequency boost
+        radio.write_register(REG_LNA, 0x23)?;
+        // PA_BOOST output
+        radio.write_register(REG_PA_CONFIG, 0x80 | (TX_POWER_DBM - 2))?;
+        // 125 kHz bandwidth, coding rate 4/5, explicit header
+        radio.write_register(REG_MODEM_CONFIG_1, 0x72)?;
+        // Payload CRC on
+        radio.write_register(REG_MODEM_CONFIG_2, SPREADING_FACTOR << 4 | 0x04)?;
+        // AGC on
+        radio.write_register(REG_MODEM_CONFIG_3, 0x04)?;
+        radio.write_register(REG_SYNC_WORD, SYNC_WORD)?;
+
+        radio.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_STANDBY)?;
+        info!(
+            "SX127x ready on {} Hz, SF{}",
+            FREQUENCY_HZ, SPREADING_FACTOR
+        );
+
+        Ok(radio)
+    }
+
+    /// Sends one frame and waits until it is on air, then returns to standby.
+    pub fn transmit(&mut self, payload: &[u8]) -> Result<()> {
+        if payload.len() > u8::MAX as usize {
+            bail!("LoRa frame of {} bytes is too long", payload.len());
+        }
+
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_STANDBY)?;
+        self.write_register(REG_FIFO_ADDR_PTR, 0)?;
+        self.write_registers(REG_FIFO, payload)?;
+        self.write_register(REG_PAYLOAD_LENGTH, payload.len() as u8)?;
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_TX)?;
+
+        let started = Instant::now();
+        while self.read_register(REG_IRQ_FLAGS)? & IRQ_TX_DONE == 0 {
+            if started.elapsed() >= TX_TIMEOUT {
+                self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_STANDBY)?;
+                bail!("LoRa transmission timed out");
+            }
+            FreeRtos::delay_ms(5);
+        }
+        self.write_register(REG_IRQ_FLAGS, IRQ_TX_DONE)?;
+
+        Ok(())
+    }
+
+    pub fn start_receive(&mut self) -> Result<()> {
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_RX_CONTINUOUS)
+    }
+
+    /// The frame received since the last call, if any. Frames failing the CRC are dropped.
+    pub fn poll_receive(&mut self) -> Result<Option<Packet>> {
+        let flags = self.read_register(REG_IRQ_FLAGS)?;
+        if flags & IRQ_RX_DONE == 0 {
+            return Ok(None);
+        }
+        self.write_register(REG_IRQ_FLAGS, flags)?;
+
+        if flags & IRQ_PAYLOAD_CRC_ERROR != 0 {
+            warn!("Dropping LoRa frame with a CRC error");
+            return Ok(None);
+        }
+
+        let len = self.read_register(REG_RX_NB_BYTES)? as usize;
+        let start = self.read_register(REG_FIFO_RX_CURRENT_ADDR)?;
+        self.write_register(REG_FIFO_ADDR_PTR, start)?;
+
+        let mut data = vec![0u8; len];
+        self.read_registers(REG_FIFO, &mut data)?;
+
+        Ok(Some(Packet {
+            data,
+            rssi: RSSI_OFFSET + self.read_register(REG_PKT_RSSI_VALUE)? as i16,
+            snr: self.read_register(REG_PKT_SNR_VALUE)? as i8 as f32 / 4.0,
+        }))
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8> {
+        let mut value = [0u8];
+        self.read_registers(register, &mut value)?;
+        Ok(value[0])
+    }
+
+    fn read_registers(&mut self, register: u8, values: &mut [u8]) -> Result<()> {
+        let mut write = vec![0u8; values.len() + 1];
+        write[0] = register & 0x7f;
+        let mut read = vec![0u8; write.len()];
+
+        self.spi.transfer(&mut read, &write)?;
+        values.copy_from_slice(&read[1..]);
+        Ok(())
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<()> {
+        self.write_registers(register, &[value])
+    }
+
+    fn write_registers(&mut self, register: u8, values: &[u8]) -> Result<()> {
+        let mut write = Vec::with_capacity(values.len() + 1);
+        write.push(register | 0x80);
+        write.extend_from_slice(values);
+
+        self.spi.write(&write)?;
+        Ok(())
+    }
+}
+
+/// Sensor node out of Wi-Fi coverage: sends every reading to the gateway and never
+/// brings up Wi-Fi or MQTT.
+pub fn run_node(
+    mut radio: Sx127x,
+    sensor: &mut SensorSlot,
+    delay: &mut Delay,
+    config: &Config,
+    burst_samples: u8,
+    nvs: &EspDefaultNvsPartition,
+    console: &Receiver<Command>,
+) -> Result<()> {
+    let node_id = &config.client_id;
+    let Some(node_key) = config.lora_key else {
+        bail!("No LoRa key provisioned, the gateway drops unsigned frames");
+    };
+    let mut sequences = SequenceCounter::new(nvs)?;
+    info!("Running as LoRa node {}", node_id);
+
+    loop {
+        // Without MQTT the console is the only way to turn the node back into a Wi-Fi device
+        for command in console.try_iter() {
+            match command {
+                Command::LoraRole { role } => match set_role(nvs, role) {
+                    Ok(_) => {
+                        info!("LoRa role set to {:?}, restart to apply", role);
+                        journal::record("lora_role_changed", json!({ "role": role }));
+                    }
+                    Err(e) => error!("Failed to store LoRa role: {:?}", e),
+                },
+                command => warn!("{:?} is not available on LoRa nodes", command),
+            }
+        }
+
+        sensor.poll(delay);
+
+        if sensor.is_attached() {
+            let samples = burst_samples.clamp(1, burst::MAX_BURST_SAMPLES);
+            match sensor.read_burst(delay, samples) {
+                Ok(readings) => {
+                    let summary = burst::summarize(&readings);
+                    let reading = SensorData {
+                        temperature: Some(summary.temperature.mean),
+                        humidity: Some(summary.humidity.mean),
+                        pressure: Some(summary.pressure.mean),
+                        gas_resistance: summary.gas_resistance.map(|gas| gas.mean),
+                        raw: None,
+                        // Nodes have no time source, the gateway stamps the reading
+                        timestamp: None,
+                        clock_suspect: false,
+                        burst: None,
+                        trend: None,
+                        inputs: BTreeMap::new(),
+                        pulses: BTreeMap::new(),
+                    };
+
+                    match sequences
+                        .next()
+                        .and_then(|sequence| {
+                            codec::encode_frame(node_id, &reading, sequence, node_key)
+                        })
+                        .and_then(|frame| radio.transmit(&frame))
+                    {
+                        Ok(_) => info!("Sent reading over LoRa"),
+                        Err(e) => error!("Failed to send reading over LoRa: {:?}", e),
+                    }
+                }
+                Err(e) => error!("Failed to read sensor: {:?}", e),
+            }
+        }
+
+        delay.delay_ms(NODE_INTERVAL.as_millis() as u32);
+    }
+}
+
+/// Starts a thread listening for node frames, which the main loop bridges to MQTT.
+pub fn spawn_gateway(mut radio: Sx127x, packets: SyncSender<Packet>) -> Result<()> {
+    radio.start_receive()?;
+
+    thread::Builder::new()
+        .stack_size(GATEWAY_STACK_SIZE)
+        .spawn(move || loop {
+            match radio.poll_receive() {
+                Ok(Some(packet)) => {
+                    if packets.try_send(packet).is_err() {
+                        error!("LoRa queue full, dropping frame");
+                    }
+                }
+                Ok(None) => FreeRtos::delay_ms(GATEWAY_POLL_MS),
+                Err(e) => {
+                    error!("Failed to poll the LoRa radio: {:?}", e);
+                    FreeRtos::delay_ms(1000);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Publishes a node reading to `<pub_topic>/nodes/<node>`.
+pub fn publish(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    bridged: &BridgedReading,
+) -> Result<()> {
+    publisher::publish(
+        client,
+        Priority::Telemetry,
+        &config.topic(&format!("nodes/{}", bridged.node)),
+        QoS::AtLeastOnce,
+        false,
+        &codec::encode(bridged)?,
+    )?;
+    Ok(())
+}
@@ -1,194 +1,1055 @@
-mod wifi;
+mod alerts;
+mod authorization;
+mod boot;
+mod build_info;
+mod burst;
+mod clock;
+mod cloud;
+mod coap;
+mod codec;
+mod collision;
+mod console;
+mod credentials;
+mod diagnostics;
+mod digital;
+mod dns;
+mod dtls;
+mod energy;
+mod errors;
+mod events;
+mod health;
+mod i2c;
+mod journal;
+mod logging;
+#[cfg(feature = "lora")]
+mod lora;
+mod metrics;
+mod mqtt;
+mod publisher;
+mod pulse;
+mod security;
+mod selftest;
+mod sensor;
+mod sitewise;
+mod startup;
 mod structs;
+mod summary;
+mod twin;
+mod weather;
+mod wifi;
 
-use anyhow::Result;
+use alerts::Alert;
+use anyhow::{bail, Result};
+use authorization::NonceStore;
+use boot::Boot;
+use build_info::BuildInfo;
+use dns::DnsCache;
+use dtls::Psk;
+use energy::EnergyMeter;
+use errors::DeviceError;
+use esp32_aws_core::{
+    backoff::Backoff,
+    cert::{self, Validity},
+    cloud::Inbound,
+    command::Command,
+    diagnostics::authorize,
+    errors::ErrorCode,
+    outbox::Priority,
+    rapid_change::RapidChangeDetector,
+    schedule::Interval,
+    settings::{Settings, Transport},
+    startup::Stage,
+    summary::DailyTrigger,
+    threshold,
+    trend::TrendTracker,
+    weather::Air,
+};
+#[cfg(feature = "lora")]
+use esp32_aws_core::{command::LoraRole, frame::FrameError};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    hal::{delay::Delay, i2c::{config::Config, I2cDriver}, prelude::Peripherals},
-    mqtt::client::{EspMqttClient, EventPayload, MqttClientConfiguration, QoS},
+    hal::{
+        delay::Delay,
+        i2c::{config::Config, I2cDriver},
+        prelude::Peripherals,
+    },
+    mqtt::client::{EspMqttClient, QoS},
+    nvs::EspDefaultNvsPartition,
+    sntp::{EspSntp, SntpConf},
+    wifi::EspWifi,
 };
+use events::Event;
+use i2c::SharedI2c;
 use log::{error, info};
-use bme680::*;
-use serde::Serialize;
+use publisher::Sent;
+use sensor::{Presence, SensorSlot};
+use serde_json::json;
+use std::{
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use structs::{Config as MqttConfig, InboundMessage, MqttMessage, SensorData};
+use twin::TwinCache;
 use wifi::{try_reconnect_wifi, wifi};
-use std::{thread, time::Duration};
-use structs::{Config as MqttConfig, MqttMessage};
 
 const MAX_RETRY_ATTEMPTS: u32 = 3;
-const RETRY_DELAY_MS: u64 = 5000;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Messages received between two iterations of the main loop
+const INBOUND_QUEUE_LEN: usize = 8;
+// Input changes between two iterations of the main loop
+const EDGE_QUEUE_LEN: usize = 16;
+const HEALTH_INTERVAL: Duration = Duration::from_secs(300);
+// Pulses counted since are lost on a reset, more often wears the flash
+const PULSE_PERSIST_INTERVAL: Duration = Duration::from_secs(600);
 
 fn main() -> Result<()> {
     esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+    logging::init();
+
+    let build_info = BuildInfo::get();
+    build_info.log_banner();
 
     let mut delay: Delay = Default::default();
     let peripherals = Peripherals::take()?;
     let sysloop = EspSystemEventLoop::take()?;
 
+    // Optional subsystems that fail to start are left out instead of ending `main`
+    let mut boot = Boot::new()?;
+    let nvs = boot.require("nvs", || Ok(EspDefaultNvsPartition::take()?))?;
+    boot.start("journal", || journal::init(nvs.clone(), build_info.version));
+
     let sda = peripherals.pins.gpio22;
     let scl = peripherals.pins.gpio23;
     let config = Config::new();
-    let mqtt_config = MqttConfig::new();
+    let mut mqtt_config = boot.require("credentials", || {
+        MqttConfig::new(&credentials::load()?, cloud::kind(&nvs)?)
+    })?;
+    // Settings pushed while the device was off or offline apply before the first connect
+    let mut twin = boot.start("settings", || TwinCache::new(nvs.clone()));
+    let mut settings = twin.as_ref().map(TwinCache::load).unwrap_or_default();
+    mqtt_config.persistent_session = settings.persistent_session;
 
-    // Initialize I2C and BME680
-    let i2c = I2cDriver::new(peripherals.i2c0, sda, scl, &config)?;
-    let mut dev = Bme680::init(i2c, &mut delay, I2CAddress::Secondary)
-        .map_err(|e| {
-            error!("Error at bme680 init {e:?}");
-            anyhow::anyhow!("BME680 initialization failed: {:?}", e)
-        })?;
-
-    let settings = SettingsBuilder::new()
-        .with_humidity_oversampling(OversamplingSetting::OS2x)
-        .with_pressure_oversampling(OversamplingSetting::OS4x)
-        .with_temperature_oversampling(OversamplingSetting::OS8x)
-        .with_temperature_filter(IIRFilterSize::Size3)
-        .with_gas_measurement(Duration::from_millis(1500), 320, 25)
-        .with_temperature_offset(-2.2)
-        .with_run_gas(true)
-        .build();
-
-    let profile_dur = dev.get_profile_dur(&settings.0)
-        .map_err(|e| anyhow::anyhow!("Failed to get profile duration: {:?}", e))?;
-    info!("Profile duration {:?}", profile_dur);
-
-    dev.set_sensor_settings(&mut delay, settings)
-        .map_err(|e| anyhow::anyhow!("Failed to apply sensor settings: {:?}", e))?;
-
-    dev.set_sensor_mode(&mut delay, PowerMode::ForcedMode)
-        .map_err(|e| anyhow::anyhow!("Failed to set sensor mode: {:?}", e))?;
-
-    let sensor_settings = dev.get_sensor_settings(settings.1);
-    info!("Sensor settings: {:?}", sensor_settings);
+    let (edge_tx, edge_rx) = mpsc::sync_channel::<digital::Edge>(EDGE_QUEUE_LEN);
+    let mut inputs = boot.start("inputs", || digital::spawn(edge_tx));
+    if let Some(inputs) = inputs.as_mut() {
+        inputs.configure(&settings.digital_inputs);
+    }
+    let mut pulses = boot.start("pulses", || pulse::spawn(nvs.clone()));
+    if let Some(pulses) = pulses.as_mut() {
+        pulses.configure(
+            &weather::pulse_counters(&settings),
+            &settings.digital_inputs,
+        );
+    }
+    let mut station = weather::Station::default();
+    station.configure(&settings.weather_station);
 
-    // Initialize WiFi
-    let mut wifi = wifi(&mqtt_config.ssid, &mqtt_config.password, peripherals.modem, sysloop)?;
-
-    // Create MQTT client configuration
-    let mqtt_client_config = MqttClientConfiguration {
-        client_id: Some(&mqtt_config.client_id),
-        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
-        server_certificate: Some(mqtt_config.server_cert),
-        client_certificate: Some(mqtt_config.client_cert),
-        private_key: Some(mqtt_config.private_key),
-        ..Default::default()
+    let cert_validity = match cert::parse_validity(mqtt_config.client_cert_pem) {
+        Ok(validity) => {
+            info!("Device certificate valid until {}", validity.not_after);
+            Some(validity)
+        }
+        Err(e) => {
+            error!("Could not read device certificate validity: {:?}", e);
+            None
+        }
     };
+    let mut cert_alert_raised = false;
 
-    // Create MQTT client with retry logic
-    let mut retry_count = 0;
-    let mut client = None;
-
-    while retry_count < MAX_RETRY_ATTEMPTS {
-        match EspMqttClient::new_cb(
-            &mqtt_config.mqtts_url,
-            &mqtt_client_config,
-            move |message_event| {
-                match message_event.payload() {
-                    EventPayload::Connected(_) => info!("Connected"),
-                    EventPayload::Subscribed(id) => info!("Subscribed to id: {}", id),
-                    EventPayload::Received { data, .. } => {
-                        if !data.is_empty() {
-                            let mqtt_message: Result<MqttMessage, serde_json::Error> =
-                                serde_json::from_slice(data);
-
-                            match mqtt_message {
-                                Ok(message) => {
-                                    info!("Received: {:?}", message);
-                                }
-                                Err(err) => error!(
-                                    "Could not parse message: {:?}. Err: {}",
-                                    std::str::from_utf8(data).unwrap(),
-                                    err
-                                ),
-                            }
-                        }
+    // Initialize I2C and BME680
+    let i2c = boot.require("sensor_bus", || {
+        Ok(I2cDriver::new(peripherals.i2c0, sda, scl, &config)?)
+    })?;
+    let mut sensor = SensorSlot::new(SharedI2c::new(i2c), settings.sensor_profile, &mut delay);
+
+    // Commands typed on the serial console are executed like the ones received via MQTT
+    let (console_tx, console_rx) = mpsc::channel::<Command>();
+    boot.start("console", || console::spawn(console_tx));
+
+    // A LoRa node never brings up Wi-Fi, a gateway also bridges node readings to MQTT
+    #[cfg(feature = "lora")]
+    let (lora_tx, lora_rx) = mpsc::sync_channel::<lora::Packet>(INBOUND_QUEUE_LEN);
+    #[cfg(feature = "lora")]
+    let mut lora_gateway = None;
+    #[cfg(feature = "lora")]
+    match lora::role(&nvs) {
+        Ok(LoraRole::Off) => {}
+        Ok(role) => {
+            use esp_idf_svc::hal::{
+                prelude::*,
+                spi::{
+                    config::{Config as SpiConfig, DriverConfig},
+                    SpiDeviceDriver,
+                },
+            };
+
+            // SX1276 wiring of the TTGO LoRa32
+            let radio = SpiDeviceDriver::new_single(
+                peripherals.spi2,
+                peripherals.pins.gpio5,
+                peripherals.pins.gpio27,
+                Some(peripherals.pins.gpio19),
+                Some(peripherals.pins.gpio18),
+                &DriverConfig::new(),
+                &SpiConfig::new().baudrate(8.MHz().into()),
+            )
+            .map_err(anyhow::Error::from)
+            .and_then(|spi| lora::Sx127x::new(spi, peripherals.pins.gpio14, &mut delay));
+
+            match (role, radio) {
+                (LoraRole::Node, Ok(radio)) => {
+                    return lora::run_node(
+                        radio,
+                        &mut sensor,
+                        &mut delay,
+                        &mqtt_config,
+                        settings.burst_samples,
+                        &nvs,
+                        &console_rx,
+                    );
+                }
+                (_, Ok(radio)) => match mqtt_config.lora_key {
+                    Some(key) => {
+                        lora_gateway = Some(lora::Gateway::new(&nvs, key)?);
+                        lora::spawn_gateway(radio, lora_tx)?;
                     }
-                    _ => info!("{:?}", message_event.payload()),
-                };
-            },
-        ) {
-            Ok(mqtt_client) => {
-                client = Some(mqtt_client);
-                break;
-            }
-            Err(e) => {
-                error!("Failed to create MQTT client (attempt {}): {:?}", retry_count + 1, e);
-                retry_count += 1;
-                thread::sleep(Duration::from_millis(RETRY_DELAY_MS));
+                    None => error!("No LoRa key provisioned, not bridging LoRa nodes"),
+                },
+                (_, Err(e)) => error!("LoRa radio unavailable, continuing without it: {:?}", e),
             }
         }
+        Err(e) => error!("Failed to read LoRa role: {:?}", e),
+    }
+
+    // After an outage a whole site comes back at once, each device waits its
+    // own delay before Wi-Fi and again before MQTT
+    let power_restored = startup::power_restored();
+    if power_restored {
+        startup::wait(Stage::Wifi, settings.startup.wifi_max_delay_s);
     }
 
-    let mut client = client.ok_or_else(|| anyhow::anyhow!("Failed to create MQTT client after {} attempts", MAX_RETRY_ATTEMPTS))?;
+    // Initialize WiFi
+    let mut wifi = boot.require("wifi", || {
+        wifi(
+            &mqtt_config.ssid,
+            &mqtt_config.password,
+            peripherals.modem,
+            sysloop,
+            nvs.clone(),
+        )
+    })?;
+
+    // Time is needed for timestamps, the self-test and certificate checks
+    let sntp = boot.start("sntp", || {
+        Ok(EspSntp::new_with_callback(
+            &SntpConf::default(),
+            clock::on_sync,
+        )?)
+    });
+
+    let (inbound_tx, inbound_rx) = mpsc::sync_channel::<InboundMessage>(INBOUND_QUEUE_LEN);
+    let connected = Arc::new(AtomicBool::new(false));
+    let mut dns_cache = boot.start("dns_cache", || DnsCache::new(nvs.clone()));
+    let mut energy = boot.start("energy", || EnergyMeter::new(nvs.clone()));
+    let mut nonces = boot
+        .start("nonces", || NonceStore::new(nvs.clone()))
+        .unwrap_or_else(NonceStore::in_memory);
 
-    // Subscribe to MQTT topic with retry logic
-    retry_count = 0;
-    while retry_count < MAX_RETRY_ATTEMPTS {
-        match client.subscribe(&mqtt_config.sub_topic, QoS::AtLeastOnce) {
+    if power_restored {
+        startup::wait(Stage::Mqtt, settings.startup.mqtt_max_delay_s);
+    }
+
+    // Create MQTT client with retry logic
+    let mut client = boot.require("mqtt", || {
+        for (attempt, delay) in Backoff::constant(RETRY_DELAY, MAX_RETRY_ATTEMPTS).enumerate() {
+            match mqtt::connect(&mqtt_config, dns_cache.as_mut(), &inbound_tx, &connected) {
+                Ok(mqtt_client) => return Ok(mqtt_client),
+                Err(e) => {
+                    error!(
+                        "Failed to create MQTT client (attempt {}): {:?}",
+                        attempt + 1,
+                        e
+                    );
+                    thread::sleep(delay);
+                }
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Failed to create MQTT client after {} attempts",
+            MAX_RETRY_ATTEMPTS
+        ))
+    })?;
+    let mut token_renew_at = mqtt::token_renewal(&mqtt_config);
+
+    // Subscribe to MQTT topics with retry logic
+    for (attempt, delay) in Backoff::constant(RETRY_DELAY, MAX_RETRY_ATTEMPTS).enumerate() {
+        match mqtt::subscribe(&mut client, &mqtt_config) {
             Ok(_) => {
-                info!("Successfully subscribed to topic");
+                info!("Successfully subscribed to topics");
                 break;
             }
             Err(e) => {
-                error!("Failed to subscribe (attempt {}): {:?}", retry_count + 1, e);
-                retry_count += 1;
-                thread::sleep(Duration::from_millis(RETRY_DELAY_MS));
+                error!("Failed to subscribe (attempt {}): {:?}", attempt + 1, e);
+                thread::sleep(delay);
             }
         }
     }
 
-    info!("Starting main loop");
+    // Announce the running build so fleet behavior can be correlated with it
+    match publisher::publish(
+        &mut client,
+        Priority::Telemetry,
+        &mqtt_config.topic("birth"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(&build_info)?.as_bytes(),
+    ) {
+        Ok(_) => info!("Published birth message"),
+        Err(e) => error!("Failed to publish birth message: {:?}", e),
+    }
+
+    info!(
+        "Starting main loop, components: {}",
+        serde_json::to_string(boot.readiness())?
+    );
+
+    let started = Instant::now();
+    let mut health_interval = Interval::new(HEALTH_INTERVAL);
+    let mut pulse_persist_interval = Interval::new(PULSE_PERSIST_INTERVAL);
+    let mut summary_trigger = DailyTrigger::default();
+    let mut trends = TrendTracker::new(trend_window(&settings));
+    let mut rapid_changes = RapidChangeDetector::new(settings.rapid_change.thresholds());
+    let mut coap = None;
+    // Sent once per attach, it only changes with the sensor
+    let mut calibration_published = false;
 
     loop {
         delay.delay_ms(5000u32);
 
-        if !wifi.is_connected()? {
-            try_reconnect_wifi(&mut wifi, &mut client, &mqtt_config)?;
+        let wifi_connected = match wifi.is_connected() {
+            Ok(connected) => connected,
+            Err(e) => {
+                error!("Failed to read the Wi-Fi state: {:?}", e);
+                false
+            }
+        };
+        energy::record_cycle(Duration::from_millis(5000), wifi_connected);
+
+        // Also while offline, meters keep counting
+        if let Some(pulses) = pulses.as_mut() {
+            if pulse_persist_interval.is_due(started.elapsed()) {
+                match pulses.persist() {
+                    Ok(_) => pulse_persist_interval.mark(started.elapsed()),
+                    Err(e) => error!("Failed to store pulse totals: {:?}", e),
+                }
+            }
+        }
+
+        if !wifi_connected {
+            reconnect_wifi(&mut wifi, &mut client, &mqtt_config, &mut boot);
             continue;
         }
+        // Until the topics are subscribed again commands and settings do not arrive
+        if !boot.is_ready("mqtt") {
+            match mqtt::subscribe(&mut client, &mqtt_config) {
+                Ok(_) => boot.mark_ready("mqtt"),
+                Err(e) => error!("Failed to resubscribe: {:?}", e),
+            }
+        }
+        publisher::flush(&mut client);
 
-        dev.set_sensor_mode(&mut delay, PowerMode::ForcedMode)
-            .map_err(|e| {
-                error!("Unable to set sensor mode: {:?}", e);
-                anyhow::anyhow!("Failed to set sensor mode: {:?}", e)
-            })?;
+        // The token is fixed when the client is created, so it is replaced before it expires
+        let mut reconnect = token_renew_at.is_some_and(|at| Instant::now() >= at);
+        if reconnect {
+            info!("Access token about to expire, reconnecting");
+        }
+        // So are the client ID and whether the broker keeps the session
+        reconnect |= collision::check(&mut client, &mut mqtt_config, settings.client_id_fallback);
+        if settings.persistent_session != mqtt_config.persistent_session {
+            info!(
+                "Persistent session {}, reconnecting",
+                if settings.persistent_session {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            );
+            mqtt_config.persistent_session = settings.persistent_session;
+            reconnect = true;
+        }
+        if reconnect {
+            match mqtt::connect(&mqtt_config, dns_cache.as_mut(), &inbound_tx, &connected) {
+                Ok(renewed) => {
+                    client = renewed;
+                    if let Err(e) = mqtt::subscribe(&mut client, &mqtt_config) {
+                        error!("Failed to subscribe after reconnecting: {:?}", e);
+                    }
+                    token_renew_at = mqtt::token_renewal(&mqtt_config);
+                }
+                Err(e) => error!("Failed to reconnect: {:?}", e),
+            }
+        }
 
-        let (data, _state) = dev.get_sensor_data(&mut delay)
-            .map_err(|e| {
-                error!("Unable to get sensor data: {:?}", e);
-                anyhow::anyhow!("Failed to get sensor data: {:?}", e)
-            })?;
+        // Console commands are typed on the device and need no expiry
+        let mut commands: Vec<Command> = console_rx.try_iter().collect();
 
-        
-        #[derive(Serialize)]
-        struct SensorData {
-            temperature: u32,
-            humidity: u32,
-            pressure: u32,
-            gas_resistance: u32,
+        for message in inbound_rx.try_iter() {
+            metrics::record_dequeued(message.received_at);
+            match handle_message(
+                &message,
+                &mut client,
+                &mqtt_config,
+                &mut settings,
+                &mut twin,
+                &mut nonces,
+            ) {
+                Ok(Some(command)) => commands.push(command),
+                Ok(None) => {}
+                Err(e) => error!("Failed to handle message on {}: {:?}", message.topic, e),
+            }
         }
 
+        for command in commands {
+            if let Err(denied) = authorize(&command, &settings.diagnostics) {
+                if let Err(e) = diagnostics::deny(&mut client, &mqtt_config, &command, denied) {
+                    error!("Failed to report refused command: {:?}", e);
+                }
+                continue;
+            }
+            if let Err(e) = run_command(
+                &command,
+                &mut client,
+                &mqtt_config,
+                &mut sensor,
+                &mut delay,
+                &nvs,
+                sntp.as_ref(),
+            ) {
+                let device_error = DeviceError::new(
+                    ErrorCode::CommandFailed,
+                    format!("Failed to run command {:?}: {:?}", command, e),
+                    json!({ "command": command.name() }),
+                );
+                errors::report(&mut client, &mqtt_config, device_error);
+            }
+        }
+
+        if let Some(inputs) = inputs.as_mut() {
+            inputs.configure(&settings.digital_inputs);
+        }
+        if let Some(pulses) = pulses.as_mut() {
+            pulses.configure(
+                &weather::pulse_counters(&settings),
+                &settings.digital_inputs,
+            );
+        }
+        station.configure(&settings.weather_station);
+        for edge in edge_rx.try_iter() {
+            if !settings.features.input_events {
+                continue;
+            }
+            let event = Event {
+                timestamp: edge.timestamp,
+                ..Event::new(
+                    "input_changed",
+                    json!({ "input": edge.label, "pin": edge.pin, "state": edge.state }),
+                )
+            };
+            if let Err(e) = events::publish(&mut client, &mqtt_config, event) {
+                error!("Failed to publish input change: {:?}", e);
+            }
+        }
+
+        if health_interval.is_due(started.elapsed()) {
+            if settings.features.health_reports {
+                let report = health::HealthReport::collect(
+                    &build_info,
+                    cert_validity.as_ref(),
+                    energy
+                        .as_mut()
+                        .map(|energy| energy.report(settings.battery_capacity_mah)),
+                    boot.readiness(),
+                );
+                match health::publish(&mut client, &mqtt_config, &report) {
+                    Ok(_) => health_interval.mark(started.elapsed()),
+                    Err(e) => error!("Failed to publish health report: {:?}", e),
+                }
+            } else {
+                health_interval.mark(started.elapsed());
+            }
+
+            if !cert_alert_raised {
+                cert_alert_raised =
+                    check_cert_expiry(&mut client, &mqtt_config, &settings, cert_validity.as_ref());
+            }
+        }
+
+        if !boot.is_ready("clock") && clock::unix_now().is_some() {
+            boot.mark_ready("clock");
+        }
+        if let Some(now) = clock::unix_now() {
+            if summary_trigger.is_due(&settings.daily_summary, now) {
+                // Tried again on the next iteration until it goes through
+                match summary::publish(&mut client, &mqtt_config, now) {
+                    Ok(()) => summary_trigger.mark(now),
+                    Err(e) => error!("Failed to publish daily summary: {:?}", e),
+                }
+            }
+        }
+
+        if let Some(drift) = clock::take_drift_event() {
+            let alert = Alert::new(
+                "clock_drift",
+                format!(
+                    "Clock drifted {} ms ({} ppm), timestamps since {:?} are skewed",
+                    drift.drift_ms, drift.drift_ppm, drift.since
+                ),
+            );
+            if let Err(e) = alerts::raise(&mut client, &mqtt_config, alert) {
+                error!("Failed to raise clock drift alert: {:?}", e);
+            }
+        }
+        clock::update_sync_interval();
+
+        if let Some(batch) = logging::take_tail() {
+            if let Err(e) = publisher::publish(
+                &mut client,
+                Priority::Bulk,
+                &mqtt_config.topic("logs"),
+                QoS::AtMostOnce,
+                false,
+                serde_json::to_string(&batch)?.as_bytes(),
+            ) {
+                error!("Failed to publish log lines: {:?}", e);
+            }
+        }
+
+        #[cfg(feature = "lora")]
+        if let Some(gateway) = lora_gateway.as_mut() {
+            for packet in lora_rx.try_iter() {
+                let bridged = match packet.bridged(gateway, &settings.lora_nodes) {
+                    Ok(bridged) => bridged,
+                    Err(e) => {
+                        let code = e
+                            .downcast_ref::<FrameError>()
+                            .map_or(ErrorCode::FrameDecode, ErrorCode::from);
+                        let device_error = DeviceError::new(
+                            code,
+                            format!("Dropping LoRa frame: {:?}", e),
+                            json!({ "len": packet.data.len(), "rssi": packet.rssi, "snr": packet.snr }),
+                        );
+                        errors::report(&mut client, &mqtt_config, device_error);
+                        continue;
+                    }
+                };
+                if let Err(e) = lora::publish(&mut client, &mqtt_config, &bridged) {
+                    error!("Failed to bridge LoRa frame: {:?}", e);
+                }
+            }
+            gateway.flush();
+        }
+
+        // The sensor can be swapped at runtime, readings pause while it is unplugged
+        sensor.set_profile(settings.sensor_profile, &mut delay);
+        sensor.poll(&mut delay);
+        if let Some(change) = sensor.take_change() {
+            let name = match change {
+                Presence::Attached => "sensor_attached",
+                Presence::Detached => "sensor_detached",
+            };
+            journal::record(name, json!({ "sensor": "bme680" }));
+            if settings.features.sensor_events {
+                if let Err(e) = events::publish(
+                    &mut client,
+                    &mqtt_config,
+                    Event::new(name, json!({ "sensor": "bme680" })),
+                ) {
+                    error!("Failed to publish {} event: {:?}", name, e);
+                }
+            }
+        }
+
+        // Inputs and pulse counters do not depend on the BME680 and are reported without it
+        let mut pulse_readings = pulses
+            .as_mut()
+            .map(pulse::PulseCounters::readings)
+            .unwrap_or_default();
+        let input_states = inputs
+            .as_ref()
+            .map(digital::DigitalInputs::states)
+            .unwrap_or_default();
+
+        // Air values are left out while the sensor is missing or fails to read
+        let readings = if sensor.is_attached() {
+            if settings.reported_values.raw() && !calibration_published {
+                match publish_calibration(&mut client, &mqtt_config, &mut sensor) {
+                    Ok(_) => calibration_published = true,
+                    Err(e) => {
+                        let device_error = DeviceError::new(
+                            ErrorCode::SensorCalibration,
+                            format!("Failed to publish sensor calibration: {:?}", e),
+                            json!({ "sensor": "bme680" }),
+                        );
+                        errors::report(&mut client, &mqtt_config, device_error);
+                    }
+                }
+            }
+
+            let samples = settings.burst_samples.clamp(1, burst::MAX_BURST_SAMPLES);
+            match sensor.read_burst(&mut delay, samples) {
+                Ok(readings) => Some(readings),
+                Err(e) => {
+                    let device_error = DeviceError::new(
+                        ErrorCode::SensorRead,
+                        format!("Failed to read sensor: {:?}", e),
+                        json!({ "sensor": "bme680", "samples": samples }),
+                    );
+                    errors::report(&mut client, &mqtt_config, device_error);
+                    None
+                }
+            }
+        } else {
+            calibration_published = false;
+            None
+        };
+        // The result registers hold the last sample of a burst
+        let raw_wanted = readings.is_some() && settings.reported_values.raw();
+        let raw = match raw_wanted.then(|| sensor.read_raw()) {
+            Some(Ok(raw)) => Some(raw),
+            Some(Err(e)) => {
+                let device_error = DeviceError::new(
+                    ErrorCode::SensorRawRead,
+                    format!("Failed to read raw values: {:?}", e),
+                    json!({ "sensor": "bme680" }),
+                );
+                errors::report(&mut client, &mqtt_config, device_error);
+                None
+            }
+            None => None,
+        };
+        let summary = readings.as_deref().map(burst::summarize);
+        if let Some(summary) = &summary {
+            summary::record_reading(summary);
+            trends.set_window(trend_window(&settings));
+            trends.add(
+                started.elapsed(),
+                summary.temperature.mean,
+                summary.humidity.mean,
+                summary.pressure.mean,
+            );
+            rapid_changes.set_thresholds(settings.rapid_change.thresholds());
+            for change in rapid_changes.add(
+                started.elapsed(),
+                summary.pressure.mean,
+                summary.humidity.mean,
+            ) {
+                let details = json!({
+                    "metric": change.metric,
+                    "from": change.from,
+                    "to": change.to,
+                    "delta": change.delta(),
+                    "over_s": change.over_s,
+                    "deployment": settings.rapid_change.deployment,
+                });
+                if let Err(e) = events::publish(
+                    &mut client,
+                    &mqtt_config,
+                    Event::new("rapid_change", details),
+                ) {
+                    error!("Failed to publish rapid change: {:?}", e);
+                }
+            }
+        }
+
+        let air = summary.as_ref().map(|summary| Air {
+            temperature_c: summary.temperature.mean,
+            humidity_pct: summary.humidity.mean,
+            pressure_hpa: summary.pressure.mean,
+        });
+        if let Some(report) = station.report(&mut pulse_readings, air, clock::unix_now()) {
+            if let Err(e) = weather::publish(&mut client, &mqtt_config, &report) {
+                error!("Failed to publish weather report: {:?}", e);
+            }
+        }
+
+        let converted = settings.reported_values.converted();
+        let reported = summary.as_ref().filter(|_| converted);
+        let multiple = readings.as_ref().is_some_and(|readings| readings.len() > 1);
         let sensor_data = SensorData {
-            temperature: data.temperature_celsius() as u32,
-            humidity: data.humidity_percent() as u32,
-            pressure: data.pressure_hpa() as u32,
-            gas_resistance: data.gas_resistance_ohm() as u32,
+            temperature: reported.map(|summary| summary.temperature.mean),
+            humidity: reported.map(|summary| summary.humidity.mean),
+            pressure: reported.map(|summary| summary.pressure.mean),
+            gas_resistance: reported
+                .and_then(|summary| summary.gas_resistance)
+                .map(|gas| gas.mean),
+            raw,
+            timestamp: clock::unix_now(),
+            clock_suspect: clock::status().suspect,
+            burst: summary.filter(|_| multiple).map(|summary| summary.stats),
+            trend: trends.trend(),
+            inputs: input_states,
+            pulses: pulse_readings,
         };
 
-        let sensor_json = serde_json::to_string(&sensor_data)?;
+        if settings.sitewise.enabled {
+            if let Err(e) =
+                sitewise::publish(&mut client, &mqtt_config, &sensor_data, &settings.sitewise)
+            {
+                error!("Failed to publish SiteWise entries: {:?}", e);
+            }
+        }
 
-        match client.publish(
-            &mqtt_config.pub_topic,
-            QoS::AtLeastOnce,
-            false,
-            sensor_json.as_bytes(),
-        ) {
-            Ok(_) => info!("Successfully published sensor data"),
+        let mut payload = match codec::encode(&sensor_data) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to encode sensor data: {:?}", e);
+                summary::record_publish(false);
+                continue;
+            }
+        };
+        if settings.sign_payloads {
+            payload = match codec::sign(&payload, mqtt_config.signing_key) {
+                Ok(signed) => signed,
+                Err(e) => {
+                    error!("Failed to sign sensor data: {:?}", e);
+                    summary::record_publish(false);
+                    continue;
+                }
+            };
+        }
+
+        if settings.transport == Transport::Coap {
+            // The CoAP thread counts the reading in the summary once it is done with it
+            let pushed = push_coap(&mut coap, mqtt_config.coap_psk, &settings.coap_url, payload);
+            if let Err(e) = pushed {
+                error!("Failed to push sensor data over CoAP: {:?}", e);
+                summary::record_publish(false);
+            }
+            continue;
+        }
+
+        // Counted in the summary by the publisher, a queued reading once it is sent or dropped
+        let published =
+            publisher::publish_reading(&mut client, &mqtt_config.backend.topic(None), &payload);
+        match published {
+            Ok(sent) => {
+                match sent {
+                    Sent::Published => info!("Successfully published sensor data"),
+                    Sent::Queued => info!("Link backed up, queued sensor data"),
+                }
+
+                // Retained, so dashboards connecting mid-stream get the current values right away
+                if settings.publish_latest {
+                    if let Err(e) = publisher::publish(
+                        &mut client,
+                        Priority::Bulk,
+                        &mqtt_config.topic("latest"),
+                        QoS::AtLeastOnce,
+                        true,
+                        &payload,
+                    ) {
+                        error!("Failed to publish latest reading: {:?}", e);
+                    }
+                }
+            }
             Err(e) => {
                 error!("Failed to publish sensor data: {:?}", e);
-                // Attempt to reconnect on publish failure
-                try_reconnect_wifi(&mut wifi, &mut client, &mqtt_config)?;
+                // Attempt to reconnect on publish failure. A backed up link queues
+                // instead of failing and does not get here, the MQTT client
+                // reconnects on its own once it notices the broker is gone.
+                reconnect_wifi(&mut wifi, &mut client, &mqtt_config, &mut boot);
+            }
+        }
+    }
+}
+
+// The loop goes on when the topics cannot be subscribed again, with MQTT
+// marked failed until they are
+fn reconnect_wifi(
+    wifi: &mut Box<EspWifi<'static>>,
+    client: &mut EspMqttClient<'static>,
+    config: &MqttConfig,
+    boot: &mut Boot,
+) {
+    if let Err(e) = try_reconnect_wifi(wifi, client, config) {
+        error!("Failed to resubscribe after reconnecting: {:?}", e);
+        boot.mark_failed("mqtt");
+    }
+}
+
+fn handle_message(
+    message: &InboundMessage,
+    client: &mut EspMqttClient<'static>,
+    config: &MqttConfig,
+    settings: &mut Settings,
+    twin: &mut Option<TwinCache>,
+    nonces: &mut NonceStore,
+) -> Result<Option<Command>> {
+    let inbound = match config.backend.parse(&message.topic, &message.data) {
+        Ok(inbound) => inbound,
+        Err(e) => {
+            let device_error = DeviceError::new(
+                ErrorCode::SettingsDecode,
+                format!("Invalid desired settings: {:?}", e),
+                json!({ "topic": message.topic }),
+            );
+            errors::report(client, config, device_error);
+            return Ok(None);
+        }
+    };
+    let update = match inbound {
+        Inbound::Desired(update) => update,
+        Inbound::Ignored => return Ok(None),
+        Inbound::Message => {
+            if let Ok(command) = serde_json::from_slice::<Command>(&message.data) {
+                let parsed: Result<esp32_aws_core::authorization::Validity, _> =
+                    serde_json::from_slice(&message.data);
+                let validity = match parsed {
+                    Ok(validity) => validity,
+                    Err(e) => {
+                        journal::record(
+                            "command_rejected",
+                            json!({ "command": command.name(), "reason": e.to_string() }),
+                        );
+                        let device_error = DeviceError::new(
+                            ErrorCode::CommandInvalidEnvelope,
+                            format!("Rejected command {}: {}", command.name(), e),
+                            json!({ "command": command.name() }),
+                        );
+                        errors::report(client, config, device_error);
+                        return Ok(None);
+                    }
+                };
+                if let Err(rejected) = nonces.check(&validity, settings.command_window_s) {
+                    journal::record(
+                        "command_rejected",
+                        json!({ "command": command.name(), "reason": rejected.to_string() }),
+                    );
+                    let device_error = DeviceError::new(
+                        ErrorCode::from(&rejected),
+                        format!("Rejected command {}: {}", command.name(), rejected),
+                        json!({ "command": command.name(), "nonce": validity.nonce, "issued_at": validity.issued_at, "expires_at": validity.expires_at }),
+                    );
+                    errors::report(client, config, device_error);
+                    return Ok(None);
+                }
+                return Ok(Some(command));
             }
+
+            match serde_json::from_slice::<MqttMessage>(&message.data) {
+                Ok(message) => info!("Received: {:?}", message),
+                Err(err) => {
+                    let device_error = DeviceError::new(
+                        ErrorCode::MessageDecode,
+                        format!(
+                            "Could not parse message: {:?}. Err: {}",
+                            String::from_utf8_lossy(&message.data),
+                            err
+                        ),
+                        json!({ "topic": message.topic, "len": message.data.len() }),
+                    );
+                    errors::report(client, config, device_error);
+                }
+            }
+            return Ok(None);
         }
+    };
+
+    let applied = twin.as_ref().and_then(TwinCache::version);
+    if update.is_stale(applied) {
+        info!(
+            "Ignoring desired state version {:?}, version {:?} is applied",
+            update.version, applied
+        );
+        return Ok(None);
     }
-}
\ No newline at end of file
+
+    if let Some(desired) = update.state {
+        let changed = match settings.apply(&desired) {
+            Ok(changed) => changed,
+            Err(e) => {
+                let device_error = DeviceError::new(
+                    ErrorCode::SettingsDecode,
+                    format!("Invalid desired settings: {:?}", e),
+                    json!({ "topic": message.topic, "version": update.version }),
+                );
+                errors::report(client, config, device_error);
+                return Ok(None);
+            }
+        };
+        if changed {
+            info!("Settings updated: {:?}", settings);
+            journal::record("settings_changed", json!({ "version": update.version }));
+        }
+        if let Some(twin) = twin.as_mut() {
+            if let Err(e) = twin.store(settings, update.version) {
+                error!("Failed to cache settings: {:?}", e);
+            }
+        }
+
+        // Report even when nothing changed so the shadow delta gets cleared
+        let report = config.backend.report(settings)?;
+        publisher::publish(
+            client,
+            Priority::Response,
+            &report.topic,
+            QoS::AtLeastOnce,
+            false,
+            report.payload.as_bytes(),
+        )?;
+    }
+
+    Ok(None)
+}
+
+fn trend_window(settings: &Settings) -> Duration {
+    Duration::from_secs(settings.trend_window_min as u64 * 60)
+}
+
+/// Publishes the calibration of the sensor retained to `<pub_topic>/calibration`,
+/// to compensate raw values with.
+fn publish_calibration(
+    client: &mut EspMqttClient<'static>,
+    config: &MqttConfig,
+    sensor: &mut SensorSlot,
+) -> Result<()> {
+    let calibration = sensor.calibration()?;
+    publisher::publish(
+        client,
+        Priority::Telemetry,
+        &config.topic("calibration"),
+        QoS::AtLeastOnce,
+        true,
+        serde_json::to_string(&calibration)?.as_bytes(),
+    )?;
+    info!("Published sensor calibration");
+    Ok(())
+}
+
+/// Hands a reading to the CoAP thread, starting it with the first one.
+fn push_coap(
+    coap: &mut Option<SyncSender<coap::Push>>,
+    psk: Option<Psk>,
+    url: &str,
+    payload: Vec<u8>,
+) -> Result<()> {
+    if coap.is_none() {
+        *coap = Some(coap::spawn(psk)?);
+    }
+    let Some(pushes) = coap else {
+        return Ok(());
+    };
+
+    let push = coap::Push {
+        url: url.into(),
+        payload,
+    };
+    match pushes.try_send(push) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Full(_)) => bail!("CoAP gateway is behind, dropping the reading"),
+        Err(TrySendError::Disconnected(_)) => {
+            // Started again with the next reading
+            *coap = None;
+            bail!("CoAP thread stopped")
+        }
+    }
+}
+
+/// Raises an alert when the device certificate is about to expire.
+/// Returns whether the alert was raised.
+fn check_cert_expiry(
+    client: &mut EspMqttClient<'static>,
+    config: &MqttConfig,
+    settings: &Settings,
+    validity: Option<&Validity>,
+) -> bool {
+    let (Some(validity), Some(now)) = (validity, clock::unix_now()) else {
+        return false;
+    };
+
+    let days = validity.days_remaining(now);
+    if !threshold::cert_expiry_due(days, settings.cert_expiry_warn_days) {
+        return false;
+    }
+
+    let alert = Alert::new(
+        "cert_expiry",
+        format!("Device certificate expires in {} days", days),
+    );
+    match alerts::raise(client, config, alert) {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Failed to raise certificate expiry alert: {:?}", e);
+            false
+        }
+    }
+}
+
+fn run_command(
+    command: &Command,
+    client: &mut EspMqttClient<'static>,
+    config: &MqttConfig,
+    sensor: &mut SensorSlot,
+    delay: &mut Delay,
+    nvs: &EspDefaultNvsPartition,
+    sntp: Option<&EspSntp>,
+) -> Result<()> {
+    match command {
+        Command::Selftest => {
+            let report = selftest::run(sensor, delay, nvs, sntp, config.client_cert_pem);
+            info!(
+                "Self-test {}: {:?}",
+                if report.passed { "passed" } else { "failed" },
+                report
+            );
+
+            publisher::publish(
+                client,
+                Priority::Response,
+                &config.topic("selftest"),
+                QoS::AtLeastOnce,
+                false,
+                serde_json::to_string(&report)?.as_bytes(),
+            )?;
+        }
+        Command::LogLevel { module, level } => logging::set_module_level(module, level)?,
+        Command::TailLogs { duration_s, rate } => {
+            logging::start_tail(Duration::from_secs(*duration_s), *rate)
+        }
+        Command::StopLogs => logging::stop_tail(),
+        Command::BatteryReplaced => {
+            energy::reset_battery(nvs)?;
+            info!("Battery replaced, energy use starts over");
+            journal::record("battery_replaced", serde_json::Value::Null);
+        }
+        Command::CloudBackend { backend } => {
+            cloud::set_kind(nvs, *backend)?;
+            info!("Cloud backend set to {:?}, restart to apply", backend);
+            journal::record("cloud_backend_changed", json!({ "backend": backend }));
+        }
+        Command::Journal { count } => journal::publish(client, config, *count)?,
+        Command::I2cRead {
+            address,
+            register,
+            len,
+        } => diagnostics::i2c_read(client, config, &mut sensor.bus(), *address, *register, *len)?,
+        Command::I2cWrite {
+            address,
+            register,
+            data,
+        } => diagnostics::i2c_write(client, config, &mut sensor.bus(), *address, *register, data)?,
+        #[cfg(feature = "lora")]
+        Command::LoraRole { role } => {
+            lora::set_role(nvs, *role)?;
+            info!("LoRa role set to {:?}, restart to apply", role);
+            journal::record("lora_role_changed", json!({ "role": role }));
+        }
+    }
+
+    Ok(())
+}
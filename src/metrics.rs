@@ -0,0 +1,96 @@
+use serde::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Receive side of the MQTT client since boot. A mutex rather than atomics,
+// the ESP32 has no 64 bit atomics for the running totals
+struct Counters {
+    events: u32,
+    handler_total: Duration,
+    handler_max: Duration,
+    received: u32,
+    dropped: u32,
+    dequeued: u32,
+    queue_total: Duration,
+    queue_max: Duration,
+    max_pending: u32,
+}
+
+static COUNTERS: Mutex<Counters> = Mutex::new(Counters {
+    events: 0,
+    handler_total: Duration::ZERO,
+    handler_max: Duration::ZERO,
+    received: 0,
+    dropped: 0,
+    dequeued: 0,
+    queue_total: Duration::ZERO,
+    queue_max: Duration::ZERO,
+    max_pending: 0,
+});
+
+/// How the MQTT event callback and the inbound queue keep up, reported on the health topic.
+#[derive(Serialize, Debug, Clone)]
+pub struct MqttMetrics {
+    /// Events of any kind delivered to the callback
+    pub events: u32,
+    pub handler_avg_us: u32,
+    pub handler_max_us: u32,
+    pub received: u32,
+    /// Messages lost because the inbound queue was full
+    pub dropped: u32,
+    /// Time messages waited in the inbound queue for the main loop
+    pub queue_avg_ms: u32,
+    pub queue_max_ms: u32,
+    pub max_pending: u32,
+}
+
+/// Called at the end of the MQTT event callback with the time it started.
+pub fn record_event(started: Instant) {
+    let elapsed = started.elapsed();
+    let mut counters = COUNTERS.lock().unwrap();
+
+    counters.events += 1;
+    counters.handler_total += elapsed;
+    counters.handler_max = counters.handler_max.max(elapsed);
+}
+
+/// Called from the MQTT event callback before a message is put on the inbound queue.
+pub fn record_received() {
+    let mut counters = COUNTERS.lock().unwrap();
+
+    counters.received += 1;
+    let pending = counters.received - counters.dropped - counters.dequeued;
+    counters.max_pending = counters.max_pending.max(pending);
+}
+
+/// Called from the MQTT event callback after [`record_received`] when the inbound queue was full.
+pub fn record_dropped() {
+    COUNTERS.lock().unwrap().dropped += 1;
+}
+
+/// Called by the main loop for every message taken off the inbound queue.
+pub fn record_dequeued(received_at: Instant) {
+    let waited = received_at.elapsed();
+    let mut counters = COUNTERS.lock().unwrap();
+
+    counters.dequeued += 1;
+    counters.queue_total += waited;
+    counters.queue_max = counters.queue_max.max(waited);
+}
+
+pub fn snapshot() -> MqttMetrics {
+    let counters = COUNTERS.lock().unwrap();
+
+    MqttMetrics {
+        events: counters.events,
+        handler_avg_us: (counters.handler_total / counters.events.max(1)).as_micros() as u32,
+        handler_max_us: counters.handler_max.as_micros() as u32,
+        received: counters.received,
+        dropped: counters.dropped,
+        queue_avg_ms: (counters.queue_total / counters.dequeued.max(1)).as_millis() as u32,
+        queue_max_ms: counters.queue_max.as_millis() as u32,
+        max_pending: counters.max_pending,
+    }
+}
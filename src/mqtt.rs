@@ -0,0 +1,253 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, EventPayload, MqttClientConfiguration, QoS},
+    sys::EspError,
+};
+use log::{error, info, warn};
+use serde_json::Value;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::SyncSender,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock, collision,
+    dns::{self, DnsCache},
+    energy, journal, metrics, publisher,
+    structs::{Config, InboundMessage},
+};
+
+// How long a cached broker address gets to connect before falling back to DNS
+const PINNED_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// User name and password the backend wants next to the TLS connection.
+struct Auth {
+    username: Option<String>,
+    /// A token, the client certificate is not presented when set
+    password: Option<String>,
+}
+
+impl Auth {
+    fn new(config: &Config) -> Result<Self> {
+        let now = clock::unix_now();
+        if config.backend.token_lifetime().is_some() && now.is_none() {
+            bail!("Time not synced yet, cannot issue an access token");
+        }
+
+        Ok(Auth {
+            username: config.backend.username(),
+            password: config.backend.password(now.unwrap_or(0)),
+        })
+    }
+}
+
+/// Creates the MQTT client, trying the cached broker address first.
+///
+/// The cached address answers the DNS lookup of the client, the URL keeps the
+/// host name for the certificate check. When no address is cached or the cached
+/// one does not connect in time, the host name is resolved again.
+pub fn connect(
+    config: &Config<'static>,
+    dns_cache: Option<&mut DnsCache>,
+    inbound: &SyncSender<InboundMessage>,
+    connected: &Arc<AtomicBool>,
+) -> Result<EspMqttClient<'static>> {
+    let auth = Auth::new(config)?;
+    let host = dns::host(&config.mqtts_url)?;
+
+    let Some(dns_cache) = dns_cache else {
+        return Ok(new_client(
+            &config.mqtts_url,
+            &client_configuration(config, &auth),
+            inbound,
+            connected,
+        )?);
+    };
+
+    // While the previous client is still connected the flag says nothing about the new one
+    let cached = dns_cache
+        .get(host)
+        .filter(|_| !connected.load(Ordering::Relaxed));
+    if let Some(ip) = cached {
+        info!("Connecting to cached broker address {}", ip);
+        dns::pin(host, ip);
+        let client = new_client(
+            &config.mqtts_url,
+            &client_configuration(config, &auth),
+            inbound,
+            connected,
+        );
+        // Reconnects of the client resolve the host name again
+        let ready = match client {
+            Ok(client) if wait_connected(connected, PINNED_CONNECT_TIMEOUT) => Some(client),
+            _ => None,
+        };
+        dns::unpin();
+        if let Some(client) = ready {
+            return Ok(client);
+        }
+
+        warn!(
+            "Cached broker address {} did not connect, resolving {}",
+            ip, host
+        );
+        if let Err(e) = dns_cache.invalidate() {
+            error!("Failed to invalidate DNS cache: {:?}", e);
+        }
+    }
+
+    match dns::resolve(host) {
+        Ok(ip) => {
+            info!("Resolved {} to {}", host, ip);
+            if let Err(e) = dns_cache.store(host, ip) {
+                error!("Failed to cache broker address: {:?}", e);
+            }
+        }
+        Err(e) => warn!("Could not resolve {}: {:?}", host, e),
+    }
+
+    Ok(new_client(
+        &config.mqtts_url,
+        &client_configuration(config, &auth),
+        inbound,
+        connected,
+    )?)
+}
+
+/// Waits up to `timeout` for the connected callback.
+fn wait_connected(connected: &AtomicBool, timeout: Duration) -> bool {
+    let started = Instant::now();
+
+    while !connected.load(Ordering::Relaxed) {
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+fn client_configuration<'a>(
+    config: &'a Config<'static>,
+    auth: &'a Auth,
+) -> MqttClientConfiguration<'a> {
+    let use_certificate = auth.password.is_none();
+
+    MqttClientConfiguration {
+        client_id: Some(&config.client_id),
+        disable_clean_session: config.persistent_session,
+        username: auth.username.as_deref(),
+        password: auth.password.as_deref(),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        server_certificate: Some(config.server_cert),
+        client_certificate: use_certificate.then_some(config.client_cert),
+        private_key: use_certificate.then_some(config.private_key),
+        ..Default::default()
+    }
+}
+
+fn new_client(
+    url: &str,
+    conf: &MqttClientConfiguration,
+    inbound: &SyncSender<InboundMessage>,
+    connected: &Arc<AtomicBool>,
+) -> Result<EspMqttClient<'static>, EspError> {
+    // Received messages are handled by the main loop, the callback runs on the MQTT task
+    let inbound = inbound.clone();
+    let connected = connected.clone();
+    let persistent = conf.disable_clean_session;
+    // A session the broker could only have kept from an earlier connection of this client
+    let mut reconnecting = false;
+
+    EspMqttClient::new_cb(url, conf, move |message_event| {
+        let started = Instant::now();
+
+        match message_event.payload() {
+            EventPayload::Connected(session_present) => {
+                info!("Connected, session present: {}", session_present);
+                if !connected.swap(true, Ordering::Relaxed) {
+                    journal::record("mqtt_connected", Value::Null);
+                    collision::record_connected();
+                }
+                if persistent && reconnecting && !session_present {
+                    warn!("Broker did not keep the session, messages sent while offline are lost");
+                    journal::record("mqtt_session_lost", Value::Null);
+                }
+                reconnecting = true;
+
+                let resumed = publisher::record_connected();
+                if resumed > 0 {
+                    info!("{} publishes still in flight after connecting", resumed);
+                }
+            }
+            EventPayload::Disconnected => {
+                info!("Disconnected");
+                // Failed reconnect attempts report a disconnect each, only the first is kept
+                if connected.swap(false, Ordering::Relaxed) {
+                    journal::record("mqtt_disconnected", Value::Null);
+                    collision::record_disconnected();
+                }
+            }
+            EventPayload::Subscribed(id) => info!("Subscribed to id: {}", id),
+            EventPayload::Published(id) => {
+                info!("Published id: {}", id);
+                energy::record_publish();
+                publisher::record_acked(id);
+            }
+            EventPayload::Deleted(id) => {
+                warn!("Message {} expired before the broker acknowledged it", id);
+                publisher::record_acked(id);
+            }
+            EventPayload::Received {
+                topic: Some(topic),
+                data,
+                ..
+            } => {
+                if !data.is_empty() {
+                    let message = InboundMessage {
+                        topic: topic.into(),
+                        data: data.to_vec(),
+                        received_at: started,
+                    };
+
+                    // Counted before it is queued, the main loop may take it off right away
+                    metrics::record_received();
+                    if inbound.try_send(message).is_err() {
+                        metrics::record_dropped();
+                        error!("Inbound queue full, dropping message on {}", topic);
+                    }
+                }
+            }
+            _ => info!("{:?}", message_event.payload()),
+        };
+
+        metrics::record_event(started);
+    })
+}
+
+/// Subscribes to the command topic and the device shadow or twin, then asks for
+/// the current document so desired settings are applied after every (re)connect.
+pub fn subscribe(client: &mut EspMqttClient<'static>, config: &Config) -> Result<(), EspError> {
+    for topic in config.backend.subscriptions() {
+        client.subscribe(&topic, QoS::AtLeastOnce)?;
+    }
+
+    if let Some(topic) = config.backend.twin_request() {
+        info!("Requesting desired settings");
+        client.publish(&topic, QoS::AtLeastOnce, false, &[])?;
+    }
+    Ok(())
+}
+
+/// When the client has to be created again with a fresh access token, if it uses one.
+pub fn token_renewal(config: &Config) -> Option<Instant> {
+    config
+        .backend
+        .token_lifetime()
+        .map(|lifetime| Instant::now() + Duration::from_secs(lifetime) * 9 / 10)
+}
@@ -0,0 +1,175 @@
+use esp32_aws_core::outbox::{Dropped, InFlight, Outbox, Priority};
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, MessageId, QoS},
+    sys::{esp_timer_get_time, EspError},
+};
+use log::warn;
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+
+use crate::summary;
+
+// About 10 kB of readings, the heap has to hold them while the link is slow
+const CAPACITY: usize = 32;
+// Unacknowledged publishes until the link counts as backed up
+const MAX_IN_FLIGHT: usize = 8;
+// CONFIG_MQTT_OUTBOX_EXPIRED_TIMEOUT_MS, after which the client drops unacknowledged messages
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(120);
+
+struct Message {
+    topic: String,
+    qos: QoS,
+    retain: bool,
+    payload: Vec<u8>,
+    /// A reading, counted in the daily summary once it is sent or dropped
+    reading: bool,
+}
+
+/// What became of a message `publish` accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sent {
+    /// Handed to the client
+    Published,
+    /// Waiting in the outbox, from where it may still be dropped to make room
+    Queued,
+}
+
+// Separate locks, the MQTT task acknowledges while the main loop may be publishing
+static OUTBOX: Mutex<Outbox<Message>> = Mutex::new(Outbox::new(CAPACITY));
+static IN_FLIGHT: Mutex<InFlight> = Mutex::new(InFlight::new(IN_FLIGHT_TIMEOUT));
+
+/// How the outgoing side keeps up, reported on the health topic.
+#[derive(Serialize, Debug, Clone)]
+pub struct OutboxMetrics {
+    pub queued: u32,
+    pub in_flight: u32,
+    /// Messages given up since boot because the outbox was full, by priority
+    pub dropped: Dropped,
+}
+
+fn now() -> Duration {
+    Duration::from_micros(unsafe { esp_timer_get_time() } as u64)
+}
+
+fn backed_up() -> bool {
+    IN_FLIGHT.lock().unwrap().count(now()) >= MAX_IN_FLIGHT
+}
+
+fn send(client: &mut EspMqttClient<'static>, message: &Message) -> Result<(), EspError> {
+    let id = client.publish(
+        &message.topic,
+        message.qos,
+        message.retain,
+        &message.payload,
+    )?;
+    // Only QoS 1 and 2 are acknowledged
+    if message.qos != QoS::AtMostOnce {
+        IN_FLIGHT.lock().unwrap().sent(id, now());
+    }
+    Ok(())
+}
+
+/// Publishes right away while the link keeps up. Once too many publishes wait
+/// for their acknowledgement the message is queued by `priority` instead, and
+/// goes out with `flush`.
+pub fn publish(
+    client: &mut EspMqttClient<'static>,
+    priority: Priority,
+    topic: &str,
+    qos: QoS,
+    retain: bool,
+    payload: &[u8],
+) -> Result<Sent, EspError> {
+    let message = Message {
+        topic: topic.into(),
+        qos,
+        retain,
+        payload: payload.to_vec(),
+        reading: false,
+    };
+    enqueue(client, priority, message)
+}
+
+/// Publishes a reading like `publish` and counts it in the daily summary: as
+/// sent once the client has it, as failed when publishing fails or the
+/// outbox drops it.
+pub fn publish_reading(
+    client: &mut EspMqttClient<'static>,
+    topic: &str,
+    payload: &[u8],
+) -> Result<Sent, EspError> {
+    let message = Message {
+        topic: topic.into(),
+        qos: QoS::AtLeastOnce,
+        retain: false,
+        payload: payload.to_vec(),
+        reading: true,
+    };
+    let sent = enqueue(client, Priority::Telemetry, message);
+    match sent {
+        Ok(Sent::Published) => summary::record_publish(true),
+        Ok(Sent::Queued) => {}
+        Err(_) => summary::record_publish(false),
+    }
+    sent
+}
+
+fn enqueue(
+    client: &mut EspMqttClient<'static>,
+    priority: Priority,
+    message: Message,
+) -> Result<Sent, EspError> {
+    // Queued messages go first, whatever their priority
+    if OUTBOX.lock().unwrap().is_empty() && !backed_up() {
+        send(client, &message)?;
+        return Ok(Sent::Published);
+    }
+    let dropped = OUTBOX.lock().unwrap().push(priority, message);
+    for message in dropped.iter().filter(|message| message.reading) {
+        warn!("Outbox full, dropping reading on {}", message.topic);
+        summary::record_publish(false);
+    }
+    Ok(Sent::Queued)
+}
+
+/// Sends queued messages, the most important first, as far as the link keeps up.
+pub fn flush(client: &mut EspMqttClient<'static>) {
+    while !backed_up() {
+        let Some((priority, message)) = OUTBOX.lock().unwrap().pop() else {
+            return;
+        };
+        if let Err(e) = send(client, &message) {
+            warn!(
+                "Failed to publish queued message on {}: {:?}",
+                message.topic, e
+            );
+            OUTBOX.lock().unwrap().requeue(priority, message);
+            return;
+        }
+        if message.reading {
+            summary::record_publish(true);
+        }
+    }
+}
+
+/// Called from the MQTT event callback when the broker acknowledged a message,
+/// or the client gave up on it.
+pub fn record_acked(id: MessageId) {
+    IN_FLIGHT.lock().unwrap().acked(id);
+}
+
+/// Called from the MQTT event callback on every connect. The client sends the
+/// unacknowledged messages again, so they stay in flight. Returns how many.
+pub fn record_connected() -> usize {
+    IN_FLIGHT.lock().unwrap().resume(now())
+}
+
+pub fn snapshot() -> OutboxMetrics {
+    let outbox = OUTBOX.lock().unwrap();
+
+    OutboxMetrics {
+        queued: outbox.len() as u32,
+        in_flight: IN_FLIGHT.lock().unwrap().count(now()) as u32,
+        dropped: outbox.dropped(),
+    }
+}
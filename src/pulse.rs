@@ -0,0 +1,280 @@
+use anyhow::{bail, Result};
+use esp32_aws_core::{
+    digital::DigitalInput,
+    pulse::{self, PulseCounter, PulseReading, PulseTotal, COUNTER_LIMIT},
+};
+use esp_idf_svc::{
+    hal::{
+        gpio::AnyInputPin,
+        pcnt::{
+            PcntChannel, PcntChannelConfig, PcntControlMode, PcntCountMode, PcntDriver, PinIndex,
+            PCNT0, PCNT1, PCNT2, PCNT3, PCNT4, PCNT5, PCNT6, PCNT7,
+        },
+    },
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+};
+use log::{error, info, warn};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::digital::RESERVED_PINS;
+
+const NVS_NAMESPACE: &str = "pulse";
+// Totals by label
+const TOTALS_KEY: &str = "totals";
+// The counter wraps after 32767 pulses, a meter takes far longer than this for that
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Unit {
+    counter: PulseCounter,
+    driver: PcntDriver<'static>,
+    total: PulseTotal,
+}
+
+impl Unit {
+    fn open(index: usize, counter: &PulseCounter, total: u64) -> Result<Self> {
+        // Only pins `validate` accepted get here, none of them is driven by another peripheral
+        let pin = unsafe { AnyInputPin::new(counter.pin as i32) };
+        macro_rules! driver {
+            ($pcnt:ident) => {
+                PcntDriver::new(
+                    unsafe { $pcnt::new() },
+                    Some(pin),
+                    None::<AnyInputPin>,
+                    None::<AnyInputPin>,
+                    None::<AnyInputPin>,
+                )?
+            };
+        }
+        let mut driver = match index {
+            0 => driver!(PCNT0),
+            1 => driver!(PCNT1),
+            2 => driver!(PCNT2),
+            3 => driver!(PCNT3),
+            4 => driver!(PCNT4),
+            5 => driver!(PCNT5),
+            6 => driver!(PCNT6),
+            7 => driver!(PCNT7),
+            _ => bail!("No pulse counter unit {}", index),
+        };
+
+        // Counts rising edges, the input has the pull-up of the driver for open collector outputs
+        driver.channel_config(
+            PcntChannel::Channel0,
+            PinIndex::Pin0,
+            PinIndex::Pin1,
+            &PcntChannelConfig {
+                lctrl_mode: PcntControlMode::Keep,
+                hctrl_mode: PcntControlMode::Keep,
+                pos_mode: PcntCountMode::Increment,
+                neg_mode: PcntCountMode::Hold,
+                counter_h_lim: COUNTER_LIMIT as i16,
+                counter_l_lim: 0,
+            },
+        )?;
+        match counter.filter_cycles() {
+            0 => driver.filter_disable()?,
+            cycles => {
+                driver.set_filter_value(cycles)?;
+                driver.filter_enable()?;
+            }
+        }
+        driver.counter_pause()?;
+        driver.counter_clear()?;
+        driver.counter_resume()?;
+
+        Ok(Unit {
+            counter: counter.clone(),
+            driver,
+            total: PulseTotal::new(total),
+        })
+    }
+}
+
+/// Handle to the thread reading the pulse counters.
+pub struct PulseCounters {
+    configured: Vec<PulseCounter>,
+    // Last configuration that passed validation
+    active: Vec<PulseCounter>,
+    config_tx: Sender<Vec<PulseCounter>>,
+    totals: Arc<Mutex<BTreeMap<String, u64>>>,
+    // Totals and when they were taken for the rates of the next readings
+    previous: BTreeMap<String, (u64, Instant)>,
+    nvs: EspNvs<NvsDefault>,
+    persisted: BTreeMap<String, u64>,
+}
+
+/// Starts counting from the totals kept in NVS.
+pub fn spawn(partition: EspDefaultNvsPartition) -> Result<PulseCounters> {
+    let nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+    let persisted = match read_totals(&nvs) {
+        Ok(totals) => totals,
+        Err(e) => {
+            warn!("Ignoring stored pulse totals: {:?}", e);
+            BTreeMap::new()
+        }
+    };
+
+    let (config_tx, config_rx) = mpsc::channel();
+    let totals = Arc::new(Mutex::new(persisted.clone()));
+    let shared = totals.clone();
+
+    thread::Builder::new()
+        .name("pulses".into())
+        .stack_size(4096)
+        .spawn(move || poll(config_rx, shared))?;
+
+    Ok(PulseCounters {
+        configured: Vec::new(),
+        active: Vec::new(),
+        config_tx,
+        totals,
+        previous: BTreeMap::new(),
+        nvs,
+        persisted,
+    })
+}
+
+fn read_totals(nvs: &EspNvs<NvsDefault>) -> Result<BTreeMap<String, u64>> {
+    let Some(len) = nvs.blob_len(TOTALS_KEY)? else {
+        return Ok(BTreeMap::new());
+    };
+    let mut buf = vec![0; len];
+    let Some(data) = nvs.get_blob(TOTALS_KEY, &mut buf)? else {
+        return Ok(BTreeMap::new());
+    };
+
+    Ok(serde_json::from_slice(data)?)
+}
+
+impl PulseCounters {
+    /// Hands a changed configuration to the counting thread. An invalid one is
+    /// rejected as a whole and the counters keep running as before.
+    pub fn configure(&mut self, counters: &[PulseCounter], inputs: &[DigitalInput]) {
+        if counters == self.configured {
+            return;
+        }
+        self.configured = counters.to_vec();
+
+        match validate(counters, inputs) {
+            Ok(_) => {
+                info!("Configuring {} pulse counters", counters.len());
+                self.active = counters.to_vec();
+                let _ = self.config_tx.send(counters.to_vec());
+            }
+            Err(e) => error!("Pulse counters not changed: {}", e),
+        }
+    }
+
+    /// Count, total and rate since the previous call of every counter by label.
+    pub fn readings(&mut self) -> BTreeMap<String, PulseReading> {
+        let now = Instant::now();
+        let totals = self.totals.lock().unwrap().clone();
+
+        let readings = self
+            .active
+            .iter()
+            .filter_map(|counter| {
+                let count = *totals.get(&counter.label)?;
+                let (previous, at) = self
+                    .previous
+                    .get(&counter.label)
+                    .copied()
+                    .unwrap_or((count, now));
+                let reading = counter.reading(
+                    count,
+                    count.saturating_sub(previous),
+                    now.duration_since(at).as_millis() as u64,
+                );
+                Some((counter.label.clone(), reading))
+            })
+            .collect();
+
+        self.previous = totals
+            .into_iter()
+            .map(|(label, count)| (label, (count, now)))
+            .collect();
+        readings
+    }
+
+    /// Writes the totals to NVS when they changed. Pulses counted since the last
+    /// call are lost on a reset, so this runs periodically rather than on every
+    /// pulse to spare the flash.
+    pub fn persist(&mut self) -> Result<()> {
+        let totals = self.totals.lock().unwrap().clone();
+        if totals == self.persisted {
+            return Ok(());
+        }
+
+        self.nvs
+            .set_blob(TOTALS_KEY, &serde_json::to_vec(&totals)?)?;
+        self.persisted = totals;
+        Ok(())
+    }
+}
+
+fn validate(counters: &[PulseCounter], inputs: &[DigitalInput]) -> Result<()> {
+    pulse::validate(counters, inputs)?;
+
+    if let Some(counter) = counters
+        .iter()
+        .find(|counter| RESERVED_PINS.contains(&counter.pin))
+    {
+        bail!(
+            "GPIO {} of {} is already in use",
+            counter.pin,
+            counter.label
+        );
+    }
+    Ok(())
+}
+
+fn poll(config_rx: Receiver<Vec<PulseCounter>>, totals: Arc<Mutex<BTreeMap<String, u64>>>) {
+    let mut units: Vec<Unit> = Vec::new();
+
+    loop {
+        if let Some(counters) = config_rx.try_iter().last() {
+            // Releases the units before they are configured again
+            units.clear();
+            let mut totals = totals.lock().unwrap();
+            // Totals of removed counters are dropped, one added again starts from zero
+            totals.retain(|label, _| counters.iter().any(|counter| &counter.label == label));
+
+            for (index, counter) in counters.iter().enumerate() {
+                let total = *totals.entry(counter.label.clone()).or_insert(0);
+                match Unit::open(index, counter, total) {
+                    Ok(unit) => units.push(unit),
+                    Err(e) => error!(
+                        "Failed to set up pulse counter {} on GPIO {}: {:?}",
+                        counter.label, counter.pin, e
+                    ),
+                }
+            }
+        }
+
+        for unit in &mut units {
+            match unit.driver.get_counter_value() {
+                Ok(count) => {
+                    let total = unit.total.update(count as u16);
+                    totals
+                        .lock()
+                        .unwrap()
+                        .insert(unit.counter.label.clone(), total);
+                }
+                Err(e) => warn!(
+                    "Failed to read pulse counter {}: {:?}",
+                    unit.counter.label, e
+                ),
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,74 @@
+// The esp_idf_* cfgs are set from the sdkconfig by embuild
+#![allow(unexpected_cfgs)]
+
+use serde::Serialize;
+use std::ffi::c_uint;
+
+// From esp_flash_encrypt.h and esp_secure_boot.h, which the bindings do not include
+extern "C" {
+    fn esp_flash_encryption_enabled() -> bool;
+    fn esp_get_flash_encryption_mode() -> c_uint;
+    fn esp_secure_boot_enabled() -> bool;
+}
+
+// esp_flash_enc_mode_t
+const FLASH_ENC_MODE_RELEASE: c_uint = 2;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashEncryption {
+    Disabled,
+    /// Encrypted, but plaintext can still be reflashed over UART
+    Development,
+    Release,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialSource {
+    /// Compiled into the firmware image
+    Embedded,
+    EncryptedNvs,
+}
+
+/// Security configuration of the device, reported for compliance audits.
+///
+/// Secure boot and flash encryption are read from the eFuses, so an image built
+/// for them but flashed to a chip where the bootloader never burned them is
+/// reported as it runs. The other fields come from the sdkconfig.
+#[derive(Serialize, Debug, Clone)]
+pub struct SecurityStatus {
+    pub secure_boot: bool,
+    /// Built with `CONFIG_SECURE_SIGNED_ON_UPDATE`, the check is up to the OTA
+    /// functions of ESP-IDF and the firmware does not download updates itself
+    pub update_signing_configured: bool,
+    pub flash_encryption: FlashEncryption,
+    /// Built to keep NVS encrypted, which needs flash encryption on to protect anything
+    pub nvs_encryption: bool,
+    pub credentials: CredentialSource,
+}
+
+pub fn status() -> SecurityStatus {
+    SecurityStatus {
+        secure_boot: unsafe { esp_secure_boot_enabled() },
+        update_signing_configured: cfg!(esp_idf_secure_signed_on_update),
+        flash_encryption: flash_encryption(),
+        nvs_encryption: cfg!(esp_idf_nvs_encryption),
+        credentials: if cfg!(feature = "encrypted-credentials") {
+            CredentialSource::EncryptedNvs
+        } else {
+            CredentialSource::Embedded
+        },
+    }
+}
+
+/// The flash encryption state burned into the eFuses.
+pub fn flash_encryption() -> FlashEncryption {
+    if !unsafe { esp_flash_encryption_enabled() } {
+        FlashEncryption::Disabled
+    } else if unsafe { esp_get_flash_encryption_mode() } == FLASH_ENC_MODE_RELEASE {
+        FlashEncryption::Release
+    } else {
+        FlashEncryption::Development
+    }
+}
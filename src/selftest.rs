@@ -0,0 +1,136 @@
+use anyhow::{anyhow, bail, Result};
+use esp32_aws_core::cert;
+use esp_idf_svc::{
+    hal::delay::Delay,
+    nvs::{EspDefaultNvsPartition, EspNvs},
+    sntp::EspSntp,
+    sys::{esp, esp_ota_get_running_partition, esp_partition_read},
+};
+use serde::Serialize;
+use std::time::Instant;
+
+use crate::{
+    clock,
+    sensor::{self, SensorSlot},
+};
+
+const NVS_NAMESPACE: &str = "selftest";
+const NVS_KEY: &str = "counter";
+// First byte of every ESP application image
+const IMAGE_MAGIC: u8 = 0xe9;
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub passed: bool,
+    pub duration_ms: u64,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Exercises the hardware and services the firmware depends on.
+pub fn run(
+    sensor: &mut SensorSlot,
+    delay: &mut Delay,
+    nvs: &EspDefaultNvsPartition,
+    sntp: Option<&EspSntp>,
+    client_cert: &[u8],
+) -> Report {
+    let started = Instant::now();
+
+    let checks = vec![
+        check("sensor", || check_sensor(sensor, delay)),
+        check("nvs", || check_nvs(nvs)),
+        check("flash", check_flash),
+        check("time_sync", || check_time_sync(sntp)),
+        check("certificate", || check_certificate(client_cert)),
+    ];
+
+    Report {
+        passed: checks.iter().all(|check| check.passed),
+        duration_ms: started.elapsed().as_millis() as u64,
+        checks,
+    }
+}
+
+fn check(name: &'static str, test: impl FnOnce() -> Result<()>) -> Check {
+    let started = Instant::now();
+    let result = test();
+
+    Check {
+        name,
+        passed: result.is_ok(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+fn check_sensor(sensor: &mut SensorSlot, delay: &mut Delay) -> Result<()> {
+    let data = sensor.read(delay)?;
+    if !sensor::is_plausible(&data) {
+        bail!("Implausible reading: {:?}", data);
+    }
+    Ok(())
+}
+
+fn check_nvs(partition: &EspDefaultNvsPartition) -> Result<()> {
+    let nvs = EspNvs::new(partition.clone(), NVS_NAMESPACE, true)?;
+
+    let value = nvs.get_u32(NVS_KEY)?.unwrap_or(0).wrapping_add(1);
+    nvs.set_u32(NVS_KEY, value)?;
+
+    if nvs.get_u32(NVS_KEY)? != Some(value) {
+        bail!("Read back a different value than written");
+    }
+    Ok(())
+}
+
+fn check_flash() -> Result<()> {
+    let partition = unsafe { esp_ota_get_running_partition() };
+    if partition.is_null() {
+        bail!("Running partition not found");
+    }
+
+    let mut header = [0u8; 1];
+    esp!(unsafe { esp_partition_read(partition, 0, header.as_mut_ptr().cast(), header.len()) })?;
+
+    if header[0] != IMAGE_MAGIC {
+        bail!("Unexpected image header {:#04x}", header[0]);
+    }
+    Ok(())
+}
+
+fn check_time_sync(sntp: Option<&EspSntp>) -> Result<()> {
+    sntp.ok_or_else(|| anyhow!("SNTP client not running"))?;
+
+    // The SNTP status only reads completed until the next sync starts, the clock keeps the last one
+    let status = clock::status();
+    if status.suspect {
+        bail!("Clock drifting by {} ppm", status.drift_ppm.unwrap_or(0));
+    }
+    match (status.last_good_sync, clock::unix_now()) {
+        (Some(_), Some(_)) => Ok(()),
+        _ => bail!("Time not synchronized"),
+    }
+}
+
+fn check_certificate(client_cert: &[u8]) -> Result<()> {
+    let validity = cert::parse_validity(client_cert)?;
+    let now = clock::unix_now().ok_or_else(|| anyhow!("Time not set"))?;
+
+    if !validity.contains(now) {
+        bail!(
+            "Certificate not valid now, valid from {} until {}",
+            validity.not_before,
+            validity.not_after
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Result};
+use bme680::*;
+use embedded_hal::blocking::i2c::WriteRead;
+use esp32_aws_core::{
+    raw::{self, Calibration, RawValues},
+    settings::SensorProfile,
+    threshold,
+};
+use esp_idf_svc::hal::delay::Delay;
+use log::{error, info, warn};
+use std::time::{Duration, Instant};
+
+use crate::i2c::SharedI2c;
+
+pub type Sensor = Bme680<SharedI2c, Delay>;
+
+const ADDRESS: I2CAddress = I2CAddress::Secondary;
+const CHIP_ID_REGISTER: u8 = 0xd0;
+// How often an absent sensor is looked for
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+const TEMPERATURE_OFFSET_C: f32 = -2.2;
+// Heater control bit that turns the gas plate heater off
+const HEATER_OFF: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    Attached,
+    Detached,
+}
+
+/// The BME680 position on the I2C bus, which may or may not have a sensor plugged in.
+pub struct SensorSlot {
+    bus: SharedI2c,
+    dev: Option<Sensor>,
+    profile: SensorProfile,
+    last_probe: Instant,
+    change: Option<Presence>,
+}
+
+impl SensorSlot {
+    pub fn new(bus: SharedI2c, profile: SensorProfile, delay: &mut Delay) -> Self {
+        let dev = match init(bus.clone(), profile, delay) {
+            Ok(dev) => Some(dev),
+            Err(e) => {
+                warn!("BME680 not available, will keep probing: {:?}", e);
+                None
+            }
+        };
+
+        SensorSlot {
+            bus,
+            dev,
+            profile,
+            last_probe: Instant::now(),
+            change: None,
+        }
+    }
+
+    /// Handle to the bus the sensor is on, for talking to other devices on it.
+    pub fn bus(&self) -> SharedI2c {
+        self.bus.clone()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.dev.is_some()
+    }
+
+    /// Switches the measurement profile, applied right away when the sensor is
+    /// attached and otherwise once it shows up.
+    pub fn set_profile(&mut self, profile: SensorProfile, delay: &mut Delay) {
+        if profile == self.profile {
+            return;
+        }
+        self.profile = profile;
+
+        if let Some(dev) = self.dev.as_mut() {
+            match configure(dev, profile, delay) {
+                Ok(_) => info!("BME680 switched to the {:?} profile", profile),
+                Err(e) => error!(
+                    "Failed to switch BME680 to the {:?} profile: {:?}",
+                    profile, e
+                ),
+            }
+        }
+    }
+
+    /// Probes for the sensor while it is absent and initializes it once it shows up.
+    pub fn poll(&mut self, delay: &mut Delay) {
+        if self.dev.is_some() || self.last_probe.elapsed() < PROBE_INTERVAL {
+            return;
+        }
+        self.last_probe = Instant::now();
+
+        if !is_present(&mut self.bus) {
+            return;
+        }
+
+        match init(self.bus.clone(), self.profile, delay) {
+            Ok(dev) => {
+                info!("BME680 attached");
+                self.dev = Some(dev);
+                self.change = Some(Presence::Attached);
+            }
+            Err(e) => error!("BME680 found but failed to initialize: {:?}", e),
+        }
+    }
+
+    /// Takes `count` readings back to back, stopping at the first failure.
+    pub fn read_burst(&mut self, delay: &mut Delay, count: u8) -> Result<Vec<FieldData>> {
+        (0..count.max(1)).map(|_| self.read(delay)).collect()
+    }
+
+    /// Takes the attach or detach that happened since the previous call.
+    pub fn take_change(&mut self) -> Option<Presence> {
+        self.change.take()
+    }
+
+    /// ADC counts of the last measurement, still in the result registers.
+    pub fn read_raw(&mut self) -> Result<RawValues> {
+        if self.dev.is_none() {
+            return Err(anyhow!("BME680 not attached"));
+        }
+        let mut field = [0u8; raw::FIELD_LEN];
+        self.bus
+            .write_read(ADDRESS.addr(), &[raw::FIELD_REGISTER], &mut field)
+            .map_err(|e| anyhow!("Failed to read BME680 result registers: {:?}", e))?;
+        Ok(RawValues::parse(&field))
+    }
+
+    /// Calibration the chip was programmed with at the factory.
+    pub fn calibration(&mut self) -> Result<Calibration> {
+        if self.dev.is_none() {
+            return Err(anyhow!("BME680 not attached"));
+        }
+        let mut blocks = Vec::new();
+        for (register, len) in raw::CALIBRATION_BLOCKS {
+            let mut data = vec![0u8; len];
+            self.bus
+                .write_read(ADDRESS.addr(), &[register], &mut data)
+                .map_err(|e| {
+                    anyhow!(
+                        "Failed to read BME680 calibration at {:#04x}: {:?}",
+                        register,
+                        e
+                    )
+                })?;
+            blocks.push((register, data));
+        }
+        Ok(Calibration::new(
+            blocks
+                .iter()
+                .map(|(register, data)| (*register, data.as_slice())),
+            TEMPERATURE_OFFSET_C,
+        ))
+    }
+
+    /// Reads the sensor. When a read fails and the sensor stops answering on
+    /// the bus it is considered unplugged.
+    pub fn read(&mut self, delay: &mut Delay) -> Result<FieldData> {
+        let dev = self
+            .dev
+            .as_mut()
+            .ok_or_else(|| anyhow!("BME680 not attached"))?;
+
+        let result = read(dev, delay);
+        if result.is_err() && !is_present(&mut self.bus) {
+            warn!("BME680 detached");
+            self.dev = None;
+            self.change = Some(Presence::Detached);
+            self.last_probe = Instant::now();
+        }
+        result
+    }
+}
+
+fn is_present(bus: &mut SharedI2c) -> bool {
+    let mut chip_id = [0u8];
+    bus.write_read(ADDRESS.addr(), &[CHIP_ID_REGISTER], &mut chip_id)
+        .is_ok()
+        && chip_id[0] == BME680_CHIP_ID
+}
+
+/// Initializes the BME680 and applies the measurement profile.
+fn init(i2c: SharedI2c, profile: SensorProfile, delay: &mut Delay) -> Result<Sensor> {
+    let mut dev = Bme680::init(i2c, delay, ADDRESS).map_err(|e| {
+        error!("Error at bme680 init {e:?}");
+        anyhow::anyhow!("BME680 initialization failed: {:?}", e)
+    })?;
+
+    configure(&mut dev, profile, delay)?;
+    Ok(dev)
+}
+
+fn configure(dev: &mut Sensor, profile: SensorProfile, delay: &mut Delay) -> Result<()> {
+    let builder = SettingsBuilder::new()
+        .with_humidity_oversampling(OversamplingSetting::OS2x)
+        .with_pressure_oversampling(OversamplingSetting::OS4x)
+        .with_temperature_oversampling(OversamplingSetting::OS8x)
+        .with_temperature_filter(IIRFilterSize::Size3)
+        .with_temperature_offset(TEMPERATURE_OFFSET_C);
+    let settings = match profile {
+        SensorProfile::Standard => builder
+            .with_gas_measurement(Duration::from_millis(1500), 320, 25)
+            .with_humidity_control(0)
+            .with_run_gas(true),
+        // Heating the plate takes most of the energy of a measurement
+        SensorProfile::GasDisabled => builder
+            .with_humidity_control(HEATER_OFF)
+            .with_run_gas(false),
+    }
+    .build();
+
+    let profile_dur = dev
+        .get_profile_dur(&settings.0)
+        .map_err(|e| anyhow::anyhow!("Failed to get profile duration: {:?}", e))?;
+    info!("Profile duration {:?}", profile_dur);
+
+    dev.set_sensor_settings(delay, settings)
+        .map_err(|e| anyhow::anyhow!("Failed to apply sensor settings: {:?}", e))?;
+
+    dev.set_sensor_mode(delay, PowerMode::ForcedMode)
+        .map_err(|e| anyhow::anyhow!("Failed to set sensor mode: {:?}", e))?;
+
+    let sensor_settings = dev.get_sensor_settings(settings.1);
+    info!("Sensor settings: {:?}", sensor_settings);
+
+    Ok(())
+}
+
+/// Triggers a forced mode measurement and returns its result.
+fn read(dev: &mut Sensor, delay: &mut Delay) -> Result<FieldData> {
+    dev.set_sensor_mode(delay, PowerMode::ForcedMode)
+        .map_err(|e| {
+            error!("Unable to set sensor mode: {:?}", e);
+            anyhow::anyhow!("Failed to set sensor mode: {:?}", e)
+        })?;
+
+    let (data, _state) = dev.get_sensor_data(delay).map_err(|e| {
+        error!("Unable to get sensor data: {:?}", e);
+        anyhow::anyhow!("Failed to get sensor data: {:?}", e)
+    })?;
+
+    Ok(data)
+}
+
+/// Whether a reading lies within the operating range of the BME680.
+pub fn is_plausible(data: &FieldData) -> bool {
+    threshold::is_plausible(
+        data.temperature_celsius(),
+        data.humidity_percent(),
+        data.pressure_hpa(),
+    )
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use esp32_aws_core::{
+    outbox::Priority,
+    sitewise::{self, SiteWise},
+};
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use log::warn;
+
+use crate::{
+    publisher,
+    structs::{Config, SensorData},
+};
+
+/// Publishes a reading as SiteWise property values to `<pub_topic>/sitewise`,
+/// one message per request SiteWise accepts. Readings without a timestamp are
+/// left out, SiteWise rejects values without one.
+pub fn publish(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    reading: &SensorData,
+    settings: &SiteWise,
+) -> Result<()> {
+    let Some(batches) = sitewise::batches(reading, settings) else {
+        warn!("Clock not synced, not publishing the reading to SiteWise");
+        return Ok(());
+    };
+
+    let topic = config.topic("sitewise");
+    for batch in batches {
+        publisher::publish(
+            client,
+            Priority::Telemetry,
+            &topic,
+            QoS::AtLeastOnce,
+            false,
+            serde_json::to_string(&batch)?.as_bytes(),
+        )?;
+    }
+    Ok(())
+}
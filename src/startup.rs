@@ -0,0 +1,44 @@
+use esp32_aws_core::startup::{Jitter, Stage};
+use esp_idf_svc::sys::{self, esp, esp_efuse_mac_get_default, esp_reset_reason};
+use log::{info, warn};
+use serde_json::json;
+use std::thread;
+
+use crate::journal;
+
+/// Whether the device came back from losing power, the case in which a whole
+/// site reconnects at once.
+pub fn power_restored() -> bool {
+    matches!(
+        unsafe { esp_reset_reason() },
+        sys::esp_reset_reason_t_ESP_RST_POWERON | sys::esp_reset_reason_t_ESP_RST_BROWNOUT
+    )
+}
+
+/// Waits the delay of this device before `stage`, at most `max_delay_s`.
+pub fn wait(stage: Stage, max_delay_s: u32) {
+    if max_delay_s == 0 {
+        return;
+    }
+    let mut mac = [0u8; 6];
+    if let Err(e) = esp!(unsafe { esp_efuse_mac_get_default(mac.as_mut_ptr()) }) {
+        warn!(
+            "Failed to read MAC address, starting {} right away: {:?}",
+            stage.name(),
+            e
+        );
+        return;
+    }
+
+    let delay = Jitter::new(&mac).delay(stage, max_delay_s);
+    info!(
+        "Waiting {} ms before starting {}",
+        delay.as_millis(),
+        stage.name()
+    );
+    journal::record(
+        "startup_delay",
+        json!({ "stage": stage.name(), "delay_ms": delay.as_millis() as u64 }),
+    );
+    thread::sleep(delay);
+}
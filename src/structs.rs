@@ -1,15 +1,31 @@
-use std::{mem, slice};
+use std::{mem, slice, time::Instant};
 
+use anyhow::Result;
 use dotenvy_macro::dotenv;
+use esp32_aws_core::{
+    cloud::{self, Backend, Identity},
+    url::Url,
+};
 use esp_idf_svc::tls::X509;
 use serde::{Deserialize, Serialize};
 
+use crate::{credentials::Credentials, dtls::Psk};
+
+pub use esp32_aws_core::telemetry::SensorData;
+
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MqttMessage {
     pub message: String,
 }
 
+/// A message received from the broker, handed from the MQTT callback to the main loop.
+pub struct InboundMessage {
+    pub topic: String,
+    pub data: Vec<u8>,
+    pub received_at: Instant,
+}
+
 pub struct Config<'a> {
     pub ssid: String,
     pub password: String,
@@ -17,32 +33,64 @@ pub struct Config<'a> {
     pub server_cert: X509<'a>,
     pub client_cert: X509<'a>,
     pub private_key: X509<'a>,
+    pub client_cert_pem: &'static [u8],
+    pub signing_key: Option<&'static [u8]>,
+    /// Key for `coaps://` gateways
+    pub coap_psk: Option<Psk>,
+    /// Signs or checks LoRa frames, see `Credentials::lora_key`
+    #[cfg(feature = "lora")]
+    pub lora_key: Option<&'static [u8]>,
     pub mqtts_url: String,
-    pub sub_topic: String,
-    pub pub_topic: String,
+    pub backend: Box<dyn Backend>,
+    /// Keep the session on the broker, applies when the client is created
+    pub persistent_session: bool,
 }
 
 impl Config<'_> {
-    pub fn new() -> Self {
-        let server_cert_bytes: Vec<u8> = include_bytes!("../aws/AmazonRootCA1.pem").to_vec();
-        let client_cert_bytes: Vec<u8> = include_bytes!("../aws/device.crt").to_vec();
-        let private_key_bytes: Vec<u8> = include_bytes!("../aws/private.key").to_vec();
+    pub fn new(credentials: &Credentials, cloud: cloud::Kind) -> Result<Self> {
+        let server_cert_bytes: Vec<u8> = credentials.server_cert.to_vec();
+        let client_cert_pem: &'static [u8] = credentials.client_cert;
+        let client_cert_bytes: Vec<u8> = client_cert_pem.to_vec();
+        let private_key_bytes: Vec<u8> = credentials.private_key.to_vec();
 
         let server_cert = convert_certificate(server_cert_bytes);
         let client_cert = convert_certificate(client_cert_bytes);
         let private_key = convert_certificate(private_key_bytes);
 
-        Config {
+        let client_id: String = dotenv!("CLIENT_ID").into();
+        let mqtts_url: String = dotenv!("MQTTS_URL").into();
+        let identity = Identity {
+            client_id: &client_id,
+            host: Url::parse(&mqtts_url)?.host,
+            sub_topic: dotenv!("SUB_TOPIC"),
+            pub_topic: dotenv!("PUB_TOPIC"),
+        };
+        let backend = cloud::backend(cloud, &identity, credentials.sas_key);
+
+        Ok(Config {
             ssid: dotenv!("WIFI_SSID").into(),
             password: dotenv!("WIFI_PASSWORD").into(),
-            client_id: dotenv!("CLIENT_ID").into(),
+            client_id,
             server_cert,
             client_cert,
             private_key,
-            mqtts_url: dotenv!("MQTTS_URL").into(),
-            sub_topic: dotenv!("SUB_TOPIC").into(),
-            pub_topic: dotenv!("PUB_TOPIC").into(),
-        }
+            client_cert_pem,
+            signing_key: credentials.signing_key,
+            coap_psk: credentials
+                .coap_psk_identity
+                .zip(credentials.coap_psk)
+                .map(|(identity, key)| Psk { identity, key }),
+            #[cfg(feature = "lora")]
+            lora_key: credentials.lora_key,
+            mqtts_url,
+            backend,
+            persistent_session: false,
+        })
+    }
+
+    /// Topic of the `suffix` stream, e.g. `<pub_topic>/selftest` on AWS.
+    pub fn topic(&self, suffix: &str) -> String {
+        self.backend.topic(Some(suffix))
     }
 }
 
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use esp32_aws_core::{outbox::Priority, summary::DailyStats};
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, QoS},
+    sys::esp_timer_get_time,
+};
+use log::info;
+use std::sync::Mutex;
+
+use crate::{burst, publisher, structs::Config};
+
+static STATS: Mutex<DailyStats> = Mutex::new(DailyStats::new());
+
+/// Called with every reading taken.
+pub fn record_reading(summary: &burst::Summary) {
+    STATS.lock().unwrap().record_reading(
+        summary.temperature.mean,
+        summary.humidity.mean,
+        summary.pressure.mean,
+        summary.gas_resistance.map(|gas| gas.mean),
+    );
+}
+
+/// Called for every reading handed to the transport, or that failed to be.
+pub fn record_publish(ok: bool) {
+    STATS.lock().unwrap().record_publish(ok);
+}
+
+pub fn record_alert(kind: &str) {
+    STATS.lock().unwrap().record_alert(kind);
+}
+
+/// Publishes the summary of the day to `<pub_topic>/summary` and starts the next one.
+pub fn publish(client: &mut EspMqttClient<'static>, config: &Config, now: u64) -> Result<()> {
+    let uptime_s = unsafe { esp_timer_get_time() } as u64 / 1_000_000;
+    let summary = STATS.lock().unwrap().summary(now, uptime_s);
+
+    publisher::publish(
+        client,
+        Priority::Telemetry,
+        &config.topic("summary"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(&summary)?.as_bytes(),
+    )?;
+    info!("Published daily summary");
+
+    STATS.lock().unwrap().reset(now);
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use esp32_aws_core::settings::Settings;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::{info, warn};
+
+const NVS_NAMESPACE: &str = "twin";
+const SETTINGS_KEY: &str = "settings";
+const VERSION_KEY: &str = "version";
+
+/// The settings in effect and the version of the desired state they came from,
+/// kept in NVS so configuration survives reboots and connection outages.
+pub struct TwinCache {
+    nvs: EspNvs<NvsDefault>,
+    version: Option<u64>,
+}
+
+impl TwinCache {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+        let version = nvs.get_u64(VERSION_KEY)?;
+
+        Ok(TwinCache { nvs, version })
+    }
+
+    /// Version of the desired state the settings in effect came from.
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
+
+    /// The cached settings, or the defaults when nothing usable is cached.
+    pub fn load(&self) -> Settings {
+        match self.read() {
+            Ok(Some(settings)) => {
+                info!("Restored settings of version {:?}", self.version);
+                settings
+            }
+            Ok(None) => Settings::default(),
+            Err(e) => {
+                warn!("Ignoring cached settings: {:?}", e);
+                Settings::default()
+            }
+        }
+    }
+
+    fn read(&self) -> Result<Option<Settings>> {
+        let Some(len) = self.nvs.blob_len(SETTINGS_KEY)? else {
+            return Ok(None);
+        };
+        let mut buf = vec![0; len];
+        let Some(data) = self.nvs.get_blob(SETTINGS_KEY, &mut buf)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(data)?))
+    }
+
+    pub fn store(&mut self, settings: &Settings, version: Option<u64>) -> Result<()> {
+        self.nvs
+            .set_blob(SETTINGS_KEY, &serde_json::to_vec(settings)?)?;
+        match version {
+            Some(version) => self.nvs.set_u64(VERSION_KEY, version)?,
+            None => {
+                self.nvs.remove(VERSION_KEY)?;
+            }
+        }
+        self.version = version;
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{bail, Result};
+use esp32_aws_core::{
+    outbox::Priority,
+    pulse::{PulseCounter, PulseReading},
+    settings::Settings,
+    weather::{Air, WeatherReport, WeatherStation},
+};
+use esp_idf_svc::{
+    hal::{
+        adc::{
+            attenuation::DB_11,
+            oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+            ADC1,
+        },
+        gpio::{Gpio32, Gpio33, Gpio34, Gpio35, Gpio36, Gpio37, Gpio38, Gpio39},
+    },
+    mqtt::client::{EspMqttClient, QoS},
+};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+
+use crate::{publisher, structs::Config};
+
+// The vane divider runs off the 3.3 V rail
+const SUPPLY_MV: u16 = 3300;
+
+/// The wind vane, read in mV on an ADC1 channel.
+struct Vane {
+    pin: u8,
+    read: Box<dyn FnMut() -> Result<u16>>,
+}
+
+impl Vane {
+    fn open(pin: u8) -> Result<Self> {
+        // Up to about 3.1 V with calibration, the highest vane position stays below that
+        let config = AdcChannelConfig {
+            attenuation: DB_11,
+            calibration: true,
+            ..Default::default()
+        };
+        // Only pins `validate` accepted get here, ADC1 is not used elsewhere
+        macro_rules! channel {
+            ($gpio:ident) => {{
+                let adc = AdcDriver::new(unsafe { ADC1::new() })?;
+                let mut channel = AdcChannelDriver::new(adc, unsafe { $gpio::new() }, &config)?;
+                Box::new(move || Ok(channel.read()?))
+            }};
+        }
+        let read: Box<dyn FnMut() -> Result<u16>> = match pin {
+            32 => channel!(Gpio32),
+            33 => channel!(Gpio33),
+            34 => channel!(Gpio34),
+            35 => channel!(Gpio35),
+            36 => channel!(Gpio36),
+            37 => channel!(Gpio37),
+            38 => channel!(Gpio38),
+            39 => channel!(Gpio39),
+            _ => bail!("GPIO {} is not an ADC1 pin", pin),
+        };
+
+        Ok(Vane { pin, read })
+    }
+}
+
+/// The weather station profile apart from its pulse counters.
+#[derive(Default)]
+pub struct Station {
+    configured: Option<WeatherStation>,
+    vane: Option<Vane>,
+}
+
+impl Station {
+    /// Sets up the wind vane of a changed configuration.
+    pub fn configure(&mut self, station: &WeatherStation) {
+        if self.configured.as_ref() == Some(station) {
+            return;
+        }
+        self.configured = Some(station.clone());
+        // Releases ADC1 before it is opened again
+        self.vane = None;
+
+        if !(station.enabled && station.vane) {
+            return;
+        }
+        if let Err(e) = station.validate() {
+            error!("Wind vane not set up: {}", e);
+            return;
+        }
+        match Vane::open(station.vane_pin) {
+            Ok(vane) => {
+                info!("Reading the wind vane on GPIO {}", vane.pin);
+                self.vane = Some(vane);
+            }
+            Err(e) => error!(
+                "Failed to set up wind vane on GPIO {}: {:?}",
+                station.vane_pin, e
+            ),
+        }
+    }
+
+    /// The weather payload of an enabled station. Takes the readings of the rain
+    /// gauge and anemometer out of `pulses`.
+    pub fn report(
+        &mut self,
+        pulses: &mut BTreeMap<String, PulseReading>,
+        air: Option<Air>,
+        timestamp: Option<u64>,
+    ) -> Option<WeatherReport> {
+        let station = self.configured.as_ref().filter(|station| station.enabled)?;
+        let vane_mv = self.vane.as_mut().and_then(|vane| match (vane.read)() {
+            Ok(mv) => Some((mv, SUPPLY_MV)),
+            Err(e) => {
+                warn!("Failed to read wind vane: {:?}", e);
+                None
+            }
+        });
+
+        Some(station.report(pulses, air, vane_mv, timestamp))
+    }
+}
+
+/// The configured pulse counters and the ones of an enabled weather station.
+pub fn pulse_counters(settings: &Settings) -> Vec<PulseCounter> {
+    let mut counters = settings.pulse_counters.clone();
+    if settings.weather_station.enabled {
+        counters.extend(settings.weather_station.counters());
+    }
+    counters
+}
+
+pub fn publish(
+    client: &mut EspMqttClient<'static>,
+    config: &Config,
+    report: &WeatherReport,
+) -> Result<()> {
+    publisher::publish(
+        client,
+        Priority::Telemetry,
+        &config.topic("weather"),
+        QoS::AtLeastOnce,
+        false,
+        serde_json::to_string(report)?.as_bytes(),
+    )?;
+    Ok(())
+}
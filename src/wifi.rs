@@ -2,23 +2,24 @@ use anyhow::{bail, Result};
 use esp_idf_svc::{
     hal::{delay::FreeRtos, peripheral},
     eventloop::EspSystemEventLoop,
-    mqtt::client::{EspMqttClient, QoS},
+    mqtt::client::EspMqttClient,
     nvs::EspDefaultNvsPartition,
     wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi},
     sys::EspError,
 };
 use log::info;
+use serde_json::json;
+use std::time::Instant;
 
-use crate::structs::Config;
+use crate::{journal, mqtt, structs::Config};
 
 pub fn wifi(
     ssid: &str,
     pass: &str,
     modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
 ) -> Result<Box<EspWifi<'static>>> {
-    let nvs = EspDefaultNvsPartition::take()?;
-
     let mut auth_method = AuthMethod::WPA2Personal;
     if ssid.is_empty() {
         bail!("Missing WiFi name")
@@ -86,8 +87,9 @@ pub fn try_reconnect_wifi(
     config: &Config,
 ) -> Result<(), EspError> {
     info!("Wifi disconnected");
+    let disconnected_at = Instant::now();
 
-    while !wifi.is_connected().unwrap() {
+    while !wifi.is_connected().unwrap_or(false) {
         info!("Reconnecting...");
         if wifi.as_mut().connect().is_err() {
             info!("No access point found, Sleeping for 10sec",);
@@ -95,9 +97,11 @@ pub fn try_reconnect_wifi(
         }
     }
 
+    journal::record("wifi_reconnected", json!({ "outage_s": disconnected_at.elapsed().as_secs() }));
+
     // Sleep to let mqtt client reconnect
     FreeRtos::delay_ms(10000);
     info!("Resubscribing to topic...");
-    mqtt_client.subscribe(&config.sub_topic, QoS::AtLeastOnce)?;
+    mqtt::subscribe(mqtt_client, config)?;
     Ok(())
 }
\ No newline at end of file